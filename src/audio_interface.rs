@@ -1,16 +1,30 @@
 use std::collections::VecDeque;
 use std::ops::DerefMut;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
 
+// How much audio to keep queued ahead of the device
+const TARGET_LATENCY: f64 = 0.05;
+
+// Dynamic rate control stretches the audio by at most this much to keep the
+// queue at the target, too little to hear as a change in pitch
+const MAX_RATE_DELTA: f64 = 0.005;
+
+// Longest the emulator waits on a device that has stopped pulling samples
+const MAX_WAIT: Duration = Duration::from_millis(100);
+
 struct AudioBuffer {
     data: VecDeque<i16>,
+    capacity: usize,
 }
 
 impl AudioBuffer {
-    pub fn new() -> AudioBuffer {
+    pub fn new(capacity: usize) -> AudioBuffer {
         AudioBuffer {
             data: VecDeque::new(),
+            capacity,
         }
     }
 
@@ -19,7 +33,7 @@ impl AudioBuffer {
             self.data.push_back(*sample);
         }
 
-        while self.data.len() > 512 * 16 {
+        while self.data.len() > self.capacity {
             self.data.pop_front().unwrap();
         }
     }
@@ -37,20 +51,25 @@ impl AudioCallback for AudioBuffer {
             (0, 0)
         };
 
-        for i in 0..out.len() {
-            if let Some(s) = self.data.pop_front() {
-                out[i] = s;
-            } else if (i % 2) == 0 {
-                out[i] = last_l;
-            } else {
-                out[i] = last_r;
-            }
+        for (i, sample) in out.iter_mut().enumerate() {
+            *sample = match self.data.pop_front() {
+                Some(s) => s,
+                None if (i % 2) == 0 => last_l,
+                None => last_r,
+            };
         }
     }
 }
 
 pub struct AudioInterface {
     device: AudioDevice<AudioBuffer>,
+
+    // Queue length in samples, both channels counted
+    target: usize,
+
+    // Resampler position between the last input frame and the next
+    position: f64,
+    last: (i16, i16),
 }
 
 impl AudioInterface {
@@ -63,18 +82,66 @@ impl AudioInterface {
             samples: Some(samples),
         };
 
+        let target = (freq as f64 * TARGET_LATENCY) as usize * channels as usize;
+
         let device = audio_subsystem
-            .open_playback(None, &desired_spec, |_| AudioBuffer::new())
+            .open_playback(None, &desired_spec, |_| AudioBuffer::new(target * 4))
             .unwrap();
 
-        AudioInterface { device: device }
+        AudioInterface {
+            device,
+
+            target,
+
+            position: 0.0,
+            last: (0, 0),
+        }
     }
 
     pub fn play(&mut self) {
         self.device.resume();
     }
 
-    pub fn push_samples(&mut self, samples: Vec<i16>) {
-        self.device.lock().deref_mut().push_samples(samples);
+    fn queued(&mut self) -> usize {
+        self.device.lock().data.len()
     }
+
+    // Resamples to play back at the given speed, nudging the rate so the
+    // queue drifts back to the target instead of running dry or overflowing
+    pub fn push_samples(&mut self, samples: Vec<i16>, speed: f64) {
+        let fill = self.queued() as f64 / self.target as f64;
+        let step = speed * (1.0 + MAX_RATE_DELTA * (fill - 1.0).clamp(-1.0, 1.0));
+
+        let mut output = Vec::with_capacity((samples.len() as f64 / step) as usize + 2);
+
+        for frame in samples.chunks_exact(2) {
+            let (l, r) = (frame[0], frame[1]);
+
+            while self.position < 1.0 {
+                output.push(lerp(self.last.0, l, self.position));
+                output.push(lerp(self.last.1, r, self.position));
+
+                self.position += step;
+            }
+
+            self.position -= 1.0;
+            self.last = (l, r);
+        }
+
+        self.device.lock().deref_mut().push_samples(output);
+    }
+
+    // Blocks until the queue is back down to the target, which paces the
+    // emulator to the audio device's clock
+    pub fn wait(&mut self) {
+        let start = Instant::now();
+
+        while self.queued() > self.target && start.elapsed() < MAX_WAIT {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+fn lerp(a: i16, b: i16, t: f64) -> i16 {
+    (a as f64 + (b as f64 - a as f64) * t) as i16
 }
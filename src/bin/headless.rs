@@ -0,0 +1,161 @@
+#[macro_use]
+extern crate clap;
+
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use clap::{App, ArgMatches};
+
+use rpsx::{BiosTracer, Dithering, IoDevice, System, TraceTrigger, Tracer};
+use rpsx::capture::{self, WavWriter};
+
+fn parse_number(value: &str, name: &str) -> Result<usize, String> {
+    value.parse().map_err(|_| format!("invalid {}: {}", name, value))
+}
+
+fn parse_trigger(value: Option<&str>) -> Result<Option<TraceTrigger>, String> {
+    value.map(|v| v.parse()).transpose()
+}
+
+fn dump_frame(system: &System,
+              framebuffer: &mut [u8],
+              directory: &Path,
+              format: &str,
+              full_vram: bool,
+              frame: usize) -> Result<(), String> {
+    let (width, height) = match full_vram {
+        true => (1024, 512),
+        false => system.get_display_size(),
+    };
+
+    system.get_framebuffer(framebuffer, full_vram);
+
+    let path = directory.join(format!("frame_{:05}.{}", frame, format));
+
+    let result = match format {
+        "ppm" => capture::save_ppm(&path, width, height, framebuffer),
+        _ => capture::save_png(&path, width, height, framebuffer),
+    };
+
+    result.map_err(|e| format!("unable to write {}: {}", path.display(), e))
+}
+
+fn run(matches: &ArgMatches) -> Result<(), String> {
+    let bios_filepath = matches.value_of("BIOS").unwrap();
+    let game_filepath = matches.value_of("GAME");
+
+    let frames = parse_number(matches.value_of("frames").unwrap(), "frame count")?;
+
+    let mut screenshots = Vec::new();
+
+    if let Some(values) = matches.values_of("screenshot") {
+        for value in values {
+            screenshots.push(parse_number(value, "screenshot frame")?);
+        }
+    }
+
+    let directory = Path::new(matches.value_of("output").unwrap());
+    let format = matches.value_of("format").unwrap();
+    let full_vram = matches.is_present("full-vram");
+
+    if !screenshots.is_empty() {
+        fs::create_dir_all(directory)
+            .map_err(|e| format!("unable to create {}: {}", directory.display(), e))?;
+    }
+
+    let mut wav = match matches.value_of("wav") {
+        Some(filepath) => Some(WavWriter::create(Path::new(filepath), 44100, 2)
+            .map_err(|e| format!("unable to create {}: {}", filepath, e))?),
+        None => None,
+    };
+
+    let mut system = System::new(bios_filepath.to_string(), game_filepath.map(|s| s.to_string()))
+        .map_err(|e| format!("unable to start emulator: {}", e))?;
+
+    if let Some(directory) = matches.value_of("cards") {
+        system.open_memory_cards(Path::new(directory));
+    }
+
+    system.reset();
+
+    let dithering: Dithering = matches.value_of("dither").unwrap().parse()?;
+    system.set_dithering(dithering);
+
+    if let Some(filepath) = matches.value_of("trace") {
+        let start = parse_trigger(matches.value_of("trace-start"))?;
+        let stop = parse_trigger(matches.value_of("trace-stop"))?;
+        let ring = match matches.value_of("trace-ring") {
+            Some(value) => Some(parse_number(value, "trace ring size")?),
+            None => None,
+        };
+
+        let tracer = Tracer::create(Path::new(filepath), start, stop, ring)
+            .map_err(|e| format!("unable to create {}: {}", filepath, e))?;
+        system.set_tracer(Some(tracer));
+    }
+
+    if let Some(filepath) = matches.value_of("bios-trace") {
+        let bios_tracer = BiosTracer::create(Path::new(filepath), matches.is_present("bios-trace-returns"))
+            .map_err(|e| format!("unable to create {}: {}", filepath, e))?;
+        system.set_bios_tracer(Some(bios_tracer));
+    }
+
+    if let Some(filepath) = matches.value_of("io-log") {
+        let devices = match matches.value_of("io-log-devices") {
+            Some(list) => list.split(',').map(|d| d.parse()).collect::<Result<Vec<IoDevice>, String>>()?,
+            None => IoDevice::ALL.to_vec(),
+        };
+
+        system.start_io_log(Path::new(filepath), &devices)
+            .map_err(|e| format!("unable to create {}: {}", filepath, e))?;
+    }
+
+    if let Some(exe) = matches.value_of("exe") {
+        let args: Vec<String> = matches.values_of("exe-arg")
+            .map(|values| values.map(|v| v.to_string()).collect())
+            .unwrap_or_default();
+
+        system.boot_psexe(exe, &args)
+            .map_err(|e| format!("unable to load {}: {}", exe, e))?;
+    } else if matches.is_present("fast-boot") {
+        system.fast_boot().map_err(|e| format!("unable to fast boot: {}", e))?;
+    }
+
+    let mut framebuffer = vec![0; 1024 * 512 * 3];
+
+    for frame in 1..=frames {
+        system.run_frame();
+
+        let samples = system.get_audio_samples();
+
+        if let Some(wav) = wav.as_mut() {
+            wav.write_samples(&samples)
+                .map_err(|e| format!("unable to write audio: {}", e))?;
+        }
+
+        if screenshots.contains(&frame) {
+            dump_frame(&system, &mut framebuffer, directory, format, full_vram, frame)?;
+        }
+    }
+
+    if let Some(wav) = wav {
+        wav.finish().map_err(|e| format!("unable to write audio: {}", e))?;
+    }
+
+    for frame in screenshots.iter().filter(|&&frame| frame == 0 || frame > frames) {
+        println!("[HEADLESS] [WARN] Frame {} was never reached", frame);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let yaml = load_yaml!("../../headless.yaml");
+    let matches = App::from_yaml(yaml).get_matches();
+
+    if let Err(e) = run(&matches) {
+        eprintln!("[HEADLESS] [ERROR] {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,131 @@
+#[macro_use]
+extern crate clap;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::{App, ArgMatches};
+
+use rpsx::{DirectoryEntry, Iso9660};
+
+fn open(matches: &ArgMatches) -> Result<(Iso9660, DirectoryEntry), String> {
+    let image = matches.value_of("IMAGE").unwrap();
+
+    let mut iso = Iso9660::open(Path::new(image))
+        .map_err(|e| format!("unable to open {}: {}", image, e))?;
+
+    let entry = match matches.value_of("PATH") {
+        Some(path) => iso.find(path)?,
+        None => iso.volume().root.clone(),
+    };
+
+    Ok((iso, entry))
+}
+
+fn attributes(entry: &DirectoryEntry) -> String {
+    let mut attributes = String::new();
+
+    attributes.push(if entry.directory { 'd' } else { '-' });
+
+    match entry.xa {
+        Some(xa) => {
+            attributes.push(if xa.form1() { '1' } else { '-' });
+            attributes.push(if xa.form2() { '2' } else { '-' });
+            attributes.push(if xa.interleaved() { 'i' } else { '-' });
+            attributes.push(if xa.cdda() { 'a' } else { '-' });
+        },
+        None => attributes.push_str("----"),
+    };
+
+    attributes
+}
+
+fn ls(matches: &ArgMatches) -> Result<(), String> {
+    let (mut iso, directory) = open(matches)?;
+
+    let entries = match matches.is_present("recursive") {
+        true => iso.walk(&directory)?,
+        false => iso.list(&directory)?.into_iter().map(|e| (e.name.clone(), e)).collect(),
+    };
+
+    let volume = iso.volume();
+    println!("{} ({}), {} sectors", volume.volume_id, volume.system_id, volume.sectors);
+
+    for (path, entry) in entries.iter() {
+        println!("{} {:>8} {:>10} {}", attributes(entry), entry.lba, entry.size, path.trim_start_matches('\\'));
+    }
+
+    Ok(())
+}
+
+// Disc paths become host paths without the version suffix, and anything
+// that could escape the output directory is refused
+fn host_path(output: &Path, path: &str) -> Result<PathBuf, String> {
+    let mut host = output.to_path_buf();
+
+    for component in path.split('\\').filter(|c| !c.is_empty()) {
+        let name = component.split(';').next().unwrap_or("");
+
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', ':']) {
+            return Err(format!("refusing to extract {}", path));
+        }
+
+        host.push(name);
+    }
+
+    Ok(host)
+}
+
+fn extract_file(iso: &mut Iso9660, entry: &DirectoryEntry, path: &Path) -> Result<(), String> {
+    let data = iso.read_file(entry)
+        .map_err(|e| format!("unable to read {}: {}", entry.name, e))?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("unable to create {}: {}", parent.display(), e))?;
+    }
+
+    fs::write(path, data).map_err(|e| format!("unable to write {}: {}", path.display(), e))?;
+
+    println!("{}", path.display());
+    Ok(())
+}
+
+fn extract(matches: &ArgMatches) -> Result<(), String> {
+    let (mut iso, entry) = open(matches)?;
+    let output = Path::new(matches.value_of("output").unwrap());
+
+    if !entry.directory {
+        return extract_file(&mut iso, &entry, &host_path(output, &entry.name)?);
+    }
+
+    for (path, entry) in iso.walk(&entry)? {
+        let path = host_path(output, &path)?;
+
+        if entry.directory {
+            fs::create_dir_all(&path)
+                .map_err(|e| format!("unable to create {}: {}", path.display(), e))?;
+        } else {
+            extract_file(&mut iso, &entry, &path)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let yaml = load_yaml!("../../iso.yaml");
+    let matches = App::from_yaml(yaml).get_matches();
+
+    let result = match matches.subcommand() {
+        ("ls", Some(matches)) => ls(matches),
+        ("extract", Some(matches)) => extract(matches),
+        _ => unreachable!(),
+    };
+
+    if let Err(e) = result {
+        eprintln!("[ISO] [ERROR] {}", e);
+        process::exit(1);
+    }
+}
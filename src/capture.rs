@@ -0,0 +1,84 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+pub fn save_ppm(path: &Path, width: u32, height: u32, data: &[u8]) -> io::Result<()> {
+    let size = (width * height * 3) as usize;
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    write!(writer, "P6\n{} {}\n255\n", width, height)?;
+    writer.write_all(&data[..size])?;
+    writer.flush()
+}
+
+pub fn save_png(path: &Path, width: u32, height: u32, data: &[u8]) -> io::Result<()> {
+    let size = (width * height * 3) as usize;
+    let writer = BufWriter::new(File::create(path)?);
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data[..size])?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+pub struct WavWriter {
+    writer: BufWriter<File>,
+    data_size: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32, channels: u16) -> io::Result<WavWriter> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        let block_align = channels * 2;
+        let byte_rate = sample_rate * block_align as u32;
+
+        // Chunk sizes are patched in once all samples are known
+        writer.write_all(b"RIFF")?;
+        writer.write_u32::<LittleEndian>(0)?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_u32::<LittleEndian>(16)?;
+        writer.write_u16::<LittleEndian>(1)?;
+        writer.write_u16::<LittleEndian>(channels)?;
+        writer.write_u32::<LittleEndian>(sample_rate)?;
+        writer.write_u32::<LittleEndian>(byte_rate)?;
+        writer.write_u16::<LittleEndian>(block_align)?;
+        writer.write_u16::<LittleEndian>(16)?;
+
+        writer.write_all(b"data")?;
+        writer.write_u32::<LittleEndian>(0)?;
+
+        Ok(WavWriter {
+            writer,
+            data_size: 0,
+        })
+    }
+
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        for sample in samples.iter() {
+            self.writer.write_i16::<LittleEndian>(*sample)?;
+        }
+
+        self.data_size += (samples.len() * 2) as u32;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_u32::<LittleEndian>(36 + self.data_size)?;
+
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_u32::<LittleEndian>(self.data_size)?;
+
+        self.writer.flush()
+    }
+}
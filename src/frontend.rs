@@ -1,5 +1,4 @@
-use std::fs::{self, File};
-use std::io::{Read, Write};
+use std::fs;
 use std::path::Path;
 use std::time::Instant;
 
@@ -7,12 +6,11 @@ use sdl2::controller::{Axis, Button};
 use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
 
-use xz2::read::XzDecoder;
-use xz2::write::XzEncoder;
+use rpsx::System;
+use rpsx::util;
 
-use crate::{Options, Scaling};
-use crate::psx::System;
-use crate::util;
+use crate::gui::Gui;
+use crate::{Options, Scaling, SPEEDS};
 
 fn shader_from_source(source: &std::ffi::CStr, kind: gl::types::GLuint) -> Result<gl::types::GLuint, ()> {
     let shader;
@@ -43,6 +41,8 @@ pub struct Frontend {
     imgui_sdl2: imgui_sdl2::ImguiSdl2,
     imgui_renderer: imgui_opengl_renderer::Renderer,
 
+    gui: Gui,
+
     last_frame: Instant,
 
     framebuffer: Box<[u8]>,
@@ -101,6 +101,7 @@ impl Frontend {
         }
 
         let mut imgui = imgui::Context::create();
+        let gui = Gui::new(&mut imgui);
         let imgui_sdl2 = imgui_sdl2::ImguiSdl2::new(&mut imgui, &window);
         let imgui_renderer = imgui_opengl_renderer::Renderer::new(&mut imgui, |s| video.gl_get_proc_address(s) as _);
 
@@ -113,21 +114,23 @@ impl Frontend {
         }
 
         Self {
-            window: window,
+            window,
             _gl_context: gl_context,
 
             event_pump: ctx_temp.event_pump().unwrap(),
 
             _controller: controller,
 
-            vao: vao,
-            vbo: vbo,
-            program: program,
-            texture: texture,
+            vao,
+            vbo,
+            program,
+            texture,
+
+            imgui,
+            imgui_sdl2,
+            imgui_renderer,
 
-            imgui: imgui,
-            imgui_sdl2: imgui_sdl2,
-            imgui_renderer: imgui_renderer,
+            gui,
 
             last_frame: Instant::now(),
 
@@ -151,13 +154,8 @@ impl Frontend {
                 Event::ControllerButtonUp { button, .. } => Frontend::handle_controller_button(button, false, system),
                 Event::ControllerAxisMotion { axis, value, .. } => Frontend::handle_controller_axis(axis, value, system),
 
-                Event::Window { win_event, .. } => {
-                    match win_event {
-                        WindowEvent::Resized(width, height) => {
-                            unsafe { gl::Viewport(0, 0, width, height); }
-                        },
-                        _ => {},
-                    };
+                Event::Window { win_event: WindowEvent::Resized(width, height), .. } => {
+                    unsafe { gl::Viewport(0, 0, width, height); }
                 },
                 Event::Quit { .. } => system.running = false,
                 _ => {},
@@ -237,7 +235,9 @@ impl Frontend {
         let controller = system.get_controller();
 
         match keycode {
-            Keycode::Tab => options.frame_limit ^= true,
+            Keycode::Tab => options.fast_forward ^= true,
+            Keycode::Minus => options.speed_index = options.speed_index.saturating_sub(1),
+            Keycode::Equals => options.speed_index = (options.speed_index + 1).min(SPEEDS.len() - 1),
             Keycode::F2 => system.reset(),
             Keycode::F3 => options.step = true,
             Keycode::F4 => {
@@ -247,6 +247,7 @@ impl Frontend {
                     Scaling::Fullscreen => Scaling::None
                 };
             }
+            Keycode::F5 => Frontend::change_disc(system),
             Keycode::F6 => Frontend::load_state(system, options.state_index),
             Keycode::F7 => Frontend::save_state(system, options.state_index),
             Keycode::Comma => {
@@ -276,10 +277,27 @@ impl Frontend {
         };
     }
 
-    fn load_state(system: &mut System, index: usize) {
+    pub fn change_disc(system: &mut System) {
+        let count = system.disc_count();
+
+        if count == 0 {
+            println!("No playlist to change discs from");
+            return;
+        }
+
+        let index = (system.disc_index() + 1) % count;
+        println!("Changing to disc {} of {}...", index + 1, count);
+
+        match system.change_disc(index) {
+            Ok(()) => println!("DONE!"),
+            Err(e) => println!("Unable to change disc: {}", e),
+        }
+    }
+
+    pub fn load_state(system: &mut System, index: usize) {
         println!("Loading state {}...", index);
 
-        let id = system.get_disc_id_raw();
+        let id = system.get_disc_id();
         let name = format!("./states/{id}_slot{index}.state");
         let path = Path::new(&name);
 
@@ -288,23 +306,16 @@ impl Frontend {
             return;
         }
 
-        if let Ok(file) = File::open(path) {
-            let mut bytes = Vec::new();
-            let mut decompressor = XzDecoder::new(file);
-            decompressor.read_to_end(&mut bytes).unwrap();
-            *system = rmp_serde::from_slice(&bytes).unwrap();
-            system.reload_host_files();
-            system.get_controller().reset_switch_state();
-            println!("DONE!");
-        } else {
-            println!("unable to create save state file");
+        match fs::read(path).and_then(|bytes| system.load_state(&bytes)) {
+            Ok(()) => println!("DONE!"),
+            Err(e) => println!("Unable to load save state file: {}", e),
         }
     }
 
-    fn save_state(system: &mut System, index: usize) {
+    pub fn save_state(system: &mut System, index: usize) {
         println!("Saving state {}...", index);
 
-        let id = system.get_disc_id_raw();
+        let id = system.get_disc_id();
         let name = format!("./states/{id}_slot{index}.state");
         let path = Path::new(&name);
 
@@ -312,18 +323,13 @@ impl Frontend {
             fs::create_dir_all(parent).expect("unable to create path to save state file");
         }
 
-        if let Ok(file) = File::create(path) {
-            let bytes = rmp_serde::to_vec(system).expect("unable to serialize state");
-            let mut compressor = XzEncoder::new(file, 6);
-            compressor.write_all(&bytes).unwrap();
-            compressor.finish().unwrap();
-            println!("DONE!");
-        } else {
-            println!("Unable to create save state file");
+        match system.save_state().and_then(|bytes| fs::write(path, bytes)) {
+            Ok(()) => println!("DONE!"),
+            Err(e) => println!("Unable to create save state file: {}", e),
         }
     }
 
-    pub fn render(&mut self, options: &Options, system: &System) {
+    pub fn render(&mut self, options: &mut Options, system: &mut System) {
         let (width, height) = match options.draw_full_vram {
             true => (1024, 512),
             false => system.get_display_size(),
@@ -339,7 +345,7 @@ impl Frontend {
         if !options.draw_full_vram {
             let (scale_x, scale_y) = match options.scaling {
                 Scaling::None => self.calculate_scale_none(),
-                Scaling::Aspect => self.calculate_scale_aspect(options.crop_overscan),
+                Scaling::Aspect => self.calculate_scale_aspect(options.crop_overscan, height, system.get_standard_height()),
                 Scaling::Fullscreen => {
                     let mut scale = (1.0, 1.0);
 
@@ -382,8 +388,10 @@ impl Frontend {
 
         self.imgui.io_mut().delta_time = delta.as_secs() as f32 + delta.subsec_nanos() as f32 / 1_000_000_000.0;
 
+        let window_size = self.window.size();
+
         let ui = self.imgui.frame();
-        //ui.show_demo_window(&mut true);
+        self.gui.draw(&ui, options, system, window_size);
 
         unsafe {
             gl::UseProgram(self.program);
@@ -420,7 +428,9 @@ impl Frontend {
         (util::clip(x, 0.0, 1.0), util::clip(y, 0.0, 1.0))
     }
 
-    fn calculate_scale_aspect(&self, crop_overscan: bool) -> (f32, f32) {
+    // The picture is always 4:3, a display area with fewer lines than the
+    // video standard (256 of PAL's 288) leaves borders above and below
+    fn calculate_scale_aspect(&self, crop_overscan: bool, height: u32, standard_height: u32) -> (f32, f32) {
         let (x, y) = self.get_screen_ratio();
 
         let scale = if crop_overscan {
@@ -429,7 +439,9 @@ impl Frontend {
             1.0
         };
 
-        (x / x.max(y / scale), y * scale / x.max(y))
+        let lines = util::clip(height as f32 / standard_height as f32, 0.0, 1.0);
+
+        (x / x.max(y / scale), y * scale * lines / x.max(y))
     }
 }
 
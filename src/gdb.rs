@@ -0,0 +1,538 @@
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+use crate::{StopReason, System, WatchKind};
+
+const REGISTER_COUNT: usize = 73;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>mips:3000</architecture>
+  <feature name="org.gnu.gdb.mips.cpu">
+    <reg name="r0" bitsize="32" regnum="0"/>
+    <reg name="r1" bitsize="32"/>
+    <reg name="r2" bitsize="32"/>
+    <reg name="r3" bitsize="32"/>
+    <reg name="r4" bitsize="32"/>
+    <reg name="r5" bitsize="32"/>
+    <reg name="r6" bitsize="32"/>
+    <reg name="r7" bitsize="32"/>
+    <reg name="r8" bitsize="32"/>
+    <reg name="r9" bitsize="32"/>
+    <reg name="r10" bitsize="32"/>
+    <reg name="r11" bitsize="32"/>
+    <reg name="r12" bitsize="32"/>
+    <reg name="r13" bitsize="32"/>
+    <reg name="r14" bitsize="32"/>
+    <reg name="r15" bitsize="32"/>
+    <reg name="r16" bitsize="32"/>
+    <reg name="r17" bitsize="32"/>
+    <reg name="r18" bitsize="32"/>
+    <reg name="r19" bitsize="32"/>
+    <reg name="r20" bitsize="32"/>
+    <reg name="r21" bitsize="32"/>
+    <reg name="r22" bitsize="32"/>
+    <reg name="r23" bitsize="32"/>
+    <reg name="r24" bitsize="32"/>
+    <reg name="r25" bitsize="32"/>
+    <reg name="r26" bitsize="32"/>
+    <reg name="r27" bitsize="32"/>
+    <reg name="r28" bitsize="32"/>
+    <reg name="r29" bitsize="32"/>
+    <reg name="r30" bitsize="32"/>
+    <reg name="r31" bitsize="32"/>
+    <reg name="lo" bitsize="32" regnum="33"/>
+    <reg name="hi" bitsize="32" regnum="34"/>
+    <reg name="pc" bitsize="32" regnum="37"/>
+  </feature>
+  <feature name="org.gnu.gdb.mips.cp0">
+    <reg name="status" bitsize="32" regnum="32"/>
+    <reg name="badvaddr" bitsize="32" regnum="35"/>
+    <reg name="cause" bitsize="32" regnum="36"/>
+    <reg name="epc" bitsize="32" regnum="72"/>
+  </feature>
+  <feature name="org.gnu.gdb.mips.fpu">
+    <reg name="f0" bitsize="32" type="ieee_single" regnum="38"/>
+    <reg name="f1" bitsize="32" type="ieee_single"/>
+    <reg name="f2" bitsize="32" type="ieee_single"/>
+    <reg name="f3" bitsize="32" type="ieee_single"/>
+    <reg name="f4" bitsize="32" type="ieee_single"/>
+    <reg name="f5" bitsize="32" type="ieee_single"/>
+    <reg name="f6" bitsize="32" type="ieee_single"/>
+    <reg name="f7" bitsize="32" type="ieee_single"/>
+    <reg name="f8" bitsize="32" type="ieee_single"/>
+    <reg name="f9" bitsize="32" type="ieee_single"/>
+    <reg name="f10" bitsize="32" type="ieee_single"/>
+    <reg name="f11" bitsize="32" type="ieee_single"/>
+    <reg name="f12" bitsize="32" type="ieee_single"/>
+    <reg name="f13" bitsize="32" type="ieee_single"/>
+    <reg name="f14" bitsize="32" type="ieee_single"/>
+    <reg name="f15" bitsize="32" type="ieee_single"/>
+    <reg name="f16" bitsize="32" type="ieee_single"/>
+    <reg name="f17" bitsize="32" type="ieee_single"/>
+    <reg name="f18" bitsize="32" type="ieee_single"/>
+    <reg name="f19" bitsize="32" type="ieee_single"/>
+    <reg name="f20" bitsize="32" type="ieee_single"/>
+    <reg name="f21" bitsize="32" type="ieee_single"/>
+    <reg name="f22" bitsize="32" type="ieee_single"/>
+    <reg name="f23" bitsize="32" type="ieee_single"/>
+    <reg name="f24" bitsize="32" type="ieee_single"/>
+    <reg name="f25" bitsize="32" type="ieee_single"/>
+    <reg name="f26" bitsize="32" type="ieee_single"/>
+    <reg name="f27" bitsize="32" type="ieee_single"/>
+    <reg name="f28" bitsize="32" type="ieee_single"/>
+    <reg name="f29" bitsize="32" type="ieee_single"/>
+    <reg name="f30" bitsize="32" type="ieee_single"/>
+    <reg name="f31" bitsize="32" type="ieee_single"/>
+    <reg name="fcsr" bitsize="32" group="float"/>
+    <reg name="fir" bitsize="32" group="float"/>
+  </feature>
+</target>
+"#;
+
+fn parse_hex(value: &str) -> Option<u32> {
+    u32::from_str_radix(value, 16).ok()
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..value.len()).step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Breakpoint | StopReason::Step => "S05".to_string(),
+        StopReason::Watchpoint(kind, address) => {
+            let name = match kind {
+                WatchKind::Write => "watch",
+                WatchKind::Read => "rwatch",
+                WatchKind::Access => "awatch",
+            };
+
+            format!("T05{}:{:08x};", name, address)
+        },
+    }
+}
+
+// GDB remote serial protocol stub, serving one client at a time. The
+// emulator is halted while a client is attached until it asks to continue.
+pub struct GdbServer {
+    listener: TcpListener,
+    stream: Option<TcpStream>,
+
+    buffer: Vec<u8>,
+
+    halted: bool,
+    last_stop: StopReason,
+}
+
+impl GdbServer {
+    pub fn bind(port: u16) -> io::Result<GdbServer> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(GdbServer {
+            listener,
+            stream: None,
+
+            buffer: Vec::new(),
+
+            halted: false,
+            last_stop: StopReason::Breakpoint,
+        })
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn update(&mut self, system: &mut System) {
+        if self.stream.is_none() {
+            self.accept(system);
+        }
+
+        if self.stream.is_some() {
+            self.receive(system);
+        }
+    }
+
+    // Reports a breakpoint or watchpoint hit by the last run_frame
+    pub fn check_stop(&mut self, system: &mut System) {
+        if let Some(reason) = system.take_stop_reason() {
+            if self.stream.is_some() {
+                self.halt(reason);
+            }
+        }
+    }
+
+    fn accept(&mut self, system: &mut System) {
+        let stream = match self.listener.accept() {
+            Ok((stream, address)) => {
+                println!("[GDB] [INFO] Connection from {}", address);
+                stream
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+            Err(e) => {
+                println!("[GDB] [ERROR] Unable to accept connection: {}", e);
+                return;
+            },
+        };
+
+        if let Err(e) = stream.set_nonblocking(true) {
+            println!("[GDB] [ERROR] Unable to configure connection: {}", e);
+            return;
+        }
+
+        let _ = stream.set_nodelay(true);
+
+        self.stream = Some(stream);
+        self.buffer.clear();
+
+        self.halted = true;
+        self.last_stop = StopReason::Breakpoint;
+
+        system.set_debugging(true);
+    }
+
+    fn disconnect(&mut self, system: &mut System) {
+        println!("[GDB] [INFO] Client disconnected");
+
+        self.stream = None;
+        self.buffer.clear();
+
+        self.halted = false;
+
+        system.set_debugging(false);
+    }
+
+    fn receive(&mut self, system: &mut System) {
+        let mut data = [0; 4096];
+
+        loop {
+            let stream = match self.stream.as_mut() {
+                Some(stream) => stream,
+                None => return,
+            };
+
+            match stream.read(&mut data) {
+                Ok(0) => {
+                    self.disconnect(system);
+                    return;
+                },
+                Ok(n) => self.buffer.extend_from_slice(&data[..n]),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    println!("[GDB] [ERROR] Unable to read from client: {}", e);
+                    self.disconnect(system);
+                    return;
+                },
+            }
+        }
+
+        while let Some(packet) = self.next_packet() {
+            self.handle_packet(system, &packet);
+
+            if self.stream.is_none() {
+                break;
+            }
+        }
+    }
+
+    fn next_packet(&mut self) -> Option<String> {
+        loop {
+            match self.buffer.first() {
+                None => return None,
+                Some(0x03) => {
+                    self.buffer.remove(0);
+                    return Some("\x03".to_string());
+                },
+                Some(b'$') => (),
+                Some(_) => {
+                    // Acks and anything outside of a packet
+                    self.buffer.remove(0);
+                    continue;
+                },
+            }
+
+            let end = self.buffer.iter().position(|&b| b == b'#')?;
+
+            if self.buffer.len() < end + 3 {
+                return None;
+            }
+
+            let payload = self.buffer[1..end].to_vec();
+            let checksum = std::str::from_utf8(&self.buffer[end + 1..end + 3]).ok()
+                .and_then(|c| u8::from_str_radix(c, 16).ok());
+
+            self.buffer.drain(..end + 3);
+
+            let expected = payload.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+
+            if checksum != Some(expected) {
+                println!("[GDB] [WARN] Bad packet checksum");
+                self.send_raw(b"-");
+                continue;
+            }
+
+            self.send_raw(b"+");
+
+            return Some(String::from_utf8_lossy(&payload).into_owned());
+        }
+    }
+
+    fn send_raw(&mut self, data: &[u8]) {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        // Replies are small, so it is simpler to block while writing them
+        let result = stream.set_nonblocking(false)
+            .and_then(|_| stream.write_all(data))
+            .and_then(|_| stream.set_nonblocking(true));
+
+        if let Err(e) = result {
+            println!("[GDB] [ERROR] Unable to write to client: {}", e);
+        }
+    }
+
+    fn send(&mut self, payload: &str) {
+        let checksum = payload.bytes().fold(0u8, |acc, b| acc.wrapping_add(b));
+        let packet = format!("${}#{:02x}", payload, checksum);
+
+        self.send_raw(packet.as_bytes());
+    }
+
+    fn halt(&mut self, reason: StopReason) {
+        self.halted = true;
+        self.last_stop = reason;
+
+        self.send(&stop_reply(reason));
+    }
+
+    fn handle_packet(&mut self, system: &mut System, packet: &str) {
+        if packet == "\x03" {
+            if !self.halted {
+                self.halted = true;
+                self.send("S02");
+            }
+
+            return;
+        }
+
+        let (command, args) = match packet.char_indices().nth(1) {
+            Some((i, _)) => packet.split_at(i),
+            None => (packet, ""),
+        };
+
+        let reply = match command {
+            "?" => stop_reply(self.last_stop),
+            "g" => self.read_registers(system),
+            "G" => self.write_registers(system, args),
+            "p" => self.read_register(system, args),
+            "P" => self.write_register(system, args),
+            "m" => self.read_memory(system, args),
+            "M" => self.write_memory(system, args),
+            "Z" | "z" => self.update_breakpoint(system, command == "Z", args),
+            "H" => "OK".to_string(),
+            "q" => self.query(args),
+            "c" => {
+                if let Some(address) = parse_hex(args) {
+                    system.set_debug_register(37, address);
+                }
+
+                self.halted = false;
+                return;
+            },
+            "s" => {
+                if let Some(address) = parse_hex(args) {
+                    system.set_debug_register(37, address);
+                }
+
+                let reason = system.step();
+                self.halt(reason);
+                return;
+            },
+            "D" => {
+                self.send("OK");
+                self.disconnect(system);
+                return;
+            },
+            "k" => {
+                self.disconnect(system);
+                return;
+            },
+            _ => String::new(),
+        };
+
+        self.send(&reply);
+    }
+
+    fn query(&mut self, args: &str) -> String {
+        if args.starts_with("Supported") {
+            return "PacketSize=4000;qXfer:features:read+".to_string();
+        }
+
+        if let Some(request) = args.strip_prefix("Xfer:features:read:target.xml:") {
+            return GdbServer::read_target_xml(request);
+        }
+
+        match args {
+            "Attached" => "1".to_string(),
+            "C" => "QC1".to_string(),
+            "fThreadInfo" => "m1".to_string(),
+            "sThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn read_target_xml(request: &str) -> String {
+        let mut parts = request.split(',');
+
+        let offset = parts.next().and_then(parse_hex);
+        let length = parts.next().and_then(parse_hex);
+
+        match (offset, length) {
+            (Some(offset), Some(length)) => {
+                let offset = (offset as usize).min(TARGET_XML.len());
+                let end = (offset + length as usize).min(TARGET_XML.len());
+
+                let prefix = if end < TARGET_XML.len() { "m" } else { "l" };
+                format!("{}{}", prefix, &TARGET_XML[offset..end])
+            },
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn read_registers(&self, system: &System) -> String {
+        (0..REGISTER_COUNT)
+            .map(|i| encode_hex(&system.debug_register(i).unwrap_or(0).to_le_bytes()))
+            .collect()
+    }
+
+    fn write_registers(&self, system: &mut System, args: &str) -> String {
+        let data = match decode_hex(args) {
+            Some(data) => data,
+            None => return "E01".to_string(),
+        };
+
+        for (i, chunk) in data.chunks_exact(4).enumerate() {
+            let value = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            system.set_debug_register(i, value);
+        }
+
+        "OK".to_string()
+    }
+
+    fn read_register(&self, system: &System, args: &str) -> String {
+        match parse_hex(args).and_then(|i| system.debug_register(i as usize)) {
+            Some(value) => encode_hex(&value.to_le_bytes()),
+            None => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&self, system: &mut System, args: &str) -> String {
+        let mut parts = args.split('=');
+
+        let index = parts.next().and_then(parse_hex);
+        let value = parts.next().and_then(decode_hex);
+
+        match (index, value) {
+            (Some(index), Some(value)) if value.len() == 4 => {
+                let value = u32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+
+                match system.set_debug_register(index as usize, value) {
+                    true => "OK".to_string(),
+                    false => "E01".to_string(),
+                }
+            },
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn read_memory(&self, system: &System, args: &str) -> String {
+        let mut parts = args.split(',');
+
+        let address = parts.next().and_then(parse_hex);
+        let length = parts.next().and_then(parse_hex);
+
+        let (address, length) = match (address, length) {
+            (Some(address), Some(length)) => (address, length),
+            _ => return "E01".to_string(),
+        };
+
+        // Stop at the first unmapped byte, GDB will ask again for the rest
+        let data: Vec<u8> = (0..length)
+            .map_while(|i| system.peek(address.wrapping_add(i)))
+            .collect();
+
+        if data.is_empty() && length != 0 {
+            return "E01".to_string();
+        }
+
+        encode_hex(&data)
+    }
+
+    fn write_memory(&self, system: &mut System, args: &str) -> String {
+        let (location, data) = match args.split_once(':') {
+            Some(parts) => parts,
+            None => return "E01".to_string(),
+        };
+
+        let address = location.split(',').next().and_then(parse_hex);
+
+        let (address, data) = match (address, decode_hex(data)) {
+            (Some(address), Some(data)) => (address, data),
+            _ => return "E01".to_string(),
+        };
+
+        for (i, &byte) in data.iter().enumerate() {
+            if !system.poke(address.wrapping_add(i as u32), byte) {
+                return "E01".to_string();
+            }
+        }
+
+        "OK".to_string()
+    }
+
+    fn update_breakpoint(&self, system: &mut System, insert: bool, args: &str) -> String {
+        let mut parts = args.split(',');
+
+        let kind = parts.next();
+        let address = parts.next().and_then(parse_hex);
+        let length = parts.next().and_then(|l| l.split(';').next()).and_then(parse_hex);
+
+        let (address, length) = match (address, length) {
+            (Some(address), Some(length)) => (address, length),
+            _ => return "E01".to_string(),
+        };
+
+        let watch = match kind {
+            Some("0") | Some("1") => None,
+            Some("2") => Some(WatchKind::Write),
+            Some("3") => Some(WatchKind::Read),
+            Some("4") => Some(WatchKind::Access),
+            _ => return String::new(),
+        };
+
+        match (watch, insert) {
+            (None, true) => system.add_breakpoint(address),
+            (None, false) => system.remove_breakpoint(address),
+            (Some(kind), true) => system.add_watchpoint(address, length, kind),
+            (Some(kind), false) => system.remove_watchpoint(address, length, kind),
+        };
+
+        "OK".to_string()
+    }
+}
@@ -0,0 +1,185 @@
+use rpsx::{GpuCommandRecord, System};
+
+#[derive(Clone, Copy, Default)]
+pub struct Vertex {
+    pub position: (i32, i32),
+    pub colour: [f32; 3],
+    pub texcoord: (u32, u32),
+}
+
+impl Vertex {
+    pub fn position(&self) -> (f32, f32) {
+        (self.position.0 as f32, self.position.1 as f32)
+    }
+
+    // Texture coordinates are in texels, convert them to a VRAM position
+    pub fn texcoord(&self, texpage: &Texpage) -> (f32, f32) {
+        let u = match texpage.depth {
+            0 => self.texcoord.0 / 4,
+            1 => self.texcoord.0 / 2,
+            _ => self.texcoord.0,
+        };
+
+        ((texpage.x_base + u) as f32, (texpage.y_base + self.texcoord.1) as f32)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Texpage {
+    pub x_base: u32,
+    pub y_base: u32,
+    pub depth: u32,
+}
+
+pub struct Polygon {
+    pub quad: bool,
+    pub shaded: bool,
+    pub textured: bool,
+
+    pub vertices: [Vertex; 4],
+
+    pub texpage: Texpage,
+    pub clut: (u32, u32),
+}
+
+pub enum GpuCommand {
+    Polygon(Polygon),
+    Other(Vec<u32>),
+}
+
+fn colour(word: u32) -> [f32; 3] {
+    [
+        (word & 0xff) as f32 / 255.0,
+        ((word >> 8) & 0xff) as f32 / 255.0,
+        ((word >> 16) & 0xff) as f32 / 255.0,
+    ]
+}
+
+fn sign_extend_11(value: u32) -> i32 {
+    ((value << 21) as i32) >> 21
+}
+
+impl GpuCommand {
+    pub fn decode(record: &GpuCommandRecord) -> GpuCommand {
+        let command = record.words[0] >> 24;
+
+        match command {
+            0x20..=0x3f => GpuCommand::Polygon(GpuCommand::decode_polygon(record)),
+            _ => GpuCommand::Other(record.words.clone()),
+        }
+    }
+
+    fn decode_polygon(record: &GpuCommandRecord) -> Polygon {
+        let command = record.words[0] >> 24;
+
+        let shaded = (command & 0x10) != 0;
+        let quad = (command & 0x08) != 0;
+        let textured = (command & 0x04) != 0;
+
+        let mut polygon = Polygon {
+            quad,
+            shaded,
+            textured,
+
+            vertices: [Vertex::default(); 4],
+
+            texpage: Texpage::default(),
+            clut: (0, 0),
+        };
+
+        let word = |i: usize| record.words.get(i).copied().unwrap_or(0);
+        let (x_offset, y_offset) = record.drawing_offset;
+
+        let mut colour_word = word(0);
+        let mut pos = 1;
+
+        for i in 0..(if quad { 4 } else { 3 }) {
+            if shaded && i > 0 {
+                colour_word = word(pos);
+                pos += 1;
+            }
+
+            let position = word(pos);
+            pos += 1;
+
+            let vertex = &mut polygon.vertices[i];
+
+            vertex.colour = colour(colour_word);
+            vertex.position = (sign_extend_11(position & 0x7ff) + x_offset,
+                               sign_extend_11((position >> 16) & 0x7ff) + y_offset);
+
+            if textured {
+                let texcoord = word(pos);
+                pos += 1;
+
+                vertex.texcoord = (texcoord & 0xff, (texcoord >> 8) & 0xff);
+
+                match i {
+                    0 => {
+                        let clut = texcoord >> 16;
+                        polygon.clut = ((clut & 0x3f) * 16, (clut >> 6) & 0x1ff);
+                    },
+                    1 => {
+                        let texpage = texcoord >> 16;
+
+                        polygon.texpage = Texpage {
+                            x_base: (texpage & 0xf) * 64,
+                            y_base: ((texpage >> 4) & 0x1) * 256,
+                            depth: (texpage >> 7) & 0x3,
+                        };
+                    },
+                    _ => {},
+                };
+            }
+        }
+
+        polygon
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            GpuCommand::Polygon(p) => {
+                let shading = if p.shaded { "Shaded" } else { "Flat" };
+                let shape = if p.quad { "quad" } else { "triangle" };
+                let texture = if p.textured { ", textured" } else { "" };
+
+                format!("{} {}{}", shading, shape, texture)
+            },
+            GpuCommand::Other(words) => {
+                let name = match words[0] >> 24 {
+                    0x00 => "NOP",
+                    0x01 => "Clear cache",
+                    0x02 => "Fill rectangle",
+                    0x1f => "Interrupt request",
+                    0x40..=0x5f => "Line",
+                    0x60..=0x7f => "Rectangle",
+                    0x80..=0x9f => "VRAM to VRAM copy",
+                    0xa0..=0xbf => "CPU to VRAM copy",
+                    0xc0..=0xdf => "VRAM to CPU copy",
+                    0xe1 => "Draw mode",
+                    0xe2 => "Texture window",
+                    0xe3 => "Drawing area top left",
+                    0xe4 => "Drawing area bottom right",
+                    0xe5 => "Drawing offset",
+                    0xe6 => "Mask bit",
+                    _ => "Unknown",
+                };
+
+                format!("{} (0x{:02x})", name, words[0] >> 24)
+            },
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct GpuFrame {
+    pub commands: Vec<GpuCommand>,
+}
+
+impl GpuFrame {
+    pub fn capture(system: &System) -> GpuFrame {
+        GpuFrame {
+            commands: system.captured_gpu_commands().iter().map(GpuCommand::decode).collect(),
+        }
+    }
+}
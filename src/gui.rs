@@ -1,32 +1,34 @@
 use imgui::{
-    ImGui,
-    ImGuiCol,
-    ImGuiColorEditFlags,
-    ImGuiCond,
+    im_str,
+    CollapsingHeader,
+    ColorEdit,
+    ColorEditFlags,
+    Condition,
     ImString,
-    ImVec4,
+    MenuItem,
+    StyleColor,
     Ui,
+    Window,
 };
 
+use rpsx::{Dithering, System};
+
 use crate::frontend::Frontend;
 use crate::gpu_viewer::{GpuCommand, GpuFrame};
-use crate::psx::System;
-use crate::{Options, Scaling};
+use crate::{Options, Scaling, SPEEDS};
 
-const WHITE: [f32; 3] = [1.0, 1.0, 1.0];
 const RED: [f32; 3] = [1.0, 0.0, 0.0];
 
 const RED_OVERLAY: [f32; 4] = [1.0, 0.0, 0.0, 0.25];
 const GREEN_OVERLAY: [f32; 4] = [0.0, 1.0, 0.0, 0.25];
+const BLUE_OVERLAY: [f32; 4] = [0.0, 0.0, 1.0, 0.5];
 
 pub struct Gui {
-    pub imgui: ImGui,
-    pub renderer: Renderer,
+    gpu_frame: GpuFrame,
 }
 
 impl Gui {
-    pub fn new(display: &Display) -> Self {
-        let mut imgui = ImGui::init();
+    pub fn new(imgui: &mut imgui::Context) -> Self {
         imgui.set_ini_filename(None);
 
         let style = imgui.style_mut();
@@ -37,94 +39,79 @@ impl Gui {
         style.scrollbar_rounding = 2.0;
         style.grab_rounding = 2.0;
 
-        style.colors[ImGuiCol::Border as usize] = ImVec4::new(0.43, 0.43, 0.50, 0.50);
-        style.colors[ImGuiCol::FrameBg as usize] = ImVec4::new(0.43, 0.43, 0.50, 0.50);
-        style.colors[ImGuiCol::FrameBgHovered as usize] = ImVec4::new(0.98, 0.37, 0.27, 0.40);
-        style.colors[ImGuiCol::FrameBgActive as usize] = ImVec4::new(0.98, 0.37, 0.27, 0.67);
-        style.colors[ImGuiCol::TitleBg as usize] = ImVec4::new(0.04, 0.04, 0.04, 1.00);
-        style.colors[ImGuiCol::TitleBgActive as usize] = ImVec4::new(0.75, 0.29, 0.21, 1.00);
-        style.colors[ImGuiCol::TitleBgCollapsed as usize] = ImVec4::new(0.00, 0.00, 0.00, 0.51);
-        style.colors[ImGuiCol::MenuBarBg as usize] = ImVec4::new(0.14, 0.14, 0.14, 1.00);
-        style.colors[ImGuiCol::ScrollbarBg as usize] = ImVec4::new(0.02, 0.02, 0.02, 0.53);
-        style.colors[ImGuiCol::ScrollbarGrab as usize] = ImVec4::new(0.31, 0.31, 0.31, 1.00);
-        style.colors[ImGuiCol::ScrollbarGrabHovered as usize] = ImVec4::new(0.41, 0.41, 0.41, 1.00);
-        style.colors[ImGuiCol::ScrollbarGrabActive as usize] = ImVec4::new(0.51, 0.51, 0.51, 1.00);
-        style.colors[ImGuiCol::CheckMark as usize] = ImVec4::new(0.98, 0.37, 0.27, 1.00);
-        style.colors[ImGuiCol::SliderGrab as usize] = ImVec4::new(0.88, 0.33, 0.24, 1.00);
-        style.colors[ImGuiCol::SliderGrabActive as usize] = ImVec4::new(0.98, 0.37, 0.27, 1.00);
-        style.colors[ImGuiCol::Button as usize] = ImVec4::new(1.00, 0.39, 0.28, 0.40);
-        style.colors[ImGuiCol::ButtonHovered as usize] = ImVec4::new(0.26, 0.59, 0.98, 1.00);
-        style.colors[ImGuiCol::ButtonActive as usize] = ImVec4::new(0.06, 0.53, 0.98, 1.00);
-        style.colors[ImGuiCol::Header as usize] = ImVec4::new(0.26, 0.59, 0.98, 0.31);
-        style.colors[ImGuiCol::HeaderHovered as usize] = ImVec4::new(0.26, 0.59, 0.98, 0.80);
-        style.colors[ImGuiCol::HeaderActive as usize] = ImVec4::new(0.26, 0.59, 0.98, 1.00);
-        style.colors[ImGuiCol::Separator as usize] = ImVec4::new(0.43, 0.43, 0.50, 0.50);
-        style.colors[ImGuiCol::SeparatorHovered as usize] = ImVec4::new(0.10, 0.40, 0.75, 0.78);
-        style.colors[ImGuiCol::SeparatorActive as usize] = ImVec4::new(0.10, 0.40, 0.75, 1.00);
-        style.colors[ImGuiCol::ResizeGrip as usize] = ImVec4::new(0.26, 0.59, 0.98, 0.25);
-        style.colors[ImGuiCol::ResizeGripHovered as usize] = ImVec4::new(0.26, 0.59, 0.98, 0.67);
-        style.colors[ImGuiCol::ResizeGripActive as usize] = ImVec4::new(0.26, 0.59, 0.98, 0.95);
-        style.colors[ImGuiCol::PlotLines as usize] = ImVec4::new(0.61, 0.61, 0.61, 1.00);
-        style.colors[ImGuiCol::PlotLinesHovered as usize] = ImVec4::new(1.00, 0.43, 0.35, 1.00);
-        style.colors[ImGuiCol::PlotHistogram as usize] = ImVec4::new(0.90, 0.70, 0.00, 1.00);
-        style.colors[ImGuiCol::PlotHistogramHovered as usize] = ImVec4::new(1.00, 0.60, 0.00, 1.00);
-        style.colors[ImGuiCol::TextSelectedBg as usize] = ImVec4::new(0.98, 0.37, 0.27, 0.35);
-        style.colors[ImGuiCol::DragDropTarget as usize] = ImVec4::new(1.00, 1.00, 0.00, 0.90);
-        style.colors[ImGuiCol::NavHighlight as usize] = ImVec4::new(0.26, 0.59, 0.98, 1.00);
-        style.colors[ImGuiCol::NavWindowingHighlight as usize] = ImVec4::new(1.00, 1.00, 1.00, 0.70);
-        style.colors[ImGuiCol::NavWindowingDimBg as usize] = ImVec4::new(0.80, 0.80, 0.80, 0.20);
-        style.colors[ImGuiCol::ModalWindowDimBg as usize] = ImVec4::new(0.80, 0.80, 0.80, 0.35);
-
-        let renderer = Renderer::init(&mut imgui, display).unwrap();
+        style.colors[StyleColor::Border as usize] = [0.43, 0.43, 0.50, 0.50];
+        style.colors[StyleColor::FrameBg as usize] = [0.43, 0.43, 0.50, 0.50];
+        style.colors[StyleColor::FrameBgHovered as usize] = [0.98, 0.37, 0.27, 0.40];
+        style.colors[StyleColor::FrameBgActive as usize] = [0.98, 0.37, 0.27, 0.67];
+        style.colors[StyleColor::TitleBg as usize] = [0.04, 0.04, 0.04, 1.00];
+        style.colors[StyleColor::TitleBgActive as usize] = [0.75, 0.29, 0.21, 1.00];
+        style.colors[StyleColor::TitleBgCollapsed as usize] = [0.00, 0.00, 0.00, 0.51];
+        style.colors[StyleColor::MenuBarBg as usize] = [0.14, 0.14, 0.14, 1.00];
+        style.colors[StyleColor::ScrollbarBg as usize] = [0.02, 0.02, 0.02, 0.53];
+        style.colors[StyleColor::ScrollbarGrab as usize] = [0.31, 0.31, 0.31, 1.00];
+        style.colors[StyleColor::ScrollbarGrabHovered as usize] = [0.41, 0.41, 0.41, 1.00];
+        style.colors[StyleColor::ScrollbarGrabActive as usize] = [0.51, 0.51, 0.51, 1.00];
+        style.colors[StyleColor::CheckMark as usize] = [0.98, 0.37, 0.27, 1.00];
+        style.colors[StyleColor::SliderGrab as usize] = [0.88, 0.33, 0.24, 1.00];
+        style.colors[StyleColor::SliderGrabActive as usize] = [0.98, 0.37, 0.27, 1.00];
+        style.colors[StyleColor::Button as usize] = [1.00, 0.39, 0.28, 0.40];
+        style.colors[StyleColor::ButtonHovered as usize] = [0.26, 0.59, 0.98, 1.00];
+        style.colors[StyleColor::ButtonActive as usize] = [0.06, 0.53, 0.98, 1.00];
+        style.colors[StyleColor::Header as usize] = [0.26, 0.59, 0.98, 0.31];
+        style.colors[StyleColor::HeaderHovered as usize] = [0.26, 0.59, 0.98, 0.80];
+        style.colors[StyleColor::HeaderActive as usize] = [0.26, 0.59, 0.98, 1.00];
+        style.colors[StyleColor::Separator as usize] = [0.43, 0.43, 0.50, 0.50];
+        style.colors[StyleColor::SeparatorHovered as usize] = [0.10, 0.40, 0.75, 0.78];
+        style.colors[StyleColor::SeparatorActive as usize] = [0.10, 0.40, 0.75, 1.00];
+        style.colors[StyleColor::ResizeGrip as usize] = [0.26, 0.59, 0.98, 0.25];
+        style.colors[StyleColor::ResizeGripHovered as usize] = [0.26, 0.59, 0.98, 0.67];
+        style.colors[StyleColor::ResizeGripActive as usize] = [0.26, 0.59, 0.98, 0.95];
+        style.colors[StyleColor::PlotLines as usize] = [0.61, 0.61, 0.61, 1.00];
+        style.colors[StyleColor::PlotLinesHovered as usize] = [1.00, 0.43, 0.35, 1.00];
+        style.colors[StyleColor::PlotHistogram as usize] = [0.90, 0.70, 0.00, 1.00];
+        style.colors[StyleColor::PlotHistogramHovered as usize] = [1.00, 0.60, 0.00, 1.00];
+        style.colors[StyleColor::TextSelectedBg as usize] = [0.98, 0.37, 0.27, 0.35];
+        style.colors[StyleColor::DragDropTarget as usize] = [1.00, 1.00, 0.00, 0.90];
+        style.colors[StyleColor::NavHighlight as usize] = [0.26, 0.59, 0.98, 1.00];
+        style.colors[StyleColor::NavWindowingHighlight as usize] = [1.00, 1.00, 1.00, 0.70];
+        style.colors[StyleColor::NavWindowingDimBg as usize] = [0.80, 0.80, 0.80, 0.20];
+        style.colors[StyleColor::ModalWindowDimBg as usize] = [0.80, 0.80, 0.80, 0.35];
 
         Self {
-            imgui: imgui,
-            renderer: renderer,
+            gpu_frame: GpuFrame::default(),
         }
     }
 
-    pub fn draw(ui: &Ui,
+    pub fn draw(&mut self,
+                ui: &Ui,
                 options: &mut Options,
-                gpu_frame: &mut GpuFrame,
                 system: &mut System,
-                video: &VideoInterface) {
-        let file = ui.menu(im_str!("File"));
-        let emu = ui.menu(im_str!("Emulator"));
-        let debug = ui.menu(im_str!("Debug"));
-        let view = ui.menu(im_str!("View"));
+                window_size: (u32, u32)) {
+        let window_size = [window_size.0 as f32, window_size.1 as f32];
 
         ui.main_menu_bar(|| {
-            file.build(|| { Gui::draw_file_menu(ui, system, video); });
-            emu.build(|| { Gui::draw_emu_menu(ui, options, system); });
-            debug.build(|| { Gui::draw_debug_menu(ui, options); });
-            view.build(|| { Gui::draw_view_menu(ui, options); });
-
-            if options.draw_full_vram && options.draw_display_area {
-                let (window_x, window_y) = video.get_size();
-                let (x, y) = system.get_display_origin();
-                let (w, h) = system.get_display_size();
-
-                let x_scale = (window_x as f32) / 1024.0;
-                let y_scale = (window_y as f32) / 512.0;
-
-                let x1 = (x as f32) * x_scale;
-                let x2 = ((x + w) as f32) * x_scale;
-                let y1 = (y as f32) * y_scale;
-                let y2 = ((y + h) as f32) * y_scale;
-
-                let draw_list = ui.get_window_draw_list();
-
-                draw_list.with_clip_rect([0.0, 0.0], [window_x as f32, window_y as f32], || {
-                    draw_list.add_line([x1, y1], [x2, y1], RED).build();
-                    draw_list.add_line([x1, y2], [x2, y2], RED).build();
-                    draw_list.add_line([x1, y1], [x1, y2], RED).build();
-                    draw_list.add_line([x2, y1], [x2, y2], RED).build();
-                });
-            }
+            ui.menu(im_str!("File"), true, || { Gui::draw_file_menu(ui, system); });
+            ui.menu(im_str!("Emulator"), true, || { Gui::draw_emu_menu(ui, options, system); });
+            ui.menu(im_str!("Debug"), true, || { Gui::draw_debug_menu(ui, options); });
+            ui.menu(im_str!("View"), true, || { Gui::draw_view_menu(ui, options); });
         });
 
+        if options.draw_full_vram && options.draw_display_area {
+            let (x, y) = system.get_display_origin();
+            let (w, h) = system.get_display_size();
+
+            let p1 = Gui::to_screen(window_size, (x as f32, y as f32));
+            let p2 = Gui::to_screen(window_size, ((x + w) as f32, (y + h) as f32));
+
+            ui.get_background_draw_list().add_rect(p1, p2, RED).build();
+        }
+
+        system.set_dithering(options.dithering);
+        system.set_gpu_capture(options.show_gpu_viewer);
+
         if options.show_gpu_viewer {
-            Gui::draw_gpu_frame(ui, options, video, gpu_frame);
+            self.gpu_frame = GpuFrame::capture(system);
+            Gui::draw_gpu_frame(ui, options, window_size, &self.gpu_frame);
         }
 
         if options.show_metrics {
@@ -132,208 +119,281 @@ impl Gui {
         }
     }
 
+    // Maps a VRAM position to the window while the full VRAM is drawn
+    fn to_screen(window_size: [f32; 2], position: (f32, f32)) -> [f32; 2] {
+        [position.0 * window_size[0] / 1024.0, position.1 * window_size[1] / 512.0]
+    }
+
     fn draw_gpu_frame(ui: &Ui,
                       options: &mut Options,
-                      video: &VideoInterface,
+                      window_size: [f32; 2],
                       gpu_frame: &GpuFrame) {
-        ui.window(im_str!("GPU Viewer"))
-            .size((300.0, 395.0), ImGuiCond::Once)
+        let mut opened = options.show_gpu_viewer;
+
+        Window::new(im_str!("GPU Viewer"))
+            .size([300.0, 395.0], Condition::Once)
             .menu_bar(true)
-            .build(|| {
+            .opened(&mut opened)
+            .build(ui, || {
                 ui.menu_bar(|| {
-                    ui.menu(im_str!("Filter")).build(|| {});
-                    ui.menu(im_str!("Options")).build(|| {
-                        Gui::menu_item(ui, "Overlay position", &mut options.gpu_viewer.overlay_position);
-                        Gui::menu_item(ui, "Overlay texture", &mut options.gpu_viewer.overlay_texture);
-                        Gui::menu_item(ui, "Overlay CLUT", &mut options.gpu_viewer.overlay_clut);
+                    ui.menu(im_str!("Options"), true, || {
+                        MenuItem::new(im_str!("Overlay position"))
+                            .build_with_ref(ui, &mut options.gpu_viewer.overlay_position);
+                        MenuItem::new(im_str!("Overlay texture"))
+                            .build_with_ref(ui, &mut options.gpu_viewer.overlay_texture);
+                        MenuItem::new(im_str!("Overlay CLUT"))
+                            .build_with_ref(ui, &mut options.gpu_viewer.overlay_clut);
                     });
                 });
 
-            for i in 0..gpu_frame.commands.len() {
-                let command = &gpu_frame.commands[i];
-                let command_name = GpuCommand::name(command);
-                let title = ImString::new(format!("{}. {}", i, command_name));
+                if !options.draw_full_vram {
+                    ui.text_disabled("Overlays are shown when drawing full VRAM");
+                }
 
-                Gui::draw_gpu_command(ui, video, title, command);
-            }
-        });
+                for (i, command) in gpu_frame.commands.iter().enumerate() {
+                    let title = ImString::new(format!("{}. {}", i, command.name()));
+
+                    Gui::draw_gpu_command(ui, options, window_size, &title, command);
+                }
+            });
+
+        options.show_gpu_viewer = opened;
     }
 
     fn draw_gpu_command(ui: &Ui,
-                        video: &VideoInterface,
-                        title: ImString,
+                        options: &Options,
+                        window_size: [f32; 2],
+                        title: &ImString,
                         command: &GpuCommand) {
-        if ui.collapsing_header(&title).build() {
-            match command {
-                GpuCommand::Polygon(p) => {
-                    if !p.shaded {
-                        let mut colour = p.vertices[0].colour();
-                        let flags = ImGuiColorEditFlags::NoLabel
-                                    | ImGuiColorEditFlags::NoPicker
-                                    | ImGuiColorEditFlags::NoOptions
-                                    | ImGuiColorEditFlags::NoInputs;
-
-                        ui.text("Colour:");
+        if !CollapsingHeader::new(title).build(ui) {
+            return;
+        }
+
+        let flags = ColorEditFlags::NO_LABEL
+                    | ColorEditFlags::NO_PICKER
+                    | ColorEditFlags::NO_OPTIONS
+                    | ColorEditFlags::NO_INPUTS;
+
+        match command {
+            GpuCommand::Polygon(p) => {
+                if !p.shaded {
+                    let mut colour = p.vertices[0].colour;
+
+                    ui.text("Colour:");
+                    ui.same_line(0.0);
+
+                    ColorEdit::new(im_str!("##colour"), &mut colour)
+                        .flags(flags)
+                        .build(ui);
+                }
+
+                let vertices = if p.quad { 4 } else { 3 };
+
+                for i in 0..vertices {
+                    ui.text(format!("Vertex {}", i + 1));
+
+                    if p.shaded {
+                        let mut colour = p.vertices[i].colour;
+                        let label = ImString::new(format!("##colour{}", i));
 
                         ui.same_line(0.0);
 
-                        ui.color_edit(im_str!(""), &mut colour)
+                        ColorEdit::new(&label, &mut colour)
                             .flags(flags)
-                            .build();
+                            .build(ui);
                     }
 
-                    let vertices = if p.quad { 4 } else { 3 };
+                    let (x, y) = p.vertices[i].position;
+                    ui.text(format!("Position: ({}, {})", x, y));
 
-                    for i in 0..vertices {
-                        ui.text(format!("Vertex {}", i + 1));
+                    if p.textured {
+                        let (u, v) = p.vertices[i].texcoord;
+                        ui.text(format!("Texcoord: ({}, {})", u, v));
+                    }
 
-                        if p.shaded {
-                            ui.same_line(0.0);
+                    if i < vertices - 1 {
+                        ui.new_line();
+                    }
+                }
+
+                if p.textured {
+                    ui.new_line();
+                    ui.text(format!("Texpage: ({}, {})", p.texpage.x_base, p.texpage.y_base));
+                    ui.text(format!("CLUT: ({}, {})", p.clut.0, p.clut.1));
+                }
+
+                if options.draw_full_vram {
+                    Gui::draw_polygon_overlay(ui, options, window_size, p);
+                }
+            },
+            GpuCommand::Other(words) => {
+                for word in words.iter() {
+                    ui.text(format!("0x{:08x}", word));
+                }
+            },
+        };
+    }
 
-                            let mut colour = p.vertices[i].colour();
-                            let flags = ImGuiColorEditFlags::NoLabel
-                                        | ImGuiColorEditFlags::NoPicker
-                                        | ImGuiColorEditFlags::NoOptions
-                                        | ImGuiColorEditFlags::NoInputs;
+    fn draw_polygon_overlay(ui: &Ui,
+                            options: &Options,
+                            window_size: [f32; 2],
+                            p: &crate::gpu_viewer::Polygon) {
+        let draw_list = ui.get_background_draw_list();
 
-                            ui.color_edit(im_str!(""), &mut colour)
-                                .flags(flags)
-                                .build();
-                        }
+        let position = |i: usize| Gui::to_screen(window_size, p.vertices[i].position());
+        let texcoord = |i: usize| Gui::to_screen(window_size, p.vertices[i].texcoord(&p.texpage));
 
-                        let (x, y) = p.vertices[i].position;
-                        ui.text(format!("Position: ({}, {})", x, y));
+        let mut triangles = vec![[0, 1, 2]];
 
-                        if p.textured {
-                            let (u, v) = p.vertices[i].texcoord;
-                            ui.text(format!("Texcoord: ({}, {})", u, v));
-                        }
+        if p.quad {
+            triangles.push([1, 2, 3]);
+        }
 
-                        if i < vertices - 1 {
-                            ui.new_line();
-                        }
-                    }
+        for t in triangles.iter() {
+            if options.gpu_viewer.overlay_position {
+                draw_list.add_triangle(position(t[0]), position(t[1]), position(t[2]), GREEN_OVERLAY)
+                    .filled(true)
+                    .build();
+            }
 
-                    let window_size = video.get_size();
-
-                    let p1 = video.to_screen(p.vertices[0].position());
-                    let p2 = video.to_screen(p.vertices[1].position());
-                    let p3 = video.to_screen(p.vertices[2].position());
-                    let p4 = video.to_screen(p.vertices[3].position());
-
-                    let t1 = video.to_screen(p.vertices[0].texcoord(p.texpage));
-                    let t2 = video.to_screen(p.vertices[1].texcoord(p.texpage));
-                    let t3 = video.to_screen(p.vertices[2].texcoord(p.texpage));
-                    let t4 = video.to_screen(p.vertices[3].texcoord(p.texpage));
-
-                    let draw_list = ui.get_window_draw_list();
-                    draw_list.with_clip_rect((0.0, 0.0), window_size, || {
-                        draw_list.add_triangle(p1, p2, p3, GREEN_OVERLAY)
-                            .filled(true)
-                            .build();
-
-                        if p.textured {
-                            draw_list.add_triangle(t1, t2, t3, RED_OVERLAY)
-                                .filled(true)
-                                .build();
-                        }
-
-                        if p.quad {
-                            draw_list.add_triangle(p2, p3, p4, GREEN_OVERLAY)
-                                .filled(true)
-                                .build();
-
-                            if p.textured {
-                                draw_list.add_triangle(t2, t3, t4, RED_OVERLAY)
-                                    .filled(true)
-                                    .build();
-                            }
-                        }
-                    });
-                },
+            if p.textured && options.gpu_viewer.overlay_texture {
+                draw_list.add_triangle(texcoord(t[0]), texcoord(t[1]), texcoord(t[2]), RED_OVERLAY)
+                    .filled(true)
+                    .build();
+            }
+        }
+
+        if p.textured && options.gpu_viewer.overlay_clut {
+            let width = match p.texpage.depth {
+                0 => 16.0,
+                1 => 256.0,
+                _ => 0.0,
             };
+
+            let (x, y) = (p.clut.0 as f32, p.clut.1 as f32);
+
+            let p1 = Gui::to_screen(window_size, (x, y));
+            let p2 = Gui::to_screen(window_size, (x + width, y + 1.0));
+
+            draw_list.add_rect(p1, p2, BLUE_OVERLAY).filled(true).build();
         }
     }
 
-    fn draw_file_menu(ui: &Ui, system: &System, video: &VideoInterface) {
-        if Gui::menu_item(ui, "Dump framebuffer", &mut false) {
+    fn draw_file_menu(ui: &Ui, system: &mut System) {
+        if MenuItem::new(im_str!("Dump VRAM")).build(ui) {
             system.dump_vram();
-            video.dump_framebuffer();
+        }
+
+        ui.separator();
+
+        if MenuItem::new(im_str!("Exit")).build(ui) {
+            system.running = false;
         }
     }
 
     fn draw_emu_menu(ui: &Ui, options: &mut Options, system: &mut System) {
-        if Gui::menu_item_shortcut(ui, "Reset", "F2", &mut false) {
+        if MenuItem::new(im_str!("Reset")).shortcut(im_str!("F2")).build(ui) {
             system.reset();
         }
 
-        Gui::menu_item_shortcut(ui, "Step", "F3", &mut options.step);
-        Gui::menu_item_shortcut(ui, "Pause", "P", &mut options.pause);
-        Gui::menu_item_shortcut(ui, "Frame limit", "TAB", &mut options.frame_limit);
+        MenuItem::new(im_str!("Pause")).shortcut(im_str!("P")).build_with_ref(ui, &mut options.pause);
+
+        if MenuItem::new(im_str!("Step")).shortcut(im_str!("F3")).build(ui) {
+            options.step = true;
+        }
+
+        MenuItem::new(im_str!("Fast forward")).shortcut(im_str!("TAB")).build_with_ref(ui, &mut options.fast_forward);
+
+        ui.menu(im_str!("Speed"), true, || {
+            Gui::draw_speed_menu(ui, &mut options.speed_index);
+        });
+
+        ui.menu(im_str!("Fast forward speed"), true, || {
+            Gui::draw_speed_menu(ui, &mut options.fast_forward_index);
+        });
+
+        MenuItem::new(im_str!("Sync to audio")).build_with_ref(ui, &mut options.sync_audio);
+
+        ui.separator();
+
+        let discs = system.disc_count();
+
+        if MenuItem::new(im_str!("Change disc")).shortcut(im_str!("F5")).enabled(discs > 1).build(ui) {
+            Frontend::change_disc(system);
+        }
+
+        ui.separator();
+
+        if MenuItem::new(im_str!("Save state")).shortcut(im_str!("F7")).build(ui) {
+            Frontend::save_state(system, options.state_index);
+        }
+
+        if MenuItem::new(im_str!("Load state")).shortcut(im_str!("F6")).build(ui) {
+            Frontend::load_state(system, options.state_index);
+        }
+
+        ui.menu(im_str!("State slot"), true, || {
+            for i in 0..10 {
+                let label = ImString::new(format!("Slot {}", i));
+
+                if MenuItem::new(&label).selected(options.state_index == i).build(ui) {
+                    options.state_index = i;
+                }
+            }
+        });
     }
 
     fn draw_debug_menu(ui: &Ui, options: &mut Options) {
-        Gui::menu_item(ui, "Draw full VRAM", &mut options.draw_full_vram);
-        Gui::menu_item(ui, "GPU Viewer", &mut options.show_gpu_viewer);
+        MenuItem::new(im_str!("Draw full VRAM")).shortcut(im_str!("F8")).build_with_ref(ui, &mut options.draw_full_vram);
+        MenuItem::new(im_str!("GPU Viewer")).build_with_ref(ui, &mut options.show_gpu_viewer);
+        MenuItem::new(im_str!("Show metrics")).build_with_ref(ui, &mut options.show_metrics);
+    }
+
+    fn draw_speed_menu(ui: &Ui, index: &mut usize) {
+        for (i, speed) in SPEEDS.iter().enumerate() {
+            let label = ImString::new(format!("{}%", speed * 100.0));
+
+            if MenuItem::new(&label).selected(*index == i).build(ui) {
+                *index = i;
+            }
+        }
     }
 
     fn draw_view_menu(ui: &Ui, options: &mut Options) {
-        Gui::menu_item_enabled(ui, "Draw display area",
-                               options.draw_full_vram,
-                               &mut options.draw_display_area);
+        MenuItem::new(im_str!("Draw display area"))
+            .enabled(options.draw_full_vram)
+            .build_with_ref(ui, &mut options.draw_display_area);
 
-        ui.menu(im_str!("Window scaling")).build(|| {
-            let mut selection = options.scaling as i32;
+        MenuItem::new(im_str!("Crop overscan")).shortcut(im_str!("F9")).build_with_ref(ui, &mut options.crop_overscan);
+
+        ui.menu(im_str!("Dithering"), true, || {
             let items = [
-                im_str!("None"),
-                im_str!("Aspect"),
-                im_str!("Fullscreen")
+                (im_str!("Hardware"), Dithering::Hardware),
+                (im_str!("Off"), Dithering::Off),
+                (im_str!("True colour (24-bit)"), Dithering::TrueColour),
             ];
 
-            if ui.combo(im_str!(""), &mut selection, &items, 3) {
-                options.scaling = Scaling::from(selection);
+            for (label, dithering) in items.iter() {
+                if MenuItem::new(label).selected(options.dithering == *dithering).build(ui) {
+                    options.dithering = *dithering;
+                }
             }
         });
 
-        Gui::menu_item(ui, "Hardware Rendering", &mut options.hardware);
-
-        ui.menu(im_str!("Hardware Scaling")).build(|| {
-            let mut selection = options.hardware_scale as i32;
+        ui.menu(im_str!("Window scaling"), true, || {
             let items = [
-                im_str!("1x"),
-                im_str!("2x"),
-                im_str!("4x"),
-                im_str!("8x"),
-                im_str!("16x"),
+                im_str!("None"),
+                im_str!("Aspect"),
+                im_str!("Fullscreen"),
             ];
 
-            if ui.combo(im_str!(""), &mut selection, &items, 5) {
-                options.hardware_scale = selection as usize;
+            for (i, label) in items.iter().enumerate() {
+                let selected = options.scaling as usize == i;
+
+                if MenuItem::new(label).selected(selected).build(ui) {
+                    options.scaling = Scaling::from(i as i32);
+                }
             }
         });
-
-        Gui::menu_item(ui, "Show metrics", &mut options.show_metrics);
-    }
-
-    fn menu_item(ui: &Ui, label: &str, selected: &mut bool) -> bool {
-        let l = ImString::new(label);
-        ui.menu_item(&l).selected(selected).build()
     }
-
-    fn menu_item_shortcut(ui: &Ui,
-                          label: &str,
-                          shortcut: &str,
-                          selected: &mut bool) -> bool {
-        let l = ImString::new(label);
-        let s = ImString::new(shortcut);
-        ui.menu_item(&l).shortcut(&s).selected(selected).build()
-    }
-
-    fn menu_item_enabled(ui: &Ui,
-                         label: &str,
-                         enabled: bool,
-                         selected: &mut bool) -> bool {
-        let l = ImString::new(label);
-        ui.menu_item(&l).enabled(enabled).selected(selected).build()
-    }
-}
\ No newline at end of file
+}
@@ -0,0 +1,7 @@
+pub mod capture;
+pub mod gdb;
+mod psx;
+pub mod queue;
+pub mod util;
+
+pub use psx::{disassemble, BiosTracer, BusWatchpoint, Controller, DirectoryEntry, Dithering, GpuCommandRecord, IoDevice, Iso9660, StopReason, System, TraceTrigger, Tracer, Volume, WatchHit, WatchKind, XaAttributes};
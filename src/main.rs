@@ -5,19 +5,21 @@ extern crate imgui;
 
 mod audio_interface;
 mod frontend;
-//mod gui;
+mod gpu_viewer;
+mod gui;
+mod pacer;
 
-mod psx;
-pub mod queue;
-pub mod util;
+use std::path::Path;
+use std::time::Duration;
 
 use clap::App;
 
+use rpsx::{BiosTracer, BusWatchpoint, Dithering, IoDevice, System, Tracer};
+use rpsx::gdb::GdbServer;
+
 use audio_interface::AudioInterface;
 use frontend::Frontend;
-//use gui::Gui;
-
-use psx::System;
+use pacer::Pacer;
 
 #[derive(Clone, Copy)]
 pub enum Scaling {
@@ -39,35 +41,78 @@ impl Scaling {
     }
 }
 
+// Multipliers of the console's own frame rate, slow motion below 1
+pub const SPEEDS: [f64; 8] = [0.25, 0.5, 0.75, 1.0, 1.5, 2.0, 3.0, 4.0];
+pub const NORMAL_SPEED: usize = 3;
+
+pub struct GpuViewerOptions {
+    overlay_position: bool,
+    overlay_texture: bool,
+    overlay_clut: bool,
+}
+
 pub struct Options {
     draw_full_vram: bool,
+    draw_display_area: bool,
     scaling: Scaling,
     crop_overscan: bool,
+    dithering: Dithering,
+
+    show_gpu_viewer: bool,
+    show_metrics: bool,
+
+    gpu_viewer: GpuViewerOptions,
 
     pause: bool,
     step: bool,
 
-    frame_limit: bool,
+    speed_index: usize,
+    fast_forward: bool,
+    fast_forward_index: usize,
+    sync_audio: bool,
 
     state_index: usize,
 }
 
+impl Options {
+    pub fn speed(&self) -> f64 {
+        match self.fast_forward {
+            true => SPEEDS[self.fast_forward_index],
+            false => SPEEDS[self.speed_index],
+        }
+    }
+}
+
 fn main() {
     let yaml = load_yaml!("../cli.yaml");
     let matches = App::from_yaml(yaml).get_matches();
 
     let bios_filepath = matches.value_of("BIOS").unwrap();
-    let game_filepath = matches.value_of("GAME").unwrap();
+    let game_filepath = matches.value_of("GAME");
 
     let mut options = Options {
         draw_full_vram: false,
+        draw_display_area: false,
         scaling: Scaling::Aspect,
         crop_overscan: true,
+        dithering: matches.value_of("dither").unwrap().parse().expect("invalid dithering mode"),
+
+        show_gpu_viewer: false,
+        show_metrics: false,
+
+        gpu_viewer: GpuViewerOptions {
+            overlay_position: true,
+            overlay_texture: true,
+            overlay_clut: false,
+        },
 
         pause: false,
         step: false,
 
-        frame_limit: true,
+        speed_index: NORMAL_SPEED,
+        fast_forward: false,
+        fast_forward_index: 6,
+        sync_audio: true,
 
         state_index: 0,
     };
@@ -76,28 +121,110 @@ fn main() {
     let mut audio = AudioInterface::new(&mut sdl_ctx_temp, 44100, 2, 512);
     let mut frontend = Frontend::create(&mut sdl_ctx_temp, 640, 480);
 
-    // Disabled due to Dear ImGui version bump
-    //let mut gui = Gui::new(&video.display);
-
-    let mut system = System::new(bios_filepath.to_string(), game_filepath.to_string());
+    let mut system = System::new(bios_filepath.to_string(), game_filepath.map(|s| s.to_string()))
+        .expect("unable to start emulator");
+    system.open_memory_cards(Path::new("./cards"));
     system.reset();
 
+    if let Some(filepath) = matches.value_of("trace") {
+        let start = matches.value_of("trace-start").map(|t| t.parse().expect("invalid trace start"));
+        let stop = matches.value_of("trace-stop").map(|t| t.parse().expect("invalid trace stop"));
+        let ring = matches.value_of("trace-ring").map(|n| n.parse().expect("invalid trace ring size"));
+
+        let tracer = Tracer::create(Path::new(filepath), start, stop, ring)
+            .expect("unable to create trace file");
+        system.set_tracer(Some(tracer));
+    }
+
+    if let Some(filepath) = matches.value_of("bios-trace") {
+        let bios_tracer = BiosTracer::create(Path::new(filepath), matches.is_present("bios-trace-returns"))
+            .expect("unable to create BIOS trace file");
+        system.set_bios_tracer(Some(bios_tracer));
+    }
+
+    if let Some(values) = matches.values_of("watch") {
+        for value in values {
+            let watchpoint: BusWatchpoint = value.parse().expect("invalid watchpoint");
+            system.add_bus_watchpoint(watchpoint);
+        }
+    }
+
+    if let Some(filepath) = matches.value_of("io-log") {
+        let devices: Vec<IoDevice> = match matches.value_of("io-log-devices") {
+            Some(list) => list.split(',').map(|d| d.parse().expect("invalid I/O log device")).collect(),
+            None => IoDevice::ALL.to_vec(),
+        };
+
+        system.start_io_log(Path::new(filepath), &devices).expect("unable to create I/O log");
+    }
+
+    if let Some(exe) = matches.value_of("exe") {
+        let args: Vec<String> = matches.values_of("exe-arg")
+            .map(|values| values.map(|v| v.to_string()).collect())
+            .unwrap_or_default();
+
+        system.boot_psexe(exe, &args).expect("unable to load executable");
+    } else if matches.is_present("fast-boot") {
+        system.fast_boot().expect("unable to fast boot");
+    }
+
+    let mut gdb = matches.value_of("gdb").map(|port| {
+        let port = port.parse().expect("invalid GDB port");
+        GdbServer::bind(port).expect("unable to start GDB server")
+    });
+
+    let mut pacer = Pacer::new();
+
     audio.play();
 
     while system.running {
-        if options.step {
+        if let Some(gdb) = gdb.as_mut() {
+            gdb.update(&mut system);
+        }
+
+        let halted = gdb.as_ref().is_some_and(|gdb| gdb.halted());
+
+        if options.step && !halted {
             system.run_frame();
 
             options.step = false;
             options.pause = true;
         }
 
-        if !options.pause {
+        let running = !options.pause && !halted;
+
+        if running {
             system.run_frame();
         }
 
-        audio.push_samples(system.get_audio_samples());
+        if let Some(gdb) = gdb.as_mut() {
+            gdb.check_stop(&mut system);
+        }
+
+        if let Some(hit) = system.take_watch_hit() {
+            let access = match hit.write {
+                true => "write",
+                false => "read",
+            };
+
+            println!("[WATCH] [INFO] {}-bit {} of 0x{:08x} at 0x{:08x} by pc 0x{:08x}",
+                     hit.width * 8, access, hit.value, hit.address, hit.pc);
+
+            options.pause = true;
+        }
+
+        let speed = options.speed();
+
+        audio.push_samples(system.get_audio_samples(), speed);
         frontend.update(&mut options, &mut system);
-        frontend.render(&options, &system);
+        frontend.render(&mut options, &mut system);
+
+        // While paused there is no audio to pace against
+        if options.sync_audio && running {
+            audio.wait();
+            pacer.reset();
+        } else {
+            pacer.wait(Duration::from_secs_f64(1.0 / (system.get_refresh_rate() * speed)));
+        }
     }
 }
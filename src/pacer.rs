@@ -0,0 +1,35 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Falling further behind than this starts a new schedule rather than running
+// flat out to catch up
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+// Paces frames against the wall clock
+pub struct Pacer {
+    next_frame: Instant,
+}
+
+impl Pacer {
+    pub fn new() -> Pacer {
+        Pacer {
+            next_frame: Instant::now(),
+        }
+    }
+
+    pub fn wait(&mut self, frame_time: Duration) {
+        self.next_frame += frame_time;
+
+        let now = Instant::now();
+
+        if self.next_frame > now {
+            thread::sleep(self.next_frame - now);
+        } else if now - self.next_frame > MAX_LAG {
+            self.next_frame = now;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.next_frame = Instant::now();
+    }
+}
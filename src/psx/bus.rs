@@ -1,18 +1,19 @@
 use byteorder::{ByteOrder, LittleEndian};
 use serde::{Deserialize, Serialize};
 
-use crate::util;
-
 use super::cdrom::Cdrom;
 use super::exp2::Exp2;
 use super::gpu::Gpu;
 use super::intc::Intc;
 use super::sio0::Sio0;
 use super::mdec::Mdec;
+use super::monitor::BusMonitor;
 use super::spu::Spu;
 use super::timekeeper::{Device, Timekeeper};
 use super::timers::Timers;
 
+// Named the way the hardware documentation names them
+#[allow(clippy::upper_case_acronyms)]
 #[derive(PartialEq)]
 pub enum BusWidth {
     BYTE,
@@ -20,6 +21,16 @@ pub enum BusWidth {
     WORD,
 }
 
+impl BusWidth {
+    pub fn size(&self) -> u32 {
+        match self {
+            BusWidth::BYTE => 1,
+            BusWidth::HALF => 2,
+            BusWidth::WORD => 4,
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct Bus {
     bios: Box<[u8]>,
@@ -37,38 +48,19 @@ pub struct Bus {
     intc: Intc,
 
     timers: Timers,
+
+    #[serde(skip)]
+    monitor: BusMonitor,
 }
 
 impl Bus {
-    pub fn new(bios_filepath: &str, game_filepath: &str) -> Bus {
-        let mut bios = util::read_file_to_box(bios_filepath);
-
-        /* Enable TTY output */
-        bios[0x6f0c] = 0x01;
-        bios[0x6f0d] = 0x00;
-        bios[0x6f0e] = 0x01;
-        bios[0x6f0f] = 0x24;
-        bios[0x6f14] = 0xc0;
-        bios[0x6f15] = 0xa9;
-        bios[0x6f16] = 0x81;
-        bios[0x6f17] = 0xaf;
-
-        /* Fast boot */
-        //bios[0x18000] = 0x08;
-        //bios[0x18001] = 0x00;
-        //bios[0x18002] = 0xe0;
-        //bios[0x18003] = 0x03;
-        //bios[0x18004] = 0x00;
-        //bios[0x18005] = 0x00;
-        //bios[0x18006] = 0x00;
-        //bios[0x18007] = 0x00;
-
+    pub fn new(bios: Box<[u8]>, cdrom: Cdrom) -> Bus {
         Bus {
-            bios: bios,
+            bios,
             ram: vec![0; 0x200000].into_boxed_slice(),
             scratchpad: vec![0; 0x400].into_boxed_slice(),
 
-            cdrom: Cdrom::new(game_filepath),
+            cdrom,
             gpu: Gpu::new(),
             mdec: Mdec::new(),
             sio0: Sio0::new(),
@@ -79,6 +71,8 @@ impl Bus {
             intc: Intc::new(),
 
             timers: Timers::new(),
+
+            monitor: BusMonitor::default(),
         }
     }
 
@@ -115,10 +109,38 @@ impl Bus {
         &mut self.spu
     }
 
+    pub fn exp2(&mut self) -> &mut Exp2 {
+        &mut self.exp2
+    }
+
     pub fn intc(&mut self) -> &mut Intc {
         &mut self.intc
     }
 
+    pub fn monitor(&mut self) -> &mut BusMonitor {
+        &mut self.monitor
+    }
+
+    // Side effect free access for debuggers, only memory is visible
+    pub fn peek(&self, address: u32) -> Option<u8> {
+        match address {
+            0x0000_0000..=0x007f_ffff => Some(self.ram[(address & 0x1f_ffff) as usize]),
+            0x1f80_0000..=0x1f80_03ff => Some(self.scratchpad[(address - 0x1f80_0000) as usize]),
+            0x1fc0_0000..=0x1fc7_ffff => Some(self.bios[(address - 0x1fc0_0000) as usize]),
+            _ => None,
+        }
+    }
+
+    pub fn poke(&mut self, address: u32, value: u8) -> bool {
+        match address {
+            0x0000_0000..=0x007f_ffff => self.ram[(address & 0x1f_ffff) as usize] = value,
+            0x1f80_0000..=0x1f80_03ff => self.scratchpad[(address - 0x1f80_0000) as usize] = value,
+            _ => return false,
+        };
+
+        true
+    }
+
     pub fn tick_device_by_id(&mut self, device: Device, cycles: usize) {
         let intc = &mut self.intc;
 
@@ -134,6 +156,21 @@ impl Bus {
     }
 
     pub fn load(&mut self, tk: &mut Timekeeper, width: BusWidth, address: u32) -> (u32, bool) {
+        let (value, error) = self.read(tk, &width, address);
+
+        if self.monitor.enabled() {
+            self.monitor.access(address, &width, value, false);
+        }
+
+        (value, error)
+    }
+
+    // Instruction fetches are not seen by the monitor
+    pub fn fetch(&mut self, tk: &mut Timekeeper, address: u32) -> (u32, bool) {
+        self.read(tk, &BusWidth::WORD, address)
+    }
+
+    fn read(&mut self, tk: &mut Timekeeper, width: &BusWidth, address: u32) -> (u32, bool) {
         let mut error = false;
 
         let value = match address {
@@ -188,7 +225,7 @@ impl Bus {
             0x1f80_1800..=0x1f80_1803 => {
                 tk.sync_device(self, Device::Cdrom);
 
-                if address == 0x1f80_1802 && width == BusWidth::HALF {
+                if address == 0x1f80_1802 && *width == BusWidth::HALF {
                     self.cdrom.read_data_half() as u32
                 } else {
                     self.cdrom.read(address) as u32
@@ -211,7 +248,7 @@ impl Bus {
                 match width {
                     BusWidth::BYTE => self.spu.read16(address & !0x1) as u32,
                     BusWidth::HALF => self.spu.read16(address) as u32,
-                    BusWidth::WORD => self.spu.read32(address) as u32,
+                    BusWidth::WORD => self.spu.read32(address),
                 }
             },
             0x1f80_2000..=0x1f80_207f => self.exp2.read8(address) as u32,
@@ -231,6 +268,10 @@ impl Bus {
     }
 
     pub fn store(&mut self, tk: &mut Timekeeper, width: BusWidth, address: u32, value: u32) -> bool {
+        if self.monitor.enabled() {
+            self.monitor.access(address, &width, value, true);
+        }
+
         let mut error = false;
 
         match address {
@@ -1,10 +1,37 @@
-use std::io::{self, Read, Seek};
+use std::io::{self, Cursor, Read, Seek};
 use std::{fs, path};
 
-use super::Container;
+use super::{Container, Track};
+
+trait Image: Read + Seek {}
+
+impl<T: Read + Seek> Image for T {}
 
 pub struct Bin {
-    file: fs::File,
+    file: Box<dyn Image>,
+    tracks: [Track; 1],
+    lead_out: usize,
+}
+
+impl Bin {
+    fn new(file: Box<dyn Image>, size: usize) -> Box<Self> {
+        let track = Track {
+            number: 1,
+            audio: false,
+            start: 0,
+        };
+
+        Box::new(Self {
+            file,
+            tracks: [track],
+            lead_out: size / 2352,
+        })
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Box<Self> {
+        let size = bytes.len();
+        Bin::new(Box::new(Cursor::new(bytes)), size)
+    }
 }
 
 impl Container for Bin {
@@ -13,14 +40,17 @@ impl Container for Bin {
             return Err("File does not exist.".to_string());
         }
 
-        let file;
+        let file = match fs::File::open(filepath) {
+            Ok(f) => f,
+            Err(e) => return Err(e.to_string()),
+        };
 
-        match fs::File::open(filepath) {
-            Ok(f) => file = f,
+        let size = match file.metadata() {
+            Ok(m) => m.len() as usize,
             Err(e) => return Err(e.to_string()),
         };
 
-        Ok(Box::new(Self { file: file }))
+        Ok(Bin::new(Box::new(file), size))
     }
 
     fn read(&mut self, lba: usize, buffer: &mut [u8; 2352]) -> Result<(), String> {
@@ -36,4 +66,12 @@ impl Container for Bin {
 
         Ok(())
     }
+
+    fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn lead_out(&self) -> usize {
+        self.lead_out
+    }
 }
\ No newline at end of file
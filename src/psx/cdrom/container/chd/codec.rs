@@ -0,0 +1,246 @@
+use std::convert::TryFrom;
+use std::io::{self, Read};
+
+use flate2::read::DeflateDecoder;
+use xz2::read::XzDecoder;
+use xz2::stream::Stream;
+
+use super::super::ecc;
+
+pub const SECTOR_SIZE: usize = 2352;
+pub const SUBCODE_SIZE: usize = 96;
+pub const FRAME_SIZE: usize = SECTOR_SIZE + SUBCODE_SIZE;
+
+pub const CODEC_CD_ZLIB: u32 = 0x6364_7a6c; // cdzl
+pub const CODEC_CD_LZMA: u32 = 0x6364_6c7a; // cdlz
+pub const CODEC_CD_FLAC: u32 = 0x6364_666c; // cdfl
+
+pub fn decompress(codec: u32, src: &[u8], hunk: &mut [u8]) -> Result<(), String> {
+    match codec {
+        CODEC_CD_ZLIB | CODEC_CD_LZMA => decompress_cd(codec, src, hunk),
+        CODEC_CD_FLAC => decompress_cd_flac(src, hunk),
+        _ => Err(format!("Unsupported CHD codec 0x{:08x}", codec)),
+    }
+}
+
+fn inflate(src: &[u8], dest: &mut [u8]) -> Result<(), String> {
+    DeflateDecoder::new(src).read_exact(dest).map_err(|e| e.to_string())
+}
+
+fn unlzma(src: &[u8], dest: &mut [u8]) -> Result<(), String> {
+    // The stream is headerless LZMA1 (lc=3, lp=0, pb=2) with the dictionary
+    // size chosen by the encoder from the uncompressed size. Rebuild the
+    // .lzma header so liblzma can decode it.
+    let reduce_size = dest.len() as u32;
+    let mut dictionary_size = 1 << 26;
+
+    for i in 11..=30 {
+        if reduce_size <= (2 << i) {
+            dictionary_size = 2 << i;
+            break;
+        }
+
+        if reduce_size <= (3 << i) {
+            dictionary_size = 3 << i;
+            break;
+        }
+    }
+
+    let mut header = vec![0x5d];
+    header.extend_from_slice(&(dictionary_size as u32).to_le_bytes());
+    header.extend_from_slice(&(dest.len() as u64).to_le_bytes());
+
+    let stream = Stream::new_lzma_decoder(u64::MAX).map_err(|e| e.to_string())?;
+
+    XzDecoder::new_stream(header.as_slice().chain(src), stream)
+        .read_exact(dest)
+        .map_err(|e| e.to_string())
+}
+
+fn interleave(sectors: &[u8], subcode: &[u8], hunk: &mut [u8]) {
+    let frames = hunk.len() / FRAME_SIZE;
+
+    for i in 0..frames {
+        let frame = &mut hunk[i * FRAME_SIZE..(i + 1) * FRAME_SIZE];
+
+        frame[..SECTOR_SIZE].copy_from_slice(&sectors[i * SECTOR_SIZE..(i + 1) * SECTOR_SIZE]);
+        frame[SECTOR_SIZE..].copy_from_slice(&subcode[i * SUBCODE_SIZE..(i + 1) * SUBCODE_SIZE]);
+    }
+}
+
+fn decompress_cd(codec: u32, src: &[u8], hunk: &mut [u8]) -> Result<(), String> {
+    let frames = hunk.len() / FRAME_SIZE;
+
+    let ecc_bytes = frames.div_ceil(8);
+    let length_bytes = if hunk.len() < 65536 { 2 } else { 3 };
+    let header_bytes = ecc_bytes + length_bytes;
+
+    if src.len() < header_bytes {
+        return Err("Truncated CD hunk".to_string());
+    }
+
+    let base_length = src[ecc_bytes..header_bytes].iter().fold(0, |acc, &b| (acc << 8) | b as usize);
+
+    if src.len() < header_bytes + base_length {
+        return Err("Truncated CD hunk".to_string());
+    }
+
+    let base = &src[header_bytes..header_bytes + base_length];
+    let mut sectors = vec![0; frames * SECTOR_SIZE];
+
+    match codec {
+        CODEC_CD_ZLIB => inflate(base, &mut sectors)?,
+        _ => unlzma(base, &mut sectors)?,
+    };
+
+    let mut subcode = vec![0; frames * SUBCODE_SIZE];
+    inflate(&src[header_bytes + base_length..], &mut subcode)?;
+
+    interleave(&sectors, &subcode, hunk);
+
+    // Frames flagged in the bitmap had their sync pattern and ECC stripped
+    for i in 0..frames {
+        if (src[i / 8] & (1 << (i % 8))) != 0 {
+            let sector = <&mut [u8; SECTOR_SIZE]>::try_from(&mut hunk[i * FRAME_SIZE..i * FRAME_SIZE + SECTOR_SIZE]).unwrap();
+
+            sector[..0xc].copy_from_slice(&ecc::SYNC);
+            ecc::generate_ecc(sector);
+        }
+    }
+
+    Ok(())
+}
+
+fn decompress_cd_flac(src: &[u8], hunk: &mut [u8]) -> Result<(), String> {
+    let frames = hunk.len() / FRAME_SIZE;
+    let length = frames * SECTOR_SIZE;
+
+    // The FLAC frames are stored without a stream header, and each frame
+    // header carries its own block size and sample format. Decoding straight
+    // from a cursor rather than through FlacReader, which reads ahead, counts
+    // exactly the bytes the frames took.
+    let mut blocks = claxon::frame::FrameReader::new(io::Cursor::new(src));
+
+    let mut sectors = vec![0; length];
+    let mut offset = 0;
+    let mut buffer = Vec::new();
+
+    while offset < length {
+        let block = match blocks.read_next_or_eof(buffer) {
+            Ok(Some(block)) => block,
+            Ok(None) => return Err("Truncated FLAC hunk".to_string()),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        if block.channels() != 2 {
+            return Err(format!("FLAC hunk has {} channels", block.channels()));
+        }
+
+        for i in 0..block.duration() {
+            if offset >= length {
+                break;
+            }
+
+            // Samples are stored big-endian, matching the other codecs
+            let left = block.sample(0, i) as i16;
+            let right = block.sample(1, i) as i16;
+
+            sectors[offset..offset + 2].copy_from_slice(&left.to_be_bytes());
+            sectors[offset + 2..offset + 4].copy_from_slice(&right.to_be_bytes());
+
+            offset += 4;
+        }
+
+        buffer = block.into_buffer();
+    }
+
+    // The deflated subcode follows the last FLAC frame
+    let consumed = blocks.into_inner().position() as usize;
+
+    let mut subcode = vec![0; frames * SUBCODE_SIZE];
+    inflate(&src[consumed..], &mut subcode)?;
+
+    interleave(&sectors, &subcode, hunk);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+
+    use super::{decompress, CODEC_CD_FLAC, FRAME_SIZE, SECTOR_SIZE, SUBCODE_SIZE};
+
+    fn crc8(data: &[u8]) -> u8 {
+        data.iter().fold(0, |crc, &byte| {
+            (0..8).fold(crc ^ byte, |crc, _| match crc & 0x80 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x07,
+            })
+        })
+    }
+
+    fn crc16(data: &[u8]) -> u16 {
+        data.iter().fold(0, |crc, &byte| {
+            (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x8005,
+            })
+        })
+    }
+
+    // One 16-bit stereo frame holding a constant subframe per channel
+    fn flac_frame(samples: u16, left: i16, right: i16) -> Vec<u8> {
+        let mut frame = vec![0xff, 0xf8, 0x79, 0x18, 0x00];
+        frame.extend_from_slice(&(samples - 1).to_be_bytes());
+        frame.push(crc8(&frame));
+
+        for value in [left, right] {
+            frame.push(0x00);
+            frame.extend_from_slice(&value.to_be_bytes());
+        }
+
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn flac_hunk_with_subcode() {
+        let subcode: Vec<u8> = (0..SUBCODE_SIZE as u8).collect();
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&subcode).unwrap();
+
+        // A sector is 588 stereo samples, split over two frames
+        let mut src = flac_frame(288, 0x1234, -2);
+        src.extend(flac_frame(300, -0x5678, 7));
+        src.extend(encoder.finish().unwrap());
+
+        let mut hunk = vec![0; FRAME_SIZE];
+        decompress(CODEC_CD_FLAC, &src, &mut hunk).unwrap();
+
+        for (i, sample) in hunk[..SECTOR_SIZE].chunks(4).enumerate() {
+            let expected: [u8; 4] = match i < 288 {
+                true => [0x12, 0x34, 0xff, 0xfe],
+                false => [0xa9, 0x88, 0x00, 0x07],
+            };
+
+            assert_eq!(sample, expected, "sample {}", i);
+        }
+
+        assert_eq!(&hunk[SECTOR_SIZE..], subcode.as_slice());
+    }
+
+    #[test]
+    fn truncated_flac_hunk() {
+        let mut hunk = vec![0; FRAME_SIZE];
+        let src = flac_frame(288, 0, 0);
+
+        assert!(decompress(CODEC_CD_FLAC, &src, &mut hunk).is_err());
+        assert!(decompress(CODEC_CD_FLAC, &src[..5], &mut hunk).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            data,
+            position: 0,
+        }
+    }
+
+    pub fn peek(&self, bits: usize) -> u32 {
+        let mut value = 0;
+
+        for i in 0..bits {
+            let position = self.position + i;
+            let byte = self.data.get(position / 8).copied().unwrap_or(0);
+
+            value = (value << 1) | ((byte >> (7 - (position % 8))) & 0x1) as u32;
+        }
+
+        value
+    }
+
+    pub fn skip(&mut self, bits: usize) {
+        self.position += bits;
+    }
+
+    pub fn read(&mut self, bits: usize) -> u32 {
+        let value = self.peek(bits);
+        self.skip(bits);
+        value
+    }
+
+    pub fn overflowed(&self) -> bool {
+        self.position > self.data.len() * 8
+    }
+}
+
+// Canonical Huffman decoder, used for the compressed hunk map
+pub struct Huffman {
+    max_bits: usize,
+    lookup: Vec<u16>,
+}
+
+impl Huffman {
+    pub fn import_tree_rle(codes: usize, max_bits: usize, reader: &mut BitReader) -> Result<Huffman, String> {
+        let bits = match max_bits {
+            16.. => 5,
+            8.. => 4,
+            _ => 3,
+        };
+
+        let mut lengths = Vec::with_capacity(codes);
+
+        while lengths.len() < codes {
+            let length = reader.read(bits) as usize;
+
+            if length != 1 {
+                lengths.push(length);
+                continue;
+            }
+
+            let length = reader.read(bits) as usize;
+
+            if length == 1 {
+                lengths.push(length);
+                continue;
+            }
+
+            let repeat = reader.read(bits) as usize + 3;
+
+            if lengths.len() + repeat > codes {
+                return Err("Invalid Huffman tree".to_string());
+            }
+
+            lengths.extend(std::iter::repeat_n(length, repeat));
+        }
+
+        if reader.overflowed() {
+            return Err("Truncated Huffman tree".to_string());
+        }
+
+        Huffman::new(&lengths, max_bits)
+    }
+
+    fn new(lengths: &[usize], max_bits: usize) -> Result<Huffman, String> {
+        let mut histogram = [0u32; 33];
+
+        for &length in lengths.iter() {
+            if length > max_bits {
+                return Err("Invalid Huffman code length".to_string());
+            }
+
+            histogram[length] += 1;
+        }
+
+        // Assign canonical codes, starting from the longest length
+        let mut start = 0;
+
+        for length in (1..=32).rev() {
+            let next = (start + histogram[length]) >> 1;
+
+            if length != 1 && next * 2 != start + histogram[length] {
+                return Err("Invalid Huffman tree".to_string());
+            }
+
+            histogram[length] = start;
+            start = next;
+        }
+
+        let mut lookup = vec![0; 1 << max_bits];
+
+        for (code, &length) in lengths.iter().enumerate() {
+            if length == 0 {
+                continue;
+            }
+
+            let bits = histogram[length] as usize;
+            histogram[length] += 1;
+
+            let shift = max_bits - length;
+            let value = ((code as u16) << 5) | length as u16;
+
+            for entry in lookup[(bits << shift)..((bits + 1) << shift)].iter_mut() {
+                *entry = value;
+            }
+        }
+
+        Ok(Huffman {
+            max_bits,
+            lookup,
+        })
+    }
+
+    pub fn decode(&self, reader: &mut BitReader) -> u32 {
+        let entry = self.lookup[reader.peek(self.max_bits) as usize];
+        reader.skip((entry & 0x1f) as usize);
+
+        (entry >> 5) as u32
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::{BitReader, Huffman};
+
+    #[test]
+    fn bit_reader() {
+        let mut reader = BitReader::new(&[0b1011_0010, 0xff]);
+
+        assert_eq!(reader.read(3), 0b101);
+        assert_eq!(reader.peek(5), 0b10010);
+        assert_eq!(reader.read(9), 0b1_0010_1111);
+        assert!(!reader.overflowed());
+
+        // Reads past the end see zeros
+        assert_eq!(reader.read(8), 0b1111_0000);
+        assert!(reader.overflowed());
+    }
+
+    #[test]
+    fn tree_with_runs() {
+        // Four-bit lengths with 1 as the escape: a run of four 2s, then a run
+        // of twelve 0s
+        let tree = [0x12, 0x11, 0x09];
+        let codes = [0b0001_1011];
+
+        let mut reader = BitReader::new(&tree);
+        let huffman = Huffman::import_tree_rle(16, 8, &mut reader).unwrap();
+
+        let mut reader = BitReader::new(&codes);
+        let symbols: Vec<u32> = (0..4).map(|_| huffman.decode(&mut reader)).collect();
+
+        assert_eq!(symbols, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn invalid_trees() {
+        // Three 2-bit codes leave the tree incomplete
+        assert!(Huffman::import_tree_rle(16, 8, &mut BitReader::new(&[0x12, 0x01, 0x0a])).is_err());
+
+        // A run longer than the code count
+        assert!(Huffman::import_tree_rle(16, 8, &mut BitReader::new(&[0x10, 0xf0])).is_err());
+
+        // Out of data part way through
+        assert!(Huffman::import_tree_rle(16, 8, &mut BitReader::new(&[0x22])).is_err());
+    }
+}
@@ -0,0 +1,585 @@
+mod codec;
+mod huffman;
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Seek};
+use std::{fs, path};
+
+use super::{Container, Track};
+
+use codec::{FRAME_SIZE, SECTOR_SIZE};
+use huffman::{BitReader, Huffman};
+
+const HEADER_SIZE: usize = 124;
+const CACHE_SIZE: usize = 16;
+
+const METADATA_CD_TRACK: u32 = 0x4348_5452; // CHTR
+const METADATA_CD_TRACK2: u32 = 0x4348_5432; // CHT2
+
+const COMPRESSION_TYPE_3: u8 = 3;
+const COMPRESSION_NONE: u8 = 4;
+const COMPRESSION_SELF: u8 = 5;
+const COMPRESSION_PARENT: u8 = 6;
+const COMPRESSION_RLE_SMALL: u32 = 7;
+const COMPRESSION_RLE_LARGE: u32 = 8;
+const COMPRESSION_SELF_0: u8 = 9;
+const COMPRESSION_SELF_1: u8 = 10;
+const COMPRESSION_PARENT_SELF: u8 = 11;
+const COMPRESSION_PARENT_0: u8 = 12;
+const COMPRESSION_PARENT_1: u8 = 13;
+
+// Tracks are padded to a multiple of this many frames inside the CHD
+const TRACK_PADDING: usize = 4;
+
+#[derive(Clone, Copy)]
+struct MapEntry {
+    compression: u8,
+    length: usize,
+    offset: u64,
+}
+
+// A run of consecutive sectors on the disc, either backed by frames of the
+// CHD or silence for pregaps and postgaps that are not stored.
+struct Region {
+    start: usize,
+    length: usize,
+    frame: Option<usize>,
+    audio: bool,
+}
+
+pub struct Chd {
+    file: fs::File,
+
+    compressors: [u32; 4],
+    hunk_bytes: usize,
+    unit_bytes: usize,
+    map: Vec<MapEntry>,
+
+    cache: VecDeque<(usize, Vec<u8>)>,
+
+    regions: Vec<Region>,
+    tracks: Vec<Track>,
+    lead_out: usize,
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+fn read_u48(data: &[u8]) -> u64 {
+    data[..6].iter().fold(0, |acc, &b| (acc << 8) | b as u64)
+}
+
+fn read_u64(data: &[u8]) -> u64 {
+    data[..8].iter().fold(0, |acc, &b| (acc << 8) | b as u64)
+}
+
+fn read_at(file: &mut fs::File, offset: u64, buffer: &mut [u8]) -> Result<(), String> {
+    if let Err(e) = file.seek(io::SeekFrom::Start(offset)) {
+        return Err(e.to_string());
+    }
+
+    if let Err(e) = file.read_exact(buffer) {
+        return Err(e.to_string());
+    }
+
+    Ok(())
+}
+
+// The compressed map is a Huffman coded list of compression types followed
+// by the lengths, CRCs and hunk references they need
+fn decode_map(header: &[u8],
+              compressed: &[u8],
+              hunks: usize,
+              hunk_bytes: usize,
+              unit_bytes: usize) -> Result<Vec<MapEntry>, String> {
+    let first_offset = read_u48(&header[4..]);
+    let length_bits = header[12] as usize;
+    let self_bits = header[13] as usize;
+    let parent_bits = header[14] as usize;
+
+    let mut reader = BitReader::new(compressed);
+    let huffman = Huffman::import_tree_rle(16, 8, &mut reader)?;
+
+    let mut types = Vec::with_capacity(hunks);
+    let mut last_type = 0;
+    let mut repeat = 0;
+
+    while types.len() < hunks {
+        if repeat > 0 {
+            types.push(last_type);
+            repeat -= 1;
+            continue;
+        }
+
+        match huffman.decode(&mut reader) {
+            COMPRESSION_RLE_SMALL => {
+                types.push(last_type);
+                repeat = 2 + huffman.decode(&mut reader);
+            },
+            COMPRESSION_RLE_LARGE => {
+                types.push(last_type);
+                repeat = 2 + 16 + (huffman.decode(&mut reader) << 4);
+                repeat += huffman.decode(&mut reader);
+            },
+            value => {
+                last_type = value as u8;
+                types.push(last_type);
+            },
+        };
+    }
+
+    let mut map = Vec::with_capacity(hunks);
+    let mut current_offset = first_offset;
+    let mut last_self = 0;
+    let mut last_parent = 0;
+
+    for (hunk, &compression) in types.iter().enumerate() {
+        let mut entry = MapEntry {
+            compression,
+            length: 0,
+            offset: 0,
+        };
+
+        match compression {
+            0..=COMPRESSION_TYPE_3 | COMPRESSION_NONE => {
+                entry.length = match compression {
+                    COMPRESSION_NONE => hunk_bytes,
+                    _ => reader.read(length_bits) as usize,
+                };
+
+                entry.offset = current_offset;
+                current_offset += entry.length as u64;
+
+                reader.skip(16);
+            },
+            COMPRESSION_SELF => {
+                last_self = reader.read(self_bits) as u64;
+                entry.offset = last_self;
+            },
+            COMPRESSION_PARENT => {
+                last_parent = reader.read(parent_bits) as u64;
+                entry.offset = last_parent;
+            },
+            COMPRESSION_SELF_0 | COMPRESSION_SELF_1 => {
+                if compression == COMPRESSION_SELF_1 {
+                    last_self += 1;
+                }
+
+                entry.compression = COMPRESSION_SELF;
+                entry.offset = last_self;
+            },
+            COMPRESSION_PARENT_SELF => {
+                last_parent = ((hunk * hunk_bytes) / unit_bytes) as u64;
+
+                entry.compression = COMPRESSION_PARENT;
+                entry.offset = last_parent;
+            },
+            COMPRESSION_PARENT_0 | COMPRESSION_PARENT_1 => {
+                if compression == COMPRESSION_PARENT_1 {
+                    last_parent += (hunk_bytes / unit_bytes) as u64;
+                }
+
+                entry.compression = COMPRESSION_PARENT;
+                entry.offset = last_parent;
+            },
+            _ => return Err(format!("Invalid hunk compression type {}", compression)),
+        };
+
+        map.push(entry);
+    }
+
+    if reader.overflowed() {
+        return Err("Truncated hunk map".to_string());
+    }
+
+    Ok(map)
+}
+
+impl Chd {
+    fn read_map(&mut self, offset: u64, hunks: usize) -> Result<(), String> {
+        if self.compressors[0] == 0 {
+            let mut raw = vec![0; hunks * 4];
+            read_at(&mut self.file, offset, &mut raw)?;
+
+            for entry in raw.chunks(4) {
+                let offset = read_u32(entry) as u64 * self.hunk_bytes as u64;
+
+                // A zero offset is a hunk that was never written
+                self.map.push(MapEntry {
+                    compression: COMPRESSION_NONE,
+                    length: if offset != 0 { self.hunk_bytes } else { 0 },
+                    offset,
+                });
+            }
+
+            return Ok(());
+        }
+
+        let mut header = [0; 16];
+        read_at(&mut self.file, offset, &mut header)?;
+
+        let mut compressed = vec![0; read_u32(&header[0..]) as usize];
+        read_at(&mut self.file, offset + 16, &mut compressed)?;
+
+        self.map = decode_map(&header, &compressed, hunks, self.hunk_bytes, self.unit_bytes)?;
+
+        Ok(())
+    }
+
+    fn read_metadata(&mut self, mut offset: u64) -> Result<Vec<String>, String> {
+        let mut tracks = Vec::new();
+
+        while offset != 0 {
+            let mut header = [0; 16];
+            read_at(&mut self.file, offset, &mut header)?;
+
+            let tag = read_u32(&header[0..]);
+            let length = (read_u32(&header[4..]) & 0xff_ffff) as usize;
+
+            if tag == METADATA_CD_TRACK || tag == METADATA_CD_TRACK2 {
+                let mut data = vec![0; length];
+                read_at(&mut self.file, offset + 16, &mut data)?;
+
+                let text = String::from_utf8_lossy(&data);
+                tracks.push(text.trim_end_matches('\0').to_string());
+            }
+
+            offset = read_u64(&header[8..]);
+        }
+
+        Ok(tracks)
+    }
+
+    fn build(&mut self, metadata: &[String]) -> Result<(), String> {
+        let mut disc_lba = 0;
+        let mut chd_frame = 0;
+
+        for (i, text) in metadata.iter().enumerate() {
+            let field = |name: &str| text.split_whitespace()
+                .find_map(|f| f.strip_prefix(name).and_then(|f| f.strip_prefix(':')))
+                .map(|f| f.to_string());
+
+            let number = |name: &str| match field(name) {
+                Some(value) => value.parse::<usize>().map_err(|_| format!("Invalid {} in track metadata", name)),
+                None => Ok(0),
+            };
+
+            let track = number("TRACK")?;
+
+            if track != i + 1 || track > 99 {
+                return Err(format!("Unexpected track {} in metadata", track));
+            }
+
+            let audio = match field("TYPE").as_deref() {
+                Some("AUDIO") => true,
+                Some("MODE1_RAW") | Some("MODE2_RAW") => false,
+                _ => return Err(format!("Track {}: only 2352 byte sectors are supported", track)),
+            };
+
+            let frames = number("FRAMES")?;
+            let pregap = number("PREGAP")?;
+            let postgap = number("POSTGAP")?;
+
+            // A 'V' pregap type means the pregap is stored in the CHD as
+            // part of the track's frames, otherwise it is silence.
+            let stored = field("PGTYPE").is_some_and(|t| t.starts_with('V'));
+
+            if stored && pregap > frames {
+                return Err(format!("Track {}: pregap is longer than the track", track));
+            }
+
+            if !stored && track != 1 {
+                self.push_region(disc_lba, pregap, None, audio);
+                disc_lba += pregap;
+            }
+
+            self.tracks.push(Track {
+                number: track as u8,
+                audio,
+                start: disc_lba + if stored { pregap } else { 0 },
+            });
+
+            self.push_region(disc_lba, frames, Some(chd_frame), audio);
+            disc_lba += frames;
+            chd_frame += frames.div_ceil(TRACK_PADDING) * TRACK_PADDING;
+
+            self.push_region(disc_lba, postgap, None, audio);
+            disc_lba += postgap;
+        }
+
+        if self.tracks.is_empty() {
+            return Err("No CD track metadata found".to_string());
+        }
+
+        if chd_frame * FRAME_SIZE > self.map.len() * self.hunk_bytes {
+            return Err("Track metadata does not fit the CHD".to_string());
+        }
+
+        self.lead_out = disc_lba;
+
+        Ok(())
+    }
+
+    fn push_region(&mut self, start: usize, length: usize, frame: Option<usize>, audio: bool) {
+        if length != 0 {
+            self.regions.push(Region {
+                start,
+                length,
+                frame,
+                audio,
+            });
+        }
+    }
+
+    fn read_hunk(&mut self, hunk: usize) -> Result<&[u8], String> {
+        if let Some(index) = self.cache.iter().position(|(h, _)| *h == hunk) {
+            let entry = self.cache.remove(index).unwrap();
+            self.cache.push_front(entry);
+
+            return Ok(&self.cache[0].1);
+        }
+
+        let entry = match self.map.get(hunk) {
+            Some(&entry) => entry,
+            None => return Err(format!("Hunk {} is outside of the CHD", hunk)),
+        };
+
+        let mut data = vec![0; self.hunk_bytes];
+
+        match entry.compression {
+            0..=COMPRESSION_TYPE_3 => {
+                let codec = self.compressors[entry.compression as usize];
+
+                let mut compressed = vec![0; entry.length];
+                read_at(&mut self.file, entry.offset, &mut compressed)?;
+
+                codec::decompress(codec, &compressed, &mut data)?;
+            },
+            COMPRESSION_NONE => read_at(&mut self.file, entry.offset, &mut data[..entry.length])?,
+            COMPRESSION_SELF => {
+                if entry.offset as usize >= hunk {
+                    return Err(format!("Hunk {} refers to a later hunk", hunk));
+                }
+
+                data.copy_from_slice(self.read_hunk(entry.offset as usize)?);
+            },
+            _ => return Err("Parent CHDs are not supported".to_string()),
+        };
+
+        self.cache.push_front((hunk, data));
+        self.cache.truncate(CACHE_SIZE);
+
+        Ok(&self.cache[0].1)
+    }
+}
+
+impl Container for Chd {
+    fn open(filepath: &path::Path) -> Result<Box<Self>, String> {
+        let mut file = match fs::File::open(filepath) {
+            Ok(f) => f,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let mut header = [0; HEADER_SIZE];
+        read_at(&mut file, 0, &mut header).map_err(|_| "Invalid CHD header".to_string())?;
+
+        if &header[0..8] != b"MComprHD" {
+            return Err("Invalid CHD header".to_string());
+        }
+
+        let version = read_u32(&header[12..]);
+
+        if version != 5 {
+            return Err(format!("Unsupported CHD version {}", version));
+        }
+
+        if header[104..124].iter().any(|&b| b != 0) {
+            return Err("Parent CHDs are not supported".to_string());
+        }
+
+        let mut compressors = [0; 4];
+
+        for (i, compressor) in compressors.iter_mut().enumerate() {
+            *compressor = read_u32(&header[16 + i * 4..]);
+        }
+
+        let logical_bytes = read_u64(&header[32..]);
+        let map_offset = read_u64(&header[40..]);
+        let metadata_offset = read_u64(&header[48..]);
+        let hunk_bytes = read_u32(&header[56..]) as usize;
+        let unit_bytes = read_u32(&header[60..]) as usize;
+
+        if hunk_bytes == 0 || !hunk_bytes.is_multiple_of(FRAME_SIZE) || unit_bytes != FRAME_SIZE {
+            return Err("CHD is not a CD image".to_string());
+        }
+
+        let mut chd = Self {
+            file,
+
+            compressors,
+            hunk_bytes,
+            unit_bytes,
+            map: Vec::new(),
+
+            cache: VecDeque::with_capacity(CACHE_SIZE),
+
+            regions: Vec::new(),
+            tracks: Vec::new(),
+            lead_out: 0,
+        };
+
+        let hunks = logical_bytes.div_ceil(hunk_bytes as u64) as usize;
+
+        chd.read_map(map_offset, hunks)?;
+
+        let metadata = chd.read_metadata(metadata_offset)?;
+        chd.build(&metadata)?;
+
+        Ok(Box::new(chd))
+    }
+
+    fn read(&mut self, lba: usize, buffer: &mut [u8; 2352]) -> Result<(), String> {
+        let region = match self.regions.iter().find(|r| lba >= r.start && lba < r.start + r.length) {
+            Some(r) => r,
+            None => return Err(format!("LBA {} is outside of the disc", lba)),
+        };
+
+        let audio = region.audio;
+
+        let frame = match region.frame {
+            Some(frame) => frame + lba - region.start,
+            None => {
+                buffer.fill(0);
+                return Ok(());
+            },
+        };
+
+        let frames_per_hunk = self.hunk_bytes / FRAME_SIZE;
+        let offset = (frame % frames_per_hunk) * FRAME_SIZE;
+
+        let hunk = self.read_hunk(frame / frames_per_hunk)?;
+        buffer.copy_from_slice(&hunk[offset..offset + SECTOR_SIZE]);
+
+        // Audio is stored big-endian
+        if audio {
+            for sample in buffer.chunks_mut(2) {
+                sample.swap(0, 1);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn lead_out(&self) -> usize {
+        self.lead_out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_map, COMPRESSION_NONE, COMPRESSION_PARENT, COMPRESSION_SELF};
+
+    const HUNK_BYTES: usize = 8 * 2448;
+    const UNIT_BYTES: usize = 2448;
+
+    struct BitWriter {
+        data: Vec<u8>,
+        bits: usize,
+    }
+
+    impl BitWriter {
+        fn write(&mut self, value: u32, bits: usize) {
+            for i in (0..bits).rev() {
+                if self.bits.is_multiple_of(8) {
+                    self.data.push(0);
+                }
+
+                *self.data.last_mut().unwrap() |= (((value >> i) & 0x1) as u8) << (7 - self.bits % 8);
+                self.bits += 1;
+            }
+        }
+    }
+
+    fn header(first_offset: u64, length_bits: u8, self_bits: u8, parent_bits: u8) -> [u8; 16] {
+        let mut header = [0; 16];
+
+        header[4..10].copy_from_slice(&first_offset.to_be_bytes()[2..]);
+        header[12] = length_bits;
+        header[13] = self_bits;
+        header[14] = parent_bits;
+
+        header
+    }
+
+    // Codes for types 0 (the first codec), NONE and SELF, then RLE_SMALL
+    // and PARENT_SELF which are a bit longer
+    fn tree(writer: &mut BitWriter) {
+        let lengths = [2, 0, 0, 0, 2, 2, 0, 3, 0, 0, 0, 3, 0, 0, 0, 0];
+
+        for length in lengths {
+            writer.write(length, 4);
+        }
+    }
+
+    #[test]
+    fn compressed_map() {
+        let mut writer = BitWriter {
+            data: Vec::new(),
+            bits: 0,
+        };
+
+        tree(&mut writer);
+
+        // Canonical codes are assigned from the longest length down, giving
+        // 7 -> 000, 11 -> 001, 0 -> 01, 4 -> 10 and 5 -> 11. Hunk 0 is the
+        // first codec, the next four NONE with a run of two repeats, then
+        // SELF and PARENT_SELF.
+        writer.write(0b01, 2);
+        writer.write(0b10, 2);
+        writer.write(0b000, 3);
+        writer.write(0b01, 2);
+        writer.write(0b11, 2);
+        writer.write(0b001, 3);
+
+        writer.write(0x123, 12);
+        writer.write(0xffff, 16);
+
+        for _ in 0..4 {
+            writer.write(0xffff, 16);
+        }
+
+        writer.write(1, 8);
+
+        let map = decode_map(&header(0x1000, 12, 8, 0), &writer.data, 7, HUNK_BYTES, UNIT_BYTES).unwrap();
+
+        assert_eq!(map.len(), 7);
+
+        assert_eq!((map[0].compression, map[0].length, map[0].offset), (0, 0x123, 0x1000));
+
+        for (i, entry) in map[1..5].iter().enumerate() {
+            let offset = 0x1123 + (i * HUNK_BYTES) as u64;
+            assert_eq!((entry.compression, entry.length, entry.offset), (COMPRESSION_NONE, HUNK_BYTES, offset));
+        }
+
+        assert_eq!((map[5].compression, map[5].offset), (COMPRESSION_SELF, 1));
+        assert_eq!((map[6].compression, map[6].offset), (COMPRESSION_PARENT, 6 * 8));
+    }
+
+    #[test]
+    fn truncated_map() {
+        let mut writer = BitWriter {
+            data: Vec::new(),
+            bits: 0,
+        };
+
+        tree(&mut writer);
+        writer.write(0b01, 2);
+
+        assert!(decode_map(&header(0x1000, 12, 8, 0), &writer.data, 2, HUNK_BYTES, UNIT_BYTES).is_err());
+    }
+}
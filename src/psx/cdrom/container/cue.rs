@@ -0,0 +1,301 @@
+use std::io::{self, Read, Seek};
+use std::{fs, path};
+
+use super::{Container, Track};
+
+struct CueTrack {
+    number: u8,
+    audio: bool,
+    file: usize,
+    pregap: usize,
+    postgap: usize,
+    index0: Option<usize>,
+    index1: Option<usize>,
+}
+
+// A run of consecutive sectors on the disc, either backed by one of the
+// files or silence inserted by PREGAP/POSTGAP.
+struct Region {
+    start: usize,
+    length: usize,
+    file: Option<usize>,
+    offset: usize,
+}
+
+pub struct Cue {
+    files: Vec<fs::File>,
+    regions: Vec<Region>,
+    tracks: Vec<Track>,
+    lead_out: usize,
+}
+
+fn tokenise(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            tokens.push(chars.by_ref().take_while(|&c| c != '"').collect());
+        } else {
+            let mut token = String::new();
+
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+
+                token.push(c);
+                chars.next();
+            }
+
+            tokens.push(token);
+        }
+    }
+
+    tokens
+}
+
+fn parse_msf(msf: &str) -> Result<usize, String> {
+    let fields: Vec<&str> = msf.split(':').collect();
+
+    if fields.len() != 3 {
+        return Err(format!("Invalid timecode {}", msf));
+    }
+
+    let mut values = [0; 3];
+
+    for (value, field) in values.iter_mut().zip(fields.iter()) {
+        *value = field.parse::<usize>().map_err(|_| format!("Invalid timecode {}", msf))?;
+    }
+
+    let (minute, second, sector) = (values[0], values[1], values[2]);
+
+    if second >= 60 || sector >= 75 {
+        return Err(format!("Invalid timecode {}", msf));
+    }
+
+    Ok((minute * 60 + second) * 75 + sector)
+}
+
+impl Cue {
+    fn parse(filepath: &path::Path, sheet: &str) -> Result<(Vec<path::PathBuf>, Vec<CueTrack>), String> {
+        let directory = filepath.parent().unwrap_or(path::Path::new(""));
+
+        let mut files = Vec::new();
+        let mut tracks: Vec<CueTrack> = Vec::new();
+
+        for (number, line) in sheet.lines().enumerate() {
+            let tokens = tokenise(line);
+
+            let error = |message: &str| format!("Line {}: {}", number + 1, message);
+
+            if tokens.is_empty() {
+                continue;
+            }
+
+            let argument = |index: usize| tokens.get(index).map(|s| s.as_str()).ok_or_else(|| error("Missing argument"));
+
+            match tokens[0].to_ascii_uppercase().as_str() {
+                "FILE" => {
+                    if !argument(2)?.eq_ignore_ascii_case("BINARY") {
+                        return Err(error("Only BINARY files are supported"));
+                    }
+
+                    files.push(directory.join(argument(1)?));
+                },
+                "TRACK" => {
+                    if files.is_empty() {
+                        return Err(error("TRACK before FILE"));
+                    }
+
+                    let track = argument(1)?.parse::<u8>().map_err(|_| error("Invalid track number"))?;
+
+                    let audio = match argument(2)?.to_ascii_uppercase().as_str() {
+                        "AUDIO" => true,
+                        "MODE1/2352" | "MODE2/2352" => false,
+                        _ => return Err(error("Only 2352 byte sectors are supported")),
+                    };
+
+                    if track == 0 || track > 99 || tracks.last().is_some_and(|t| t.number >= track) {
+                        return Err(error("Invalid track number"));
+                    }
+
+                    tracks.push(CueTrack {
+                        number: track,
+                        audio,
+                        file: files.len() - 1,
+                        pregap: 0,
+                        postgap: 0,
+                        index0: None,
+                        index1: None,
+                    });
+                },
+                "INDEX" => {
+                    let track = tracks.last_mut().ok_or_else(|| error("INDEX before TRACK"))?;
+
+                    let index = argument(1)?.parse::<u8>().map_err(|_| error("Invalid index number"))?;
+                    let lba = parse_msf(argument(2)?).map_err(|e| error(&e))?;
+
+                    match index {
+                        0 => track.index0 = Some(lba),
+                        1 => track.index1 = Some(lba),
+                        _ => (),
+                    };
+                },
+                "PREGAP" => {
+                    let track = tracks.last_mut().ok_or_else(|| error("PREGAP before TRACK"))?;
+                    track.pregap = parse_msf(argument(1)?).map_err(|e| error(&e))?;
+                },
+                "POSTGAP" => {
+                    let track = tracks.last_mut().ok_or_else(|| error("POSTGAP before TRACK"))?;
+                    track.postgap = parse_msf(argument(1)?).map_err(|e| error(&e))?;
+                },
+                _ => (),
+            };
+        }
+
+        if tracks.is_empty() {
+            return Err("No tracks found".to_string());
+        }
+
+        for track in tracks.iter() {
+            if track.index1.is_none() {
+                return Err(format!("Track {} has no INDEX 01", track.number));
+            }
+        }
+
+        Ok((files, tracks))
+    }
+
+    fn build(&mut self, cue_tracks: &[CueTrack], sizes: &[usize]) -> Result<(), String> {
+        let mut disc_lba = 0;
+
+        for (file, &size) in sizes.iter().enumerate() {
+            let file_tracks: Vec<&CueTrack> = cue_tracks.iter().filter(|t| t.file == file).collect();
+            let mut file_lba = 0;
+
+            for (i, track) in file_tracks.iter().enumerate() {
+                let index1 = track.index1.unwrap();
+                let index0 = track.index0.unwrap_or(index1);
+
+                let end = match file_tracks.get(i + 1) {
+                    Some(next) => next.index0.unwrap_or(next.index1.unwrap()),
+                    None => size,
+                };
+
+                if index0 > index1 || index1 < file_lba || end < index1 || end > size {
+                    return Err(format!("Track {} does not fit its file", track.number));
+                }
+
+                self.push_region(disc_lba, track.pregap, None, 0);
+                disc_lba += track.pregap;
+
+                self.tracks.push(Track {
+                    number: track.number,
+                    audio: track.audio,
+                    start: disc_lba + (index1 - file_lba),
+                });
+
+                self.push_region(disc_lba, end - file_lba, Some(file), file_lba);
+                disc_lba += end - file_lba;
+                file_lba = end;
+
+                self.push_region(disc_lba, track.postgap, None, 0);
+                disc_lba += track.postgap;
+            }
+        }
+
+        self.lead_out = disc_lba;
+
+        Ok(())
+    }
+
+    fn push_region(&mut self, start: usize, length: usize, file: Option<usize>, offset: usize) {
+        if length != 0 {
+            self.regions.push(Region {
+                start,
+                length,
+                file,
+                offset,
+            });
+        }
+    }
+}
+
+impl Container for Cue {
+    fn open(filepath: &path::Path) -> Result<Box<Self>, String> {
+        let sheet = match fs::read_to_string(filepath) {
+            Ok(s) => s,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let (paths, cue_tracks) = Cue::parse(filepath, &sheet)?;
+
+        let mut files = Vec::new();
+        let mut sizes = Vec::new();
+
+        for path in paths.iter() {
+            let file = match fs::File::open(path) {
+                Ok(f) => f,
+                Err(e) => return Err(format!("{}: {}", path.display(), e)),
+            };
+
+            let size = match file.metadata() {
+                Ok(m) => m.len() as usize,
+                Err(e) => return Err(format!("{}: {}", path.display(), e)),
+            };
+
+            files.push(file);
+            sizes.push(size / 2352);
+        }
+
+        let mut cue = Self {
+            files,
+            regions: Vec::new(),
+            tracks: Vec::new(),
+            lead_out: 0,
+        };
+
+        cue.build(&cue_tracks, &sizes)?;
+
+        Ok(Box::new(cue))
+    }
+
+    fn read(&mut self, lba: usize, buffer: &mut [u8; 2352]) -> Result<(), String> {
+        let region = match self.regions.iter().find(|r| lba >= r.start && lba < r.start + r.length) {
+            Some(r) => r,
+            None => return Err(format!("LBA {} is outside of the disc", lba)),
+        };
+
+        let file = match region.file {
+            Some(file) => &mut self.files[file],
+            None => {
+                buffer.fill(0);
+                return Ok(());
+            },
+        };
+
+        let offset = ((region.offset + lba - region.start) * 2352) as u64;
+
+        if let Err(e) = file.seek(io::SeekFrom::Start(offset)) {
+            return Err(e.to_string());
+        }
+
+        if let Err(e) = file.read_exact(buffer) {
+            return Err(e.to_string());
+        }
+
+        Ok(())
+    }
+
+    fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn lead_out(&self) -> usize {
+        self.lead_out
+    }
+}
@@ -0,0 +1,203 @@
+// EDC/ECC generation for Mode 2 Form 1 sectors (ECMA-130 annex A/B)
+
+pub const SYNC: [u8; 12] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+
+const fn edc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let mut edc = i as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            edc = (edc >> 1) ^ if (edc & 0x1) != 0 { 0xd801_8001 } else { 0 };
+            bit += 1;
+        }
+
+        table[i] = edc;
+        i += 1;
+    }
+
+    table
+}
+
+const fn ecc_tables() -> ([u8; 256], [u8; 256]) {
+    let mut forward = [0; 256];
+    let mut backward = [0; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let j = (i << 1) ^ if (i & 0x80) != 0 { 0x11d } else { 0 };
+
+        forward[i] = j as u8;
+        backward[i ^ j] = i as u8;
+        i += 1;
+    }
+
+    (forward, backward)
+}
+
+static EDC_TABLE: [u32; 256] = edc_table();
+static ECC_TABLES: ([u8; 256], [u8; 256]) = ecc_tables();
+
+fn edc(data: &[u8]) -> u32 {
+    data.iter().fold(0, |edc, &byte| {
+        (edc >> 8) ^ EDC_TABLE[((edc ^ byte as u32) & 0xff) as usize]
+    })
+}
+
+fn ecc(src: &[u8], major_count: usize, minor_count: usize, major_mult: usize, minor_inc: usize, dest: &mut [u8]) {
+    let (forward, backward) = &ECC_TABLES;
+    let size = major_count * minor_count;
+
+    for major in 0..major_count {
+        let mut index = (major >> 1) * major_mult + (major & 0x1);
+
+        let mut ecc_a = 0;
+        let mut ecc_b = 0;
+
+        for _ in 0..minor_count {
+            let value = src[index];
+
+            index += minor_inc;
+
+            if index >= size {
+                index -= size;
+            }
+
+            ecc_a ^= value;
+            ecc_b ^= value;
+            ecc_a = forward[ecc_a as usize];
+        }
+
+        ecc_a = backward[(forward[ecc_a as usize] ^ ecc_b) as usize];
+
+        dest[major] = ecc_a;
+        dest[major + major_count] = ecc_a ^ ecc_b;
+    }
+}
+
+pub fn generate_ecc(sector: &mut [u8; 2352]) {
+    let (src, dest) = sector.split_at_mut(0x81c);
+    ecc(&src[0xc..], 86, 24, 2, 86, &mut dest[..0xac]);
+
+    let (src, dest) = sector.split_at_mut(0x8c8);
+    ecc(&src[0xc..], 52, 43, 86, 88, &mut dest[..0x68]);
+}
+
+pub fn generate_mode2_form1(sector: &mut [u8; 2352]) {
+    let edc = edc(&sector[0x10..0x818]);
+    sector[0x818..0x81c].copy_from_slice(&edc.to_le_bytes());
+
+    // The header is not covered by ECC in Mode 2
+    let mut header = [0; 4];
+    header.copy_from_slice(&sector[0xc..0x10]);
+    sector[0xc..0x10].fill(0);
+
+    generate_ecc(sector);
+
+    sector[0xc..0x10].copy_from_slice(&header);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edc, generate_mode2_form1, SYNC};
+
+    fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+        let mut product = 0;
+
+        while b != 0 {
+            if (b & 0x1) != 0 {
+                product ^= a;
+            }
+
+            a = (((a as u16) << 1) ^ if (a & 0x80) != 0 { 0x11d } else { 0 }) as u8;
+            b >>= 1;
+        }
+
+        product
+    }
+
+    // Both syndromes of a Reed-Solomon codeword ending in its two parity
+    // bytes, which are zero when the parity is right
+    fn syndromes(codeword: &[u8]) -> (u8, u8) {
+        codeword.iter().fold((0, 0), |(s0, s1), &symbol| (s0 ^ symbol, gf_mul(s1, 2) ^ symbol))
+    }
+
+    fn sector() -> [u8; 2352] {
+        let mut sector = [0; 2352];
+
+        sector[..0xc].copy_from_slice(&SYNC);
+        sector[0xc..0x10].copy_from_slice(&[0x00, 0x02, 0x16, 0x02]);
+        sector[0x10..0x18].copy_from_slice(&[0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x08, 0x00]);
+
+        // Arbitrary but fixed user data
+        let mut seed = 0x1234_5678u32;
+
+        for byte in sector[0x18..0x818].iter_mut() {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            *byte = (seed >> 16) as u8;
+        }
+
+        sector
+    }
+
+    #[test]
+    fn edc_check_value() {
+        // CRC-32/CD-ROM-EDC from the CRC catalogue
+        assert_eq!(edc(b"123456789"), 0x6ec2_edc4);
+    }
+
+    #[test]
+    fn mode2_form1_sector() {
+        let mut sector = sector();
+        generate_mode2_form1(&mut sector);
+
+        // The header is restored, not zeroed, after ECC generation
+        assert_eq!(&sector[0xc..0x10], &[0x00, 0x02, 0x16, 0x02]);
+
+        let stored = u32::from_le_bytes([sector[0x818], sector[0x819], sector[0x81a], sector[0x81b]]);
+        assert_eq!(stored, edc(&sector[0x10..0x818]));
+
+        // ECC is computed with the header zeroed in Mode 2
+        let mut data = sector;
+        data[0xc..0x10].fill(0);
+        let data = &data[0xc..];
+
+        // P parity covers 86 columns of 24 bytes, 86 apart
+        for column in 0..86 {
+            let codeword: Vec<u8> = (0..26).map(|row| data[column + row * 86]).collect();
+            assert_eq!(syndromes(&codeword), (0, 0), "P column {}", column);
+        }
+
+        // Q parity covers 52 diagonals of 43 bytes, stepping 88 bytes
+        for diagonal in 0..52 {
+            let mut index = (diagonal >> 1) * 86 + (diagonal & 0x1);
+            let mut codeword = Vec::new();
+
+            for _ in 0..43 {
+                codeword.push(data[index]);
+                index = (index + 88) % (52 * 43);
+            }
+
+            codeword.push(data[52 * 43 + diagonal]);
+            codeword.push(data[52 * 43 + 52 + diagonal]);
+
+            assert_eq!(syndromes(&codeword), (0, 0), "Q diagonal {}", diagonal);
+        }
+    }
+
+    #[test]
+    fn corrupted_sector_fails_parity() {
+        let mut sector = sector();
+        generate_mode2_form1(&mut sector);
+
+        sector[0x100] ^= 0x01;
+
+        let column = (0x100 - 0xc) % 86;
+        let codeword: Vec<u8> = (0..26).map(|row| sector[0xc + column + row * 86]).collect();
+
+        assert_ne!(syndromes(&codeword), (0, 0));
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::{self, Read, Seek};
+use std::{fs, path};
+
+use super::super::helpers::u8_to_bcd;
+use super::{ecc, Container, Track};
+
+// Plain 2048 byte user data images. Every sector is presented as a
+// Mode 2 Form 1 data sector, which is what the PlayStation expects.
+pub struct Iso {
+    file: fs::File,
+    tracks: [Track; 1],
+    lead_out: usize,
+}
+
+impl Container for Iso {
+    fn open(filepath: &path::Path) -> Result<Box<Self>, String> {
+        let file = match fs::File::open(filepath) {
+            Ok(f) => f,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let size = match file.metadata() {
+            Ok(m) => m.len() as usize,
+            Err(e) => return Err(e.to_string()),
+        };
+
+        if size % 2048 != 0 {
+            return Err("ISO size is not a multiple of 2048 bytes".to_string());
+        }
+
+        let track = Track {
+            number: 1,
+            audio: false,
+            start: 0,
+        };
+
+        Ok(Box::new(Self {
+            file,
+            tracks: [track],
+            lead_out: size / 2048,
+        }))
+    }
+
+    fn read(&mut self, lba: usize, buffer: &mut [u8; 2352]) -> Result<(), String> {
+        if lba >= self.lead_out {
+            return Err(format!("LBA {} is outside of the disc", lba));
+        }
+
+        let offset = (lba * 2048) as u64;
+
+        if let Err(e) = self.file.seek(io::SeekFrom::Start(offset)) {
+            return Err(e.to_string());
+        }
+
+        if let Err(e) = self.file.read_exact(&mut buffer[0x18..0x818]) {
+            return Err(e.to_string());
+        }
+
+        let msf = lba + 150;
+
+        buffer[..0xc].copy_from_slice(&ecc::SYNC);
+        buffer[0xc] = u8_to_bcd((msf / 75 / 60) as u8);
+        buffer[0xd] = u8_to_bcd((msf / 75 % 60) as u8);
+        buffer[0xe] = u8_to_bcd((msf % 75) as u8);
+        buffer[0xf] = 2;
+
+        // Subheader: file 0, channel 0, data submode, repeated twice
+        buffer[0x10..0x18].copy_from_slice(&[0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x08, 0x00]);
+
+        ecc::generate_mode2_form1(buffer);
+
+        Ok(())
+    }
+
+    fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn lead_out(&self) -> usize {
+        self.lead_out
+    }
+}
\ No newline at end of file
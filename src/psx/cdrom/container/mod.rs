@@ -1,12 +1,74 @@
 mod bin;
+mod chd;
+mod cue;
+mod ecc;
+mod iso;
 mod no_disk;
 
-use std::path;
+use std::{fs, path};
 
 pub use bin::Bin;
+pub use chd::Chd;
+pub use cue::Cue;
+pub use iso::Iso;
 pub use no_disk::NoDisk;
 
+#[derive(Clone, Copy)]
+pub struct Track {
+    pub number: u8,
+    pub audio: bool,
+    pub start: usize,
+}
+
 pub trait Container {
-    fn open(filepath: &path::Path) -> Result<Box<Self>, String>;
+    fn open(filepath: &path::Path) -> Result<Box<Self>, String> where Self: Sized;
     fn read(&mut self, lba: usize, buffer: &mut [u8; 2352]) -> Result<(), String>;
+
+    fn tracks(&self) -> &[Track];
+    fn lead_out(&self) -> usize;
+}
+
+pub fn open(filepath: &path::Path) -> Result<Box<dyn Container>, String> {
+    let extension = filepath.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("chd") => Ok(Chd::open(filepath)?),
+        Some("cue") => Ok(Cue::open(filepath)?),
+        Some("iso") => Ok(Iso::open(filepath)?),
+        _ => Ok(Bin::open(filepath)?),
+    }
+}
+
+// Multi-disc games are described by an M3U playlist with one image per line,
+// anything else is treated as a single disc.
+pub fn playlist(filepath: &path::Path) -> Result<Vec<path::PathBuf>, String> {
+    let extension = filepath.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+
+    if extension.as_deref() != Some("m3u") {
+        return Ok(vec![filepath.to_path_buf()]);
+    }
+
+    let text = match fs::read_to_string(filepath) {
+        Ok(t) => t,
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let directory = filepath.parent().unwrap_or(path::Path::new(""));
+
+    let discs: Vec<path::PathBuf> = text.trim_start_matches('\u{feff}')
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| directory.join(l))
+        .collect();
+
+    if discs.is_empty() {
+        return Err("Playlist does not contain any discs".to_string());
+    }
+
+    Ok(discs)
 }
\ No newline at end of file
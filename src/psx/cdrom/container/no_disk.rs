@@ -1,6 +1,6 @@
 use std::path;
 
-use super::Container;
+use super::{Container, Track};
 
 pub struct NoDisk;
 
@@ -10,8 +10,16 @@ impl Container for NoDisk {
     }
 
     fn read(&mut self, _: usize, buffer: &mut [u8; 2352]) -> Result<(), String> {
-        for i in 0..buffer.len() { buffer[i] = 0; }
+        buffer.fill(0);
 
         Err("No disk inserted".to_string())
     }
+
+    fn tracks(&self) -> &[Track] {
+        &[]
+    }
+
+    fn lead_out(&self) -> usize {
+        0
+    }
 }
\ No newline at end of file
@@ -0,0 +1,448 @@
+use std::path::Path;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use super::container::{self, Container};
+use super::{BYTES_PER_SECTOR, DATA_OFFSET};
+
+pub const SECTOR_SIZE: usize = 2048;
+
+// Mode 2 Form 2 sectors as extracted: subheader, 2324 bytes of data and EDC
+pub const RAW_SECTOR_SIZE: usize = 2336;
+
+const PVD_LBA: usize = 16;
+
+// Deep enough for any real disc, and stops directory loops on broken ones
+const MAX_DEPTH: usize = 16;
+
+#[derive(Clone, Copy, Debug)]
+pub struct XaAttributes {
+    pub attributes: u16,
+    pub file_number: u8,
+}
+
+impl XaAttributes {
+    pub fn form1(&self) -> bool {
+        (self.attributes & 0x0800) != 0
+    }
+
+    pub fn form2(&self) -> bool {
+        (self.attributes & 0x1000) != 0
+    }
+
+    pub fn interleaved(&self) -> bool {
+        (self.attributes & 0x2000) != 0
+    }
+
+    pub fn cdda(&self) -> bool {
+        (self.attributes & 0x4000) != 0
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DirectoryEntry {
+    pub name: String,
+    pub lba: usize,
+    pub size: usize,
+    pub directory: bool,
+    pub xa: Option<XaAttributes>,
+}
+
+impl DirectoryEntry {
+    // Streams (video, XA audio) can only be extracted as whole raw sectors
+    pub fn raw(&self) -> bool {
+        self.xa.is_some_and(|xa| xa.form2() || xa.interleaved())
+    }
+
+    pub fn sectors(&self) -> usize {
+        self.size.div_ceil(SECTOR_SIZE)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Volume {
+    pub system_id: String,
+    pub volume_id: String,
+    pub sectors: usize,
+    pub root: DirectoryEntry,
+}
+
+fn read_raw_sector(disc: &mut dyn Container, lba: usize) -> Result<[u8; BYTES_PER_SECTOR], String> {
+    if lba >= disc.lead_out() {
+        return Err(format!("sector {} is outside of the disc", lba));
+    }
+
+    let mut sector = [0u8; BYTES_PER_SECTOR];
+    disc.read(lba, &mut sector)?;
+
+    Ok(sector)
+}
+
+// Reads the 2048 bytes of user data in a data sector
+pub fn read_sector(disc: &mut dyn Container, lba: usize, data: &mut [u8; SECTOR_SIZE]) -> Result<(), String> {
+    let sector = read_raw_sector(disc, lba)?;
+
+    let offset = match sector[15] {
+        1 => 16,
+        _ => DATA_OFFSET,
+    };
+
+    data.copy_from_slice(&sector[offset..offset + SECTOR_SIZE]);
+    Ok(())
+}
+
+fn identifier(data: &[u8]) -> String {
+    String::from_utf8_lossy(data).trim_end_matches([' ', '\0']).to_string()
+}
+
+pub fn read_volume(disc: &mut dyn Container) -> Result<Volume, String> {
+    let mut pvd = [0u8; SECTOR_SIZE];
+    read_sector(disc, PVD_LBA, &mut pvd)?;
+
+    if pvd[0] != 0x1 || &pvd[1..6] != b"CD001" {
+        return Err("no ISO9660 primary volume descriptor".to_string());
+    }
+
+    let block_size = LittleEndian::read_u16(&pvd[128..]) as usize;
+
+    if block_size != SECTOR_SIZE {
+        return Err(format!("unsupported logical block size {}", block_size));
+    }
+
+    let root = parse_record(&pvd[156..190])
+        .filter(|root| root.directory)
+        .ok_or_else(|| "invalid root directory record".to_string())?;
+
+    Ok(Volume {
+        system_id: identifier(&pvd[8..40]),
+        volume_id: identifier(&pvd[40..72]),
+        sectors: LittleEndian::read_u32(&pvd[80..]) as usize,
+        root,
+    })
+}
+
+// Directory records: 00 length, 02 extent, 0a size, 19 flags, 20 name length,
+// 21 name, then the system use area which holds the XA attributes
+fn parse_record(record: &[u8]) -> Option<DirectoryEntry> {
+    let length = *record.first()? as usize;
+    let name_length = *record.get(32)? as usize;
+
+    // Names are padded to an even length before the system use area
+    let system_use_start = (33 + name_length + 1) & !0x1;
+
+    if record.len() < length || length < system_use_start {
+        return None;
+    }
+
+    let system_use = &record[system_use_start..length];
+
+    let xa = match system_use.len() >= 14 && &system_use[6..8] == b"XA" {
+        true => Some(XaAttributes {
+            attributes: BigEndian::read_u16(&system_use[4..]),
+            file_number: system_use[8],
+        }),
+        false => None,
+    };
+
+    Some(DirectoryEntry {
+        name: String::from_utf8_lossy(&record[33..33 + name_length]).to_string(),
+        lba: LittleEndian::read_u32(&record[2..]) as usize,
+        size: LittleEndian::read_u32(&record[10..]) as usize,
+        directory: (record[25] & 0x2) != 0,
+        xa,
+    })
+}
+
+pub fn read_directory(disc: &mut dyn Container, directory: &DirectoryEntry) -> Result<Vec<DirectoryEntry>, String> {
+    if !directory.directory {
+        return Err(format!("{} is not a directory", directory.name));
+    }
+
+    let mut entries = Vec::new();
+    let mut data = [0u8; SECTOR_SIZE];
+
+    for i in 0..directory.sectors() {
+        read_sector(disc, directory.lba + i, &mut data)?;
+
+        let mut offset = 0;
+
+        // Records never cross sector boundaries, a zero length pads to the next
+        while offset < SECTOR_SIZE && data[offset] != 0 {
+            let length = data[offset] as usize;
+
+            let entry = parse_record(&data[offset..(offset + length).min(SECTOR_SIZE)])
+                .ok_or_else(|| format!("invalid directory record in sector {}", directory.lba + i))?;
+
+            // The first two records are the directory itself and its parent
+            if entry.name != "\0" && entry.name != "\u{1}" {
+                entries.push(entry);
+            }
+
+            offset += length;
+        }
+    }
+
+    Ok(entries)
+}
+
+// Every file and directory below the given one with its path
+pub fn walk(disc: &mut dyn Container, directory: &DirectoryEntry) -> Result<Vec<(String, DirectoryEntry)>, String> {
+    let mut entries = Vec::new();
+    walk_directory(disc, directory, "", 0, &mut entries)?;
+
+    Ok(entries)
+}
+
+fn walk_directory(disc: &mut dyn Container,
+                  directory: &DirectoryEntry,
+                  prefix: &str,
+                  depth: usize,
+                  entries: &mut Vec<(String, DirectoryEntry)>) -> Result<(), String> {
+    if depth >= MAX_DEPTH {
+        return Err(format!("directories nested deeper than {} levels", MAX_DEPTH));
+    }
+
+    for entry in read_directory(disc, directory)? {
+        let path = format!("{}\\{}", prefix, entry.name);
+
+        if entry.directory {
+            entries.push((path.clone(), entry.clone()));
+            walk_directory(disc, &entry, &path, depth + 1, entries)?;
+        } else {
+            entries.push((path, entry));
+        }
+    }
+
+    Ok(())
+}
+
+// Names are compared without case, and the ;1 version suffix is optional
+fn name_matches(entry: &str, name: &str) -> bool {
+    let strip = |s: &str| s.split(';').next().unwrap_or("").trim_end_matches('.').to_ascii_uppercase();
+
+    strip(entry) == strip(name)
+}
+
+// Paths look like cdrom:\DIR\FILE.EXE;1, the device prefix is optional
+pub fn find(disc: &mut dyn Container, path: &str) -> Result<DirectoryEntry, String> {
+    let path = path.strip_prefix("cdrom:").unwrap_or(path);
+
+    let mut entry = read_volume(disc)?.root;
+
+    for component in path.split(['\\', '/']).filter(|c| !c.is_empty()) {
+        entry = read_directory(disc, &entry)?
+            .into_iter()
+            .find(|e| name_matches(&e.name, component))
+            .ok_or_else(|| format!("{} not found", path))?;
+    }
+
+    Ok(entry)
+}
+
+// The size comes from the directory record, so check it before allocating
+fn check_extent(disc: &dyn Container, entry: &DirectoryEntry) -> Result<(), String> {
+    match entry.lba.checked_add(entry.sectors()) {
+        Some(end) if end <= disc.lead_out() => Ok(()),
+        _ => Err(format!("{} extends past the end of the disc", entry.name)),
+    }
+}
+
+pub fn read_file(disc: &mut dyn Container, entry: &DirectoryEntry) -> Result<Vec<u8>, String> {
+    if entry.directory {
+        return Err(format!("{} is a directory", entry.name));
+    }
+
+    check_extent(disc, entry)?;
+
+    let mut contents = Vec::with_capacity(entry.size);
+    let mut data = [0u8; SECTOR_SIZE];
+
+    for i in 0..entry.sectors() {
+        read_sector(disc, entry.lba + i, &mut data)?;
+
+        let length = (entry.size - i * SECTOR_SIZE).min(SECTOR_SIZE);
+        contents.extend_from_slice(&data[..length]);
+    }
+
+    Ok(contents)
+}
+
+// Whole Mode 2 sectors after the header, the way streams are usually dumped
+pub fn read_raw_file(disc: &mut dyn Container, entry: &DirectoryEntry) -> Result<Vec<u8>, String> {
+    check_extent(disc, entry)?;
+
+    let mut contents = Vec::with_capacity(entry.sectors() * RAW_SECTOR_SIZE);
+
+    for i in 0..entry.sectors() {
+        let sector = read_raw_sector(disc, entry.lba + i)?;
+        contents.extend_from_slice(&sector[16..16 + RAW_SECTOR_SIZE]);
+    }
+
+    Ok(contents)
+}
+
+// A disc image opened for reading its file system outside of the emulator
+pub struct Iso9660 {
+    disc: Box<dyn Container>,
+    volume: Volume,
+}
+
+impl Iso9660 {
+    pub fn open(filepath: &Path) -> Result<Iso9660, String> {
+        let mut disc = container::open(filepath)?;
+        let volume = read_volume(disc.as_mut())?;
+
+        Ok(Iso9660 {
+            disc,
+            volume,
+        })
+    }
+
+    pub fn volume(&self) -> &Volume {
+        &self.volume
+    }
+
+    pub fn find(&mut self, path: &str) -> Result<DirectoryEntry, String> {
+        find(self.disc.as_mut(), path)
+    }
+
+    pub fn list(&mut self, directory: &DirectoryEntry) -> Result<Vec<DirectoryEntry>, String> {
+        read_directory(self.disc.as_mut(), directory)
+    }
+
+    pub fn walk(&mut self, directory: &DirectoryEntry) -> Result<Vec<(String, DirectoryEntry)>, String> {
+        walk(self.disc.as_mut(), directory)
+    }
+
+    pub fn read_file(&mut self, entry: &DirectoryEntry) -> Result<Vec<u8>, String> {
+        match entry.raw() {
+            true => read_raw_file(self.disc.as_mut(), entry),
+            false => read_file(self.disc.as_mut(), entry),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::container::NoDisk;
+    use super::{parse_record, read_file, read_raw_file, DirectoryEntry};
+
+    fn record(name: &[u8], flags: u8, system_use: &[u8]) -> Vec<u8> {
+        let mut record = vec![0u8; 33];
+
+        record[2..6].copy_from_slice(&0x1234u32.to_le_bytes());
+        record[10..14].copy_from_slice(&5000u32.to_le_bytes());
+        record[25] = flags;
+        record[32] = name.len() as u8;
+        record.extend_from_slice(name);
+
+        if !record.len().is_multiple_of(2) {
+            record.push(0);
+        }
+
+        record.extend_from_slice(system_use);
+        record[0] = record.len() as u8;
+        record
+    }
+
+    fn xa(attributes: u16, file_number: u8) -> Vec<u8> {
+        let mut system_use = vec![0u8; 14];
+
+        system_use[4..6].copy_from_slice(&attributes.to_be_bytes());
+        system_use[6..8].copy_from_slice(b"XA");
+        system_use[8] = file_number;
+        system_use
+    }
+
+    #[test]
+    fn file_record() {
+        let entry = parse_record(&record(b"SLUS_123.45;1", 0, &[])).unwrap();
+
+        assert_eq!(entry.name, "SLUS_123.45;1");
+        assert_eq!(entry.lba, 0x1234);
+        assert_eq!(entry.size, 5000);
+        assert_eq!(entry.sectors(), 3);
+        assert!(!entry.directory);
+        assert!(entry.xa.is_none());
+    }
+
+    #[test]
+    fn directory_record() {
+        let entry = parse_record(&record(b"\0", 0x2, &[])).unwrap();
+
+        assert_eq!(entry.name, "\0");
+        assert!(entry.directory);
+    }
+
+    #[test]
+    fn xa_attributes() {
+        let entry = parse_record(&record(b"MOVIE.STR;1", 0, &xa(0x3555, 1))).unwrap();
+        let attributes = entry.xa.unwrap();
+
+        assert_eq!(attributes.file_number, 1);
+        assert!(attributes.form2());
+        assert!(attributes.interleaved());
+        assert!(!attributes.form1());
+        assert!(!attributes.cdda());
+        assert!(entry.raw());
+
+        let entry = parse_record(&record(b"DATA.BIN;1", 0, &xa(0x0d55, 0))).unwrap();
+
+        assert!(entry.xa.unwrap().form1());
+        assert!(!entry.raw());
+    }
+
+    #[test]
+    fn short_or_foreign_system_use_is_not_xa() {
+        let entry = parse_record(&record(b"A;1", 0, &xa(0x0d55, 0)[..10])).unwrap();
+        assert!(entry.xa.is_none());
+
+        let mut system_use = xa(0x0d55, 0);
+        system_use[6..8].copy_from_slice(b"RR");
+
+        let entry = parse_record(&record(b"A;1", 0, &system_use)).unwrap();
+        assert!(entry.xa.is_none());
+    }
+
+    #[test]
+    fn even_name_without_padding() {
+        let mut data = record(b"AB", 0, &[]);
+
+        data.pop();
+        data[0] = data.len() as u8;
+
+        assert!(parse_record(&data).is_none());
+    }
+
+    #[test]
+    fn truncated_records() {
+        let data = record(b"FILE.TXT;1", 0, &[]);
+
+        assert!(parse_record(&[]).is_none());
+        assert!(parse_record(&data[..20]).is_none());
+        assert!(parse_record(&data[..data.len() - 1]).is_none());
+
+        let mut data = data;
+        data[0] = 40;
+
+        assert!(parse_record(&data).is_none());
+    }
+
+    #[test]
+    fn file_past_lead_out() {
+        let mut entry = DirectoryEntry {
+            name: "HUGE.BIN;1".to_string(),
+            lba: 0,
+            size: 0xffff_f800,
+            directory: false,
+            xa: None,
+        };
+
+        assert!(read_file(&mut NoDisk, &entry).is_err());
+        assert!(read_raw_file(&mut NoDisk, &entry).is_err());
+
+        entry.size = 0;
+
+        assert_eq!(read_file(&mut NoDisk, &entry).unwrap().len(), 0);
+    }
+}
@@ -1,28 +1,47 @@
 mod container;
 mod headers;
 mod helpers;
+mod iso9660;
+mod system_cnf;
 mod timecode;
 
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
-use std::path::Path;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
 
+use byteorder::{ByteOrder, LittleEndian};
 use serde::{Deserialize, Serialize};
 
+use container::{Bin, Container, NoDisk, Track};
+pub use iso9660::{DirectoryEntry, Iso9660, Volume, XaAttributes, SECTOR_SIZE};
+pub use system_cnf::SystemConfig;
 use timecode::Timecode;
 
 use crate::psx::adpcm::{ADPCM_FILTERS, ADPCM_ZIGZAG_TABLE};
 use crate::queue::Queue;
-use crate::util::{bcd_to_u8, clip, u8_to_bcd};
+use crate::util::{self, bcd_to_u8, clip, u8_to_bcd};
 
 use super::intc::{Intc, Interrupt};
+use super::region::Region;
 use super::spu::Spu;
 
-pub const SECTORS_PER_SECOND: u64 = 75;
-pub const SECTORS_PER_MINUTE: u64 = 60 * SECTORS_PER_SECOND;
-pub const BYTES_PER_SECTOR: u64 = 2352;
-pub const SECTOR_HEADER_SIZE: u64 = 24;
-pub const LEAD_IN_SECTORS: u64 = 2 * SECTORS_PER_SECOND;
+pub const SECTORS_PER_SECOND: usize = 75;
+pub const SECTORS_PER_MINUTE: usize = 60 * SECTORS_PER_SECOND;
+pub const BYTES_PER_SECTOR: usize = 2352;
+pub const LEAD_IN_SECTORS: usize = 2 * SECTORS_PER_SECOND;
+
+pub const SCAN_SECTORS: usize = 8;
+
+// Sector holding the licence text the drive checks the disc region against
+pub const LICENCE_LBA: usize = 4;
+
+pub const NO_DISC_ID: &str = "NODISC";
+pub const UNKNOWN_DISC_ID: &str = "UNKNOWN";
+
+// How long the lid stays open when changing discs, and how long the drive
+// takes to spin up and read the TOC after it is closed.
+pub const SHELL_OPEN_CLOCKS: isize = 44100;
+pub const TOC_READ_CLOCKS: isize = 44100;
 
 pub const ADDRESS_OFFSET: usize = 12;
 pub const DATA_OFFSET: usize = 24;
@@ -165,6 +184,32 @@ enum CdromIndex {
     Index3,
 }
 
+#[derive(Clone, Copy, Deserialize, Serialize)]
+struct CdromVolume {
+    left_to_left: u8,
+    left_to_right: u8,
+    right_to_right: u8,
+    right_to_left: u8,
+}
+
+impl CdromVolume {
+    pub fn new() -> Self {
+        Self {
+            left_to_left: 0x80,
+            left_to_right: 0,
+            right_to_right: 0x80,
+            right_to_left: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
+enum CdromScan {
+    Normal,
+    Forward,
+    Backward,
+}
+
 #[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
 enum CdromControllerMode {
     Idle,
@@ -200,7 +245,7 @@ enum CdromSectorMode {
 }
 
 #[allow(dead_code)]
-static COMMAND_NAMES: [&'static str; 32] = [
+static COMMAND_NAMES: [&str; 32] = [
     "CdlSync",
     "CdlNop",
     "CdlSetloc",
@@ -277,6 +322,17 @@ pub struct Cdrom {
     mode_sector_size: bool,
     mode_filter: bool,
     mode_report: bool,
+    mode_autopause: bool,
+
+    mute: bool,
+    adpcm_mute: bool,
+
+    volume: CdromVolume,
+    pending_volume: CdromVolume,
+
+    scan: CdromScan,
+    play_track: u8,
+    report_right: bool,
 
     controller_counter: isize,
     controller_mode: CdromControllerMode,
@@ -308,21 +364,59 @@ pub struct Cdrom {
 
     last_subq: CdromSubchannelQ,
 
+    shell_open: bool,
+    shell_opened: bool,
+    shell_counter: isize,
+    toc_counter: isize,
+
+    #[serde(skip, default = "Cdrom::no_disk")]
+    disc: Box<dyn Container>,
     #[serde(skip)]
-    game_file: Option<File>,
+    discs: Vec<PathBuf>,
+    disc_index: usize,
+    #[serde(skip)]
+    disc_id: Option<String>,
+    // Outer None until detected, as discs without a region are common
+    #[serde(skip)]
+    disc_region: Option<Option<Region>>,
+
+    console_region: Region,
 
     sixstep: usize,
     ringbuf: [[i16; 0x20]; 2],
 }
 
 impl Cdrom {
-    pub fn new(game_filepath: &str) -> Cdrom {
-        let path = Path::new(game_filepath);
+    pub fn new(game_filepath: &str) -> io::Result<Cdrom> {
+        let discs = container::playlist(Path::new(game_filepath))
+            .map_err(|e| io::Error::other(format!("unable to open {}: {}", game_filepath, e)))?;
+
+        let disc = container::open(&discs[0])
+            .map_err(|e| io::Error::other(format!("unable to open {}: {}", discs[0].display(), e)))?;
 
-        if !path.is_file() {
-            panic!("ERROR: file does not exist: {}", path.display())
+        let mut cdrom = Cdrom::with_disc(disc);
+        cdrom.discs = discs;
+
+        Ok(cdrom)
+    }
+
+    pub fn from_bytes(game: Vec<u8>) -> Cdrom {
+        if game.is_empty() {
+            return Cdrom::empty();
         }
 
+        Cdrom::with_disc(Bin::from_bytes(game))
+    }
+
+    pub fn empty() -> Cdrom {
+        Cdrom::with_disc(Cdrom::no_disk())
+    }
+
+    fn no_disk() -> Box<dyn Container> {
+        Box::new(NoDisk)
+    }
+
+    fn with_disc(disc: Box<dyn Container>) -> Cdrom {
         Cdrom {
             index: CdromIndex::Index0,
 
@@ -364,6 +458,17 @@ impl Cdrom {
             mode_sector_size: false,
             mode_filter: false,
             mode_report: false,
+            mode_autopause: false,
+
+            mute: false,
+            adpcm_mute: false,
+
+            volume: CdromVolume::new(),
+            pending_volume: CdromVolume::new(),
+
+            scan: CdromScan::Normal,
+            play_track: 0,
+            report_right: false,
 
             controller_counter: 0,
             controller_mode: CdromControllerMode::Idle,
@@ -395,7 +500,18 @@ impl Cdrom {
 
             last_subq: CdromSubchannelQ::new(),
 
-            game_file: Some(File::open(path).unwrap()),
+            shell_open: false,
+            shell_opened: false,
+            shell_counter: 0,
+            toc_counter: 0,
+
+            disc,
+            discs: Vec::new(),
+            disc_index: 0,
+            disc_id: None,
+            disc_region: None,
+
+            console_region: Region::NorthAmerica,
 
             sixstep: 0,
             ringbuf: [[0; 0x20]; 2],
@@ -405,46 +521,211 @@ impl Cdrom {
     pub fn reset(&mut self) {
     }
 
-    pub fn load_disc(&mut self, filepath: &str) {
-        let path = Path::new(filepath);
+    // Takes the playlist and open disc from another controller, typically
+    // the one that was running before a state was loaded. If the state was
+    // saved with a different disc inserted, that disc is opened instead.
+    pub fn take_discs(&mut self, other: &mut Cdrom) -> Result<(), String> {
+        mem::swap(&mut self.discs, &mut other.discs);
+
+        if self.disc_index == other.disc_index {
+            mem::swap(&mut self.disc, &mut other.disc);
+            mem::swap(&mut self.disc_id, &mut other.disc_id);
+            mem::swap(&mut self.disc_region, &mut other.disc_region);
+            return Ok(());
+        }
 
-        if !path.is_file() {
-            panic!("ERROR: file does not exist: {}", path.display())
+        if let Err(e) = self.load_disc(self.disc_index) {
+            mem::swap(&mut self.discs, &mut other.discs);
+            return Err(e);
         }
 
-        self.game_file = Some(File::open(path).unwrap());
+        Ok(())
     }
 
-    // TODO: It's stupid to read this every time,
-    // make it an Option<String> and invalidate if the disc is changed.
-    pub fn get_disc_id(&mut self) -> String {
-        if self.game_file.is_none() {
-            return String::from("NODISC");
+    pub fn disc_count(&self) -> usize {
+        self.discs.len()
+    }
+
+    pub fn disc_index(&self) -> usize {
+        self.disc_index
+    }
+
+    pub fn load_disc(&mut self, index: usize) -> Result<(), String> {
+        let path = match self.discs.get(index) {
+            Some(path) => path,
+            None => return Err(format!("No disc {} in playlist", index + 1)),
+        };
+
+        self.disc = container::open(path)
+            .map_err(|e| format!("unable to open {}: {}", path.display(), e))?;
+        self.disc_index = index;
+        self.disc_id = None;
+        self.disc_region = None;
+
+        Ok(())
+    }
+
+    // Opens the lid, swaps in another disc from the playlist and closes the
+    // lid again once SHELL_OPEN_CLOCKS have passed. The disc is opened
+    // first so a failure leaves the drive as it was.
+    pub fn change_disc(&mut self, index: usize) -> Result<(), String> {
+        self.load_disc(index)?;
+
+        self.open_shell();
+        self.shell_counter = SHELL_OPEN_CLOCKS;
+
+        Ok(())
+    }
+
+    pub fn open_shell(&mut self) {
+        self.shell_open = true;
+        self.shell_opened = true;
+        self.shell_counter = 0;
+        self.toc_counter = 0;
+    }
+
+    pub fn close_shell(&mut self) {
+        if !self.shell_open {
+            return;
         }
 
-        let mut pvd = [0u8; 2048];
-        let file = self.game_file.as_mut().unwrap();
+        self.shell_open = false;
+        self.shell_counter = 0;
+
+        // The drive spins back up and reads the TOC of the new disc
+        self.toc_counter = TOC_READ_CLOCKS;
+
+        self.seek_unprocessed = false;
+        self.play_track = 0;
+        self.data_busy = false;
+
+        self.set_drive_location(0);
+        self.update_subq(self.drive_seek_minute, self.drive_seek_second, self.drive_seek_sector);
+    }
+
+    // Leaves the drive as the BIOS does once it has loaded the boot
+    // executable: spinning at double speed and idle after the last sector
+    pub fn finish_boot(&mut self, lba: usize) {
+        self.shell_open = false;
+        self.shell_opened = false;
+        self.shell_counter = 0;
+        self.toc_counter = 0;
+
+        self.playing = false;
+        self.seeking = false;
+        self.reading = false;
+
+        self.mode_double_speed = true;
+        self.mode_adpcm = false;
+        self.mode_sector_size = false;
+        self.mode_filter = false;
+        self.mode_report = false;
+        self.mode_autopause = false;
+
+        self.set_drive_location(lba);
+
+        self.seek_unprocessed = false;
+        self.seek_minute = self.drive_seek_minute;
+        self.seek_second = self.drive_seek_second;
+        self.seek_sector = self.drive_seek_sector;
+
+        self.update_subq(self.drive_seek_minute, self.drive_seek_second, self.drive_seek_sector);
+    }
+
+    fn drive_ready(&self) -> bool {
+        !self.shell_open && self.toc_counter <= 0
+    }
 
-        // Load the primary volume descriptor from the disc
-        file.seek(SeekFrom::Start(16 * BYTES_PER_SECTOR + SECTOR_HEADER_SIZE)).unwrap();
-        file.read_exact(&mut pvd).unwrap();
+    fn has_disc(&self) -> bool {
+        !self.disc.tracks().is_empty()
+    }
 
-        assert_eq!(pvd[0], 0x1);   // This must be of type PVD
-        assert_ne!(pvd[40], 0x20); // The volume identifier must not be empty
+    pub fn get_disc_id(&mut self) -> String {
+        if let Some(id) = &self.disc_id {
+            return id.clone();
+        }
 
-        // Read the volume identifier and strip spaces/other junk.
-        let mut id: String = String::from_utf8_lossy(&pvd[40..72]).to_string();
-        id.retain(|c| !c.is_whitespace());
+        let id = self.read_disc_id();
+        self.disc_id = Some(id.clone());
         id
     }
 
-    pub fn get_disc_id_raw(&mut self) -> String {
-        let mut id = self.get_disc_id();
-        id.retain(|c| c.is_alphanumeric());
-        id.to_ascii_lowercase()
+    fn read_disc_id(&mut self) -> String {
+        if !self.has_disc() {
+            return String::from(NO_DISC_ID);
+        }
+
+        let disc = self.disc.as_mut();
+
+        let config = match iso9660::find(disc, "SYSTEM.CNF").and_then(|entry| iso9660::read_file(disc, &entry)) {
+            Ok(data) => SystemConfig::parse(&String::from_utf8_lossy(&data)),
+            Err(_) => SystemConfig::parse(""),
+        };
+
+        if let Some(serial) = config.serial() {
+            return serial;
+        }
+
+        // Homebrew and demo discs boot generic names like PSX.EXE, tell them
+        // apart by the executable itself
+        match iso9660::find(disc, &config.boot).and_then(|entry| iso9660::read_file(disc, &entry)) {
+            Ok(exe) => format!("EXE-{:016X}", util::fnv1a(&exe)),
+            Err(_) => String::from(UNKNOWN_DISC_ID),
+        }
+    }
+
+    pub fn get_region(&mut self) -> Option<Region> {
+        if let Some(region) = self.disc_region {
+            return region;
+        }
+
+        let region = self.read_region();
+        self.disc_region = Some(region);
+        region
+    }
+
+    // The licence text decides, serials are a fallback for discs without one
+    fn read_region(&mut self) -> Option<Region> {
+        if !self.has_disc() {
+            return None;
+        }
+
+        let mut data = [0u8; SECTOR_SIZE];
+
+        let region = iso9660::read_sector(self.disc.as_mut(), LICENCE_LBA, &mut data)
+            .ok()
+            .and_then(|_| Region::from_licence(&data));
+
+        region.or_else(|| Region::from_serial(&self.get_disc_id()))
+    }
+
+    pub fn set_console_region(&mut self, region: Region) {
+        self.console_region = region;
+    }
+
+    // File system access for the HLE kernel, these bypass the drive entirely
+    pub fn find_file(&mut self, path: &str) -> Result<DirectoryEntry, String> {
+        if !self.has_disc() {
+            return Err("no disc inserted".to_string());
+        }
+
+        iso9660::find(self.disc.as_mut(), path)
+    }
+
+    pub fn read_file(&mut self, entry: &DirectoryEntry) -> Result<Vec<u8>, String> {
+        iso9660::read_file(self.disc.as_mut(), entry)
+    }
+
+    pub fn read_data_sector(&mut self, lba: usize, data: &mut [u8; SECTOR_SIZE]) -> Result<(), String> {
+        iso9660::read_sector(self.disc.as_mut(), lba, data)
+    }
+
+    pub fn list_directory(&mut self, entry: &DirectoryEntry) -> Result<Vec<DirectoryEntry>, String> {
+        iso9660::read_directory(self.disc.as_mut(), entry)
     }
 
     pub fn tick(&mut self, intc: &mut Intc, spu: &mut Spu, clocks: usize) {
+        self.tick_shell(clocks);
         self.tick_second_response(clocks);
         self.tick_drive(spu, clocks);
         self.tick_controller(clocks);
@@ -463,7 +744,7 @@ impl Cdrom {
 
         match self.controller_mode {
             CdromControllerMode::Idle => {
-                if let Some(_) = self.command {
+                if self.command.is_some() {
                     if self.parameter_buffer.has_data() {
                         self.controller_mode = CdromControllerMode::ParameterTransfer;
                     } else {
@@ -534,6 +815,20 @@ impl Cdrom {
         }
     }
 
+    fn tick_shell(&mut self, clocks: usize) {
+        if self.shell_open {
+            if self.shell_counter > 0 {
+                self.shell_counter -= clocks as isize;
+
+                if self.shell_counter <= 0 {
+                    self.close_shell();
+                }
+            }
+        } else if self.toc_counter > 0 {
+            self.toc_counter -= clocks as isize;
+        }
+    }
+
     fn tick_second_response(&mut self, clocks: usize) {
         self.second_response_counter -= clocks as isize;
 
@@ -543,6 +838,26 @@ impl Cdrom {
 
         match self.second_response_mode {
             CdromSecondResponseMode::Idle => self.second_response_counter += clocks as isize,
+            CdromSecondResponseMode::GetID if !self.has_disc() => {
+                if self.interrupt_flags == 0 {
+                    // Empty tray, ID error with the motor stopped
+                    self.controller_response_buffer.push(0x08);
+                    self.controller_response_buffer.push(0x40);
+
+                    for _ in 0..6 {
+                        self.controller_response_buffer.push(0x00);
+                    }
+
+                    self.controller_interrupt_flags = 0x5;
+
+                    self.controller_mode = CdromControllerMode::ResponseClear;
+                    self.controller_counter += 10;
+
+                    self.second_response_mode = CdromSecondResponseMode::Idle;
+                }
+
+                self.second_response_counter += 1;
+            }
             CdromSecondResponseMode::GetID => {
                 if self.interrupt_flags == 0 {
                     self.controller_response_buffer.push(0x02);
@@ -551,10 +866,13 @@ impl Cdrom {
                     self.controller_response_buffer.push(0x20);
                     self.controller_response_buffer.push(0x00);
 
-                    self.controller_response_buffer.push('S' as u8);
-                    self.controller_response_buffer.push('C' as u8);
-                    self.controller_response_buffer.push('E' as u8);
-                    self.controller_response_buffer.push('A' as u8);
+                    // Unlicensed discs pass as the console's own region, as they
+                    // would on a modified console
+                    let region = self.get_region().unwrap_or(self.console_region);
+
+                    for &c in region.licence() {
+                        self.controller_response_buffer.push(c);
+                    }
 
                     self.controller_interrupt_flags = 0x2;
 
@@ -590,6 +908,12 @@ impl Cdrom {
             return;
         }
 
+        // Opening the lid stops the motor and aborts whatever the drive was doing
+        if self.shell_open && self.drive_mode != CdromDriveMode::Idle && self.drive_mode != CdromDriveMode::GetStat {
+            self.drive_abort(0x01, 0x08);
+            return;
+        }
+
         match self.drive_mode {
             CdromDriveMode::Idle => self.drive_counter += clocks as isize,
             CdromDriveMode::GetStat => {
@@ -613,14 +937,9 @@ impl Cdrom {
                 self.drive_seek_second = self.seek_second;
                 self.drive_seek_sector = self.seek_sector;
 
-                self.last_subq.track = 1;
-                self.last_subq.index = 1;
-                self.last_subq.mm = self.seek_minute;
-                self.last_subq.ss = self.seek_second - 2;
-                self.last_subq.ff = self.seek_sector;
-                self.last_subq.amm = self.seek_minute;
-                self.last_subq.ass = self.seek_second;
-                self.last_subq.aff = self.seek_sector;
+                self.update_subq(self.seek_minute, self.seek_second, self.seek_sector);
+
+                self.scan = CdromScan::Normal;
 
                 self.reading = false;
                 self.seeking = false;
@@ -657,78 +976,70 @@ impl Cdrom {
                     return;
                 }
 
-                let cursor = self.get_seek_location();
-                let mut data = [0u8; 0x930];
+                let lba = self.get_seek_location();
 
-                if let Some(file) = self.game_file.as_mut() {
-                    file.seek(SeekFrom::Start(cursor)).unwrap();
-                    file.read_exact(&mut data).unwrap();
-                } else {
-                    panic!("no game file");
+                if lba >= self.disc.lead_out() {
+                    self.drive_data_end();
+                    return;
+                }
+
+                let track = self.get_track(lba);
+                let number = track.map_or(0, |t| t.number);
+
+                if self.mode_autopause && self.play_track != 0 && self.play_track != number {
+                    self.drive_data_end();
+                    return;
                 }
 
+                self.play_track = number;
+
+                let mut data = [0u8; BYTES_PER_SECTOR];
+
+                if let Err(e) = self.disc.read(lba, &mut data) {
+                    self.drive_error(e);
+                    return;
+                }
+
+                // Data tracks are not sent to the DAC
+                let audio = track.is_some_and(|t| t.audio);
+
+                let mut peak_left = 0;
+                let mut peak_right = 0;
+
                 for i in 0..0x24c {
-                    let left = (data[i * 4] as u16) | ((data[i * 4 + 1] as u16) << 8);
-                    let right = (data[i * 4 + 2] as u16) | ((data[i * 4 + 3] as u16) << 8);
+                    if !audio {
+                        self.push_cd_audio(spu, 0, 0);
+                        continue;
+                    }
 
-                    spu.cd_push(left as i16, right as i16);
+                    let left = LittleEndian::read_i16(&data[i * 4..]);
+                    let right = LittleEndian::read_i16(&data[i * 4 + 2..]);
+
+                    peak_left = peak_left.max(left.unsigned_abs());
+                    peak_right = peak_right.max(right.unsigned_abs());
+
+                    self.push_cd_audio(spu, left, right);
                 }
 
+                self.update_subq(self.drive_seek_minute, self.drive_seek_second, self.drive_seek_sector);
+
                 if self.mode_report {
-                    let track = 1;
-                    let index = 1;
-                    let amm = u8_to_bcd(self.drive_seek_minute);
-                    let ass = u8_to_bcd(self.drive_seek_second);
                     let aff = u8_to_bcd(self.drive_seek_sector);
-                    let peaklo = 0;
-                    let peakhi = 0;
 
                     match aff {
-                        0x00 | 0x20 | 0x40 | 0x60 => {
-                            self.push_stat();
-                            self.controller_response_buffer.push(track);
-                            self.controller_response_buffer.push(index);
-                            self.controller_response_buffer.push(amm);
-                            self.controller_response_buffer.push(ass);
-                            self.controller_response_buffer.push(aff);
-                            self.controller_response_buffer.push(peaklo);
-                            self.controller_response_buffer.push(peakhi);
-
-                            self.controller_interrupt_flags = 0x1;
-
-                            self.controller_mode = CdromControllerMode::ResponseClear;
-                            self.controller_counter += 10;
-                        }
-                        0x10 | 0x30 | 0x50 | 0x70 => {
-                            self.push_stat();
-                            self.controller_response_buffer.push(track);
-                            self.controller_response_buffer.push(index);
-                            self.controller_response_buffer.push(amm);
-                            self.controller_response_buffer.push(ass + 0x80);
-                            self.controller_response_buffer.push(aff);
-                            self.controller_response_buffer.push(peaklo);
-                            self.controller_response_buffer.push(peakhi);
-
-                            self.controller_interrupt_flags = 0x1;
-
-                            self.controller_mode = CdromControllerMode::ResponseClear;
-                            self.controller_counter += 10;
-                        }
-                        _ => ()
+                        0x00 | 0x20 | 0x40 | 0x60 => self.push_report(false, peak_left, peak_right),
+                        0x10 | 0x30 | 0x50 | 0x70 => self.push_report(true, peak_left, peak_right),
+                        _ => (),
                     }
                 }
 
-                self.drive_seek_sector += 1;
-
-                if self.drive_seek_sector >= 75 {
-                    self.drive_seek_sector = 0;
-                    self.drive_seek_second += 1;
-                }
+                let next = match self.scan {
+                    CdromScan::Normal => lba + 1,
+                    CdromScan::Forward => lba + SCAN_SECTORS,
+                    CdromScan::Backward => lba.saturating_sub(SCAN_SECTORS),
+                };
 
-                if self.drive_seek_second >= 60 {
-                    self.drive_seek_second = 0;
-                    self.drive_seek_minute += 1;
-                }
+                self.set_drive_location(next);
 
                 self.drive_counter += 44100
                     / match self.mode_double_speed {
@@ -743,19 +1054,17 @@ impl Cdrom {
                     return;
                 }
 
-                self.push_stat();
+                let lba = self.get_seek_location();
+                let mut info = [0u8; BYTES_PER_SECTOR];
 
-                self.data_busy = true;
+                if let Err(e) = self.disc.read(lba, &mut info) {
+                    self.drive_error(e);
+                    return;
+                }
 
-                let cursor = self.get_seek_location();
-                let mut info = [0u8; 0x18];
+                self.push_stat();
 
-                if let Some(file) = self.game_file.as_mut() {
-                    file.seek(SeekFrom::Start(cursor)).unwrap();
-                    file.read_exact(&mut info).unwrap();
-                } else {
-                    panic!("no game file");
-                }
+                self.data_busy = true;
 
                 let header = CdromHeader::from_slice(&info[0xc..]);
                 let subheader = CdromSubheader::from_slice(&info[0x10..]);
@@ -763,14 +1072,7 @@ impl Cdrom {
                 self.sector_header = header;
                 self.sector_subheader = subheader;
 
-                self.last_subq.track = 1;
-                self.last_subq.index = 1;
-                self.last_subq.mm = header.minute;
-                self.last_subq.ss = header.second - 2;
-                self.last_subq.ff = header.sector;
-                self.last_subq.amm = header.minute;
-                self.last_subq.ass = header.second;
-                self.last_subq.aff = header.sector;
+                self.update_subq(header.minute, header.second, header.sector);
 
                 let mut mode = CdromSectorMode::Adpcm;
 
@@ -832,13 +1134,7 @@ impl Cdrom {
                         let channels = subheader.channels();
                         let sampling_rate = subheader.sampling_rate();
 
-                        let mut data = [0u8; 0x914];
-
-                        if let Some(file) = self.game_file.as_mut() {
-                            file.read_exact(&mut data).unwrap();
-                        } else {
-                            panic!("no game file");
-                        }
+                        let data = &info[DATA_OFFSET..DATA_OFFSET + 0x914];
 
                         for i in 0..0x12 {
                             self.decode_adpcm_blocks(&data[i * 0x80..], channels);
@@ -850,6 +1146,10 @@ impl Cdrom {
                             _ => unreachable!()
                         };
 
+                        let mut output = [Vec::new(), Vec::new()];
+
+                        // The channel picks both the ring buffer and the output
+                        #[allow(clippy::needless_range_loop)]
                         for channel in 0..channels {
                             for _ in 0..times {
                                 for i in 0..self.adpcm_buffers[channel].len() {
@@ -859,31 +1159,32 @@ impl Cdrom {
                                     if self.sixstep == 6 {
                                         self.sixstep = 0;
 
-                                        for j in 0..7 {
-                                            let sample = self.zigzag_interpolate(i + 1, self.ringbuf[channel], ADPCM_ZIGZAG_TABLE[j]);
-
-                                            match (channel, channels) {
-                                                (_, 1) => spu.cd_push(sample, sample),
-                                                (0, 2) => spu.cd_push_left(sample),
-                                                (1, 2) => spu.cd_push_right(sample),
-                                                _ => unreachable!(),
-                                            }
+                                        for table in ADPCM_ZIGZAG_TABLE.iter() {
+                                            let sample = self.zigzag_interpolate(i + 1, self.ringbuf[channel], *table);
+                                            output[channel].push(sample);
                                         }
                                     }
                                 }
                             }
                         }
 
+                        let right = match channels {
+                            1 => &output[0],
+                            _ => &output[1],
+                        };
+
+                        for (&left, &right) in output[0].iter().zip(right.iter()) {
+                            match self.adpcm_mute {
+                                true => self.push_cd_audio(spu, 0, 0),
+                                false => self.push_cd_audio(spu, left, right),
+                            };
+                        }
+
                         self.adpcm_buffers[0].clear();
                         self.adpcm_buffers[1].clear();
                     }
                     CdromSectorMode::Data => {
-                        if let Some(file) = self.game_file.as_mut() {
-                            file.seek(SeekFrom::Start(cursor)).unwrap();
-                            file.read_exact(&mut self.sector).unwrap();
-                        } else {
-                            panic!("no game file");
-                        }
+                        self.sector.copy_from_slice(&info);
 
                         // TODO: stat
                         if self.drive_interrupt_pending {
@@ -977,9 +1278,25 @@ impl Cdrom {
 
         let mut interrupt = 0x3;
 
+        // Commands that need the disc fail while the lid is open, the TOC is
+        // still being read or the tray is empty. GetID reports an empty tray
+        // in its second response instead.
+        let ready = self.drive_ready() && (self.has_disc() || command == 0x1a);
+
+        if !ready && matches!(command, 0x03..=0x06 | 0x13..=0x16 | 0x1a | 0x1b | 0x1e) {
+            self.push_error(0x01, 0x80);
+            self.controller_interrupt_flags = 0x5;
+            return;
+        }
+
         match command {
             0x01 => {
                 self.push_stat();
+
+                // The shell open bit is latched until read with the lid closed
+                if !self.shell_open {
+                    self.shell_opened = false;
+                }
             }
             0x02 => {
                 self.push_stat();
@@ -997,7 +1314,21 @@ impl Cdrom {
                 self.seek_sector = bcd_to_u8(ff);
             }
             0x03 => {
-                self.controller_parameter_buffer.pop();
+                let track = bcd_to_u8(self.controller_parameter_buffer.pop());
+
+                if track != 0 {
+                    if let Some(t) = self.disc.tracks().iter().find(|t| t.number == track) {
+                        let (mm, ss, ff) = Timecode::from_lba(t.start + LEAD_IN_SECTORS).to_msf();
+
+                        self.seek_minute = mm;
+                        self.seek_second = ss;
+                        self.seek_sector = ff;
+                        self.seek_unprocessed = true;
+                    }
+                }
+
+                self.scan = CdromScan::Normal;
+                self.play_track = 0;
 
                 if self.seek_unprocessed {
                     self.seeking = true;
@@ -1027,6 +1358,19 @@ impl Cdrom {
 
                 self.push_stat();
             }
+            0x04 | 0x05 => {
+                if self.playing {
+                    self.scan = match command {
+                        0x04 => CdromScan::Forward,
+                        _ => CdromScan::Backward,
+                    };
+
+                    self.push_stat();
+                } else {
+                    self.push_error(0x01, 0x80);
+                    interrupt = 0x5;
+                }
+            }
             0x06 => {
                 if self.seek_unprocessed {
                     self.seeking = true;
@@ -1065,7 +1409,7 @@ impl Cdrom {
             0x09 => {
                 self.push_stat();
 
-                if self.playing == false && self.reading == false && self.seeking == false {
+                if !self.playing && !self.reading && !self.seeking {
                     self.second_response_counter += 10;
                 } else {
                     self.second_response_counter += match self.mode_double_speed {
@@ -1078,6 +1422,8 @@ impl Cdrom {
                 self.reading = false;
                 self.seeking = false;
 
+                self.scan = CdromScan::Normal;
+
                 //self.drive_seek_sector = self.ldrive_seek_sector;
                 //self.drive_seek_second = self.ldrive_seek_second;
                 //self.drive_seek_minute = self.ldrive_seek_minute;
@@ -1093,13 +1439,18 @@ impl Cdrom {
                 self.playing = false;
                 self.seeking = false;
 
+                self.mute = false;
+                self.scan = CdromScan::Normal;
+
                 self.second_response_mode = CdromSecondResponseMode::GetStat;
                 self.second_response_counter += 10;
             }
             0x0b => {
+                self.mute = true;
                 self.push_stat();
             }
             0x0c => {
+                self.mute = false;
                 self.push_stat();
             }
             0x0d => {
@@ -1121,6 +1472,7 @@ impl Cdrom {
                 self.mode_sector_size = (mode & 0x20) != 0;
                 self.mode_filter = (mode & 0x8) != 0;
                 self.mode_report = (mode & 0x4) != 0;
+                self.mode_autopause = (mode & 0x2) != 0;
             }
             0x10 => {
                 let amm = u8_to_bcd(self.sector_header.minute);
@@ -1154,15 +1506,40 @@ impl Cdrom {
                 //self.controller_counter += 37937;
             }
             0x13 => {
-                self.push_stat();
+                let tracks = self.disc.tracks();
+
+                if let (Some(first), Some(last)) = (tracks.first(), tracks.last()) {
+                    let first = u8_to_bcd(first.number);
+                    let last = u8_to_bcd(last.number);
 
-                self.controller_response_buffer.push(1);
-                self.controller_response_buffer.push(1);
+                    self.push_stat();
+                    self.controller_response_buffer.push(first);
+                    self.controller_response_buffer.push(last);
+                } else {
+                    self.push_error(0x01, 0x80);
+                    interrupt = 0x5;
+                }
             }
             0x14 => {
-                self.push_stat();
-                self.controller_response_buffer.push(0);
-                self.controller_response_buffer.push(0);
+                let track = bcd_to_u8(self.controller_parameter_buffer.pop());
+
+                let lba = match track {
+                    0 => Some(self.disc.lead_out()),
+                    _ => self.disc.tracks().iter()
+                        .find(|t| t.number == track)
+                        .map(|t| t.start),
+                };
+
+                if let Some(lba) = lba {
+                    let (mm, ss, _) = Timecode::from_lba(lba + LEAD_IN_SECTORS).to_bcd();
+
+                    self.push_stat();
+                    self.controller_response_buffer.push(mm);
+                    self.controller_response_buffer.push(ss);
+                } else {
+                    self.push_error(0x01, 0x10);
+                    interrupt = 0x5;
+                }
             }
             0x15 | 0x16 => {
                 self.seeking = true;
@@ -1243,20 +1620,154 @@ impl Cdrom {
                 self.controller_response_buffer.push(0x10);
                 self.controller_response_buffer.push(0xc2);
             }
+            0x22 => {
+                let region: &[u8] = match self.console_region {
+                    Region::Japan => b"for Japan",
+                    Region::NorthAmerica => b"for U/C",
+                    Region::Europe => b"for Europe",
+                };
+
+                for &c in region {
+                    self.controller_response_buffer.push(c);
+                }
+            }
             _ => panic!("[CDROM] [ERROR] Unknown test command 0x{:02x}", command),
         }
     }
 
-    fn get_seek_location(&self) -> u64 {
-        let mut sector = ((self.drive_seek_minute as u64) * SECTORS_PER_MINUTE)
-            + ((self.drive_seek_second as u64) * SECTORS_PER_SECOND)
-            + self.drive_seek_sector as u64;
+    fn get_seek_location(&self) -> usize {
+        let mut sector = ((self.drive_seek_minute as usize) * SECTORS_PER_MINUTE)
+            + ((self.drive_seek_second as usize) * SECTORS_PER_SECOND)
+            + self.drive_seek_sector as usize;
 
         if sector >= LEAD_IN_SECTORS {
             sector -= LEAD_IN_SECTORS;
         }
 
-        sector * BYTES_PER_SECTOR
+        sector
+    }
+
+    fn get_track(&self, lba: usize) -> Option<Track> {
+        let tracks = self.disc.tracks();
+
+        tracks.iter()
+            .rev()
+            .find(|t| t.start <= lba)
+            .or(tracks.first())
+            .copied()
+    }
+
+    fn update_subq(&mut self, minute: u8, second: u8, sector: u8) {
+        let absolute = Timecode::from_bcd(u8_to_bcd(minute), u8_to_bcd(second), u8_to_bcd(sector)).to_lba();
+        let lba = absolute.saturating_sub(LEAD_IN_SECTORS);
+
+        let (number, index, relative) = match self.get_track(lba) {
+            Some(t) if lba >= t.start => (t.number, 1, lba - t.start),
+            Some(t) => (t.number, 0, t.start - lba),
+            None => (1, 1, lba),
+        };
+
+        let (mm, ss, ff) = Timecode::from_lba(relative).to_msf();
+
+        self.last_subq.track = u8_to_bcd(number);
+        self.last_subq.index = index;
+        self.last_subq.mm = mm;
+        self.last_subq.ss = ss;
+        self.last_subq.ff = ff;
+        self.last_subq.amm = minute;
+        self.last_subq.ass = second;
+        self.last_subq.aff = sector;
+    }
+
+    fn set_drive_location(&mut self, lba: usize) {
+        let (mm, ss, ff) = Timecode::from_lba(lba + LEAD_IN_SECTORS).to_msf();
+
+        self.drive_seek_minute = mm;
+        self.drive_seek_second = ss;
+        self.drive_seek_sector = ff;
+    }
+
+    fn push_cd_audio(&self, spu: &mut Spu, left: i16, right: i16) {
+        if self.mute {
+            spu.cd_push(0, 0);
+            return;
+        }
+
+        let volume = &self.volume;
+
+        let l = (left as i32 * volume.left_to_left as i32 + right as i32 * volume.right_to_left as i32) >> 7;
+        let r = (left as i32 * volume.left_to_right as i32 + right as i32 * volume.right_to_right as i32) >> 7;
+
+        spu.cd_push(clip(l, -0x8000, 0x7fff) as i16, clip(r, -0x8000, 0x7fff) as i16);
+    }
+
+    fn push_report(&mut self, relative: bool, peak_left: u16, peak_right: u16) {
+        let subq = &self.last_subq;
+
+        let (mm, ss, ff) = match relative {
+            false => (u8_to_bcd(subq.amm), u8_to_bcd(subq.ass), u8_to_bcd(subq.aff)),
+            true => (u8_to_bcd(subq.mm), u8_to_bcd(subq.ss) | 0x80, u8_to_bcd(subq.ff)),
+        };
+
+        let track = subq.track;
+        let index = subq.index;
+
+        // Peak level alternates between channels, bit 15 selects the right channel
+        let peak = match self.report_right {
+            false => peak_left.min(0x7fff),
+            true => peak_right.min(0x7fff) | 0x8000,
+        };
+
+        self.report_right = !self.report_right;
+
+        self.push_stat();
+        self.controller_response_buffer.push(track);
+        self.controller_response_buffer.push(index);
+        self.controller_response_buffer.push(mm);
+        self.controller_response_buffer.push(ss);
+        self.controller_response_buffer.push(ff);
+        self.controller_response_buffer.push(peak as u8);
+        self.controller_response_buffer.push((peak >> 8) as u8);
+
+        self.controller_interrupt_flags = 0x1;
+
+        self.controller_mode = CdromControllerMode::ResponseClear;
+        self.controller_counter += 10;
+    }
+
+    fn drive_data_end(&mut self) {
+        self.playing = false;
+        self.scan = CdromScan::Normal;
+
+        self.drive_mode = CdromDriveMode::Idle;
+        self.drive_counter += 1;
+
+        self.push_stat();
+        self.controller_interrupt_flags = 0x4;
+
+        self.controller_mode = CdromControllerMode::ResponseClear;
+        self.controller_counter += 10;
+    }
+
+    fn drive_error(&mut self, error: String) {
+        println!("[CDROM] [ERROR] Unable to read sector: {}", error);
+
+        self.drive_abort(0x04, 0x04);
+    }
+
+    fn drive_abort(&mut self, stat: u8, code: u8) {
+        self.reading = false;
+        self.playing = false;
+        self.seeking = false;
+
+        self.drive_mode = CdromDriveMode::Idle;
+        self.drive_counter += 1;
+
+        self.push_error(stat, code);
+        self.controller_interrupt_flags = 0x5;
+
+        self.controller_mode = CdromControllerMode::ResponseClear;
+        self.controller_counter += 10;
     }
 
     fn get_stat(&self) -> u8 {
@@ -1265,7 +1776,8 @@ impl Cdrom {
         stat |= (self.playing as u8) << 7;
         stat |= (self.seeking as u8) << 6;
         stat |= (self.reading as u8) << 5;
-        stat |= 0x2;
+        stat |= (self.shell_opened as u8) << 4;
+        stat |= ((!self.shell_open && self.has_disc()) as u8) << 1;
 
         stat
     }
@@ -1275,6 +1787,12 @@ impl Cdrom {
         self.controller_response_buffer.push(stat);
     }
 
+    fn push_error(&mut self, stat: u8, code: u8) {
+        let stat = self.get_stat() | stat;
+        self.controller_response_buffer.push(stat);
+        self.controller_response_buffer.push(code);
+    }
+
     pub fn busy(&self) -> bool {
         if self.controller_mode != CdromControllerMode::Idle {
             return true;
@@ -1395,7 +1913,7 @@ impl Cdrom {
                     Index0 => {
                         self.command = Some(value);
                     }
-                    Index3 => (), // Right-CD to Right-SPU
+                    Index3 => self.pending_volume.right_to_right = value,
                     _ => panic!(
                         "[CDROM] [ERROR] Write to CDROM_REG_{}_{:?}",
                         address & 0x3,
@@ -1407,8 +1925,8 @@ impl Cdrom {
                 match self.index {
                     Index0 => self.parameter_buffer.push(value),
                     Index1 => self.interrupt_enable = value & 0x1f,
-                    Index2 => (), // Left-CD to Left-SPU
-                    Index3 => (), // Right-CD to Left-SPU
+                    Index2 => self.pending_volume.left_to_left = value,
+                    Index3 => self.pending_volume.right_to_left = value,
                 }
             }
             3 => {
@@ -1441,8 +1959,14 @@ impl Cdrom {
                             self.parameter_buffer.clear();
                         }
                     }
-                    Index2 => (), // Left-CD to Right-SPU
-                    Index3 => (), // Apply Volume Change
+                    Index2 => self.pending_volume.left_to_right = value,
+                    Index3 => {
+                        self.adpcm_mute = (value & 0x1) != 0;
+
+                        if (value & 0x20) != 0 {
+                            self.volume = self.pending_volume;
+                        }
+                    }
                 }
             }
             _ => panic!(
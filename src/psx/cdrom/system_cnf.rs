@@ -0,0 +1,108 @@
+// What the BIOS boots when a disc has no SYSTEM.CNF
+pub const DEFAULT_BOOT: &str = "cdrom:\\PSX.EXE;1";
+
+pub struct SystemConfig {
+    pub boot: String,
+    pub threads: Option<u32>,
+    pub events: Option<u32>,
+    pub stack: Option<u32>,
+}
+
+impl SystemConfig {
+    // SYSTEM.CNF is a list of KEY = VALUE lines, numbers are in hex
+    pub fn parse(text: &str) -> SystemConfig {
+        let mut config = SystemConfig {
+            boot: DEFAULT_BOOT.to_string(),
+            threads: None,
+            events: None,
+            stack: None,
+        };
+
+        for line in text.lines() {
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => continue,
+            };
+
+            let value = value.split_whitespace().next().unwrap_or("");
+            let number = u32::from_str_radix(value, 16).ok();
+
+            match key {
+                "BOOT" if !value.is_empty() => config.boot = value.to_string(),
+                "TCB" => config.threads = number,
+                "EVENT" => config.events = number,
+                "STACK" => config.stack = number,
+                _ => (),
+            };
+        }
+
+        config
+    }
+
+    // Licensed discs boot a file named after their serial, so
+    // cdrom:\SLUS_005.94;1 is SLUS-00594
+    pub fn serial(&self) -> Option<String> {
+        let name = self.boot.rsplit(['\\', '/', ':']).next()?;
+        let name = name.split(';').next()?.to_ascii_uppercase();
+
+        let prefix: String = name.chars().take_while(|c| c.is_ascii_alphabetic()).collect();
+        let rest = name[prefix.len()..].trim_start_matches(['_', '-']);
+
+        let digits: String = rest.chars().filter(|&c| c != '.').collect();
+
+        if prefix.len() != 4 || digits.len() != 5 || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        Some(format!("{}-{}", prefix, digits))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SystemConfig, DEFAULT_BOOT};
+
+    #[test]
+    fn serial() {
+        let cases = [
+            ("BOOT = cdrom:\\SLUS_005.94;1", Some("SLUS-00594")),
+            ("BOOT=cdrom:\\SCES_012.34;1", Some("SCES-01234")),
+            ("  BOOT   =   cdrom:\\SCPS_100.01;1   ", Some("SCPS-10001")),
+            ("BOOT = cdrom:\\slps_123.45;1", Some("SLPS-12345")),
+            ("BOOT = cdrom:\\SLES_987.65", Some("SLES-98765")),
+            ("BOOT = cdrom:\\GAME\\SLPM_860.23;1", Some("SLPM-86023")),
+            ("BOOT = cdrom:SLUS-012.34;1", Some("SLUS-01234")),
+            ("BOOT = cdrom:\\SLUS_01234;1", Some("SLUS-01234")),
+            ("BOOT = cdrom:\\SLUS_005.94;1 argument", Some("SLUS-00594")),
+            ("BOOT = cdrom:\\MAIN.EXE;1", None),
+            ("BOOT = cdrom:\\SLUS_005.9;1", None),
+            ("BOOT = cdrom:\\SLUS_005.941;1", None),
+            ("BOOT = cdrom:\\SLU_005.94;1", None),
+            ("BOOT = cdrom:\\SLUS_0A5.94;1", None),
+            ("BOOT2 = cdrom0:\\SLUS_200.62;1", None),
+            ("BOOT =", None),
+            ("", None),
+        ];
+
+        for (text, expected) in cases {
+            let config = SystemConfig::parse(text);
+            assert_eq!(config.serial().as_deref(), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let config = SystemConfig::parse("BOOT = cdrom:\\SLUS_005.94;1\r\nTCB = 4\r\nEVENT = 10\r\nSTACK = 801FFF00\r\n");
+
+        assert_eq!(config.boot, "cdrom:\\SLUS_005.94;1");
+        assert_eq!(config.threads, Some(4));
+        assert_eq!(config.events, Some(0x10));
+        assert_eq!(config.stack, Some(0x801f_ff00));
+
+        let config = SystemConfig::parse("BOOT2 = cdrom0:\\SLUS_200.62;1\nTCB = nonsense\n");
+
+        assert_eq!(config.boot, DEFAULT_BOOT);
+        assert_eq!(config.threads, None);
+        assert_eq!(config.stack, None);
+    }
+}
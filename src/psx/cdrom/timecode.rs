@@ -17,10 +17,22 @@ impl Timecode {
         }
     }
 
+    pub fn from_lba(lba: usize) -> Self {
+        Self {
+            minute: lba / (60 * 75),
+            second: (lba / 75) % 60,
+            sector: lba % 75,
+        }
+    }
+
+    pub fn to_msf(&self) -> (u8, u8, u8) {
+        (self.minute as u8, self.second as u8, self.sector as u8)
+    }
+
     pub fn to_bcd(&self) -> (u8, u8, u8) {
-        let minute = helpers::bcd_to_u8(self.minute as u8);
-        let second = helpers::bcd_to_u8(self.second as u8);
-        let sector = helpers::bcd_to_u8(self.sector as u8);
+        let minute = helpers::u8_to_bcd(self.minute as u8);
+        let second = helpers::u8_to_bcd(self.second as u8);
+        let sector = helpers::u8_to_bcd(self.sector as u8);
 
         (minute, second, sector)
     }
@@ -0,0 +1,422 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::super::bus::Bus;
+use super::R3000A;
+
+// Argument signatures use one character per argument: s is a string, c a
+// character, d a signed integer, x a hex value and p a pointer
+type BiosFunction = (u32, &'static str, &'static str);
+
+const A0_FUNCTIONS: &[BiosFunction] = &[
+    (0x00, "open", "sx"),
+    (0x01, "lseek", "ddd"),
+    (0x02, "read", "dpd"),
+    (0x03, "write", "dpd"),
+    (0x04, "close", "d"),
+    (0x05, "ioctl", "dxx"),
+    (0x06, "exit", "d"),
+    (0x07, "isatty", "d"),
+    (0x08, "getc", "d"),
+    (0x09, "putc", "cd"),
+    (0x0a, "todigit", "c"),
+    (0x0b, "atof", "s"),
+    (0x0c, "strtoul", "spd"),
+    (0x0d, "strtol", "spd"),
+    (0x0e, "abs", "d"),
+    (0x0f, "labs", "d"),
+    (0x10, "atoi", "s"),
+    (0x11, "atol", "s"),
+    (0x12, "atob", "sp"),
+    (0x13, "setjmp", "p"),
+    (0x14, "longjmp", "px"),
+    (0x15, "strcat", "ss"),
+    (0x16, "strncat", "ssd"),
+    (0x17, "strcmp", "ss"),
+    (0x18, "strncmp", "ssd"),
+    (0x19, "strcpy", "ps"),
+    (0x1a, "strncpy", "psd"),
+    (0x1b, "strlen", "s"),
+    (0x1c, "index", "sc"),
+    (0x1d, "rindex", "sc"),
+    (0x1e, "strchr", "sc"),
+    (0x1f, "strrchr", "sc"),
+    (0x20, "strpbrk", "ss"),
+    (0x21, "strspn", "ss"),
+    (0x22, "strcspn", "ss"),
+    (0x23, "strtok", "ss"),
+    (0x24, "strstr", "ss"),
+    (0x25, "toupper", "c"),
+    (0x26, "tolower", "c"),
+    (0x27, "bcopy", "ppd"),
+    (0x28, "bzero", "pd"),
+    (0x29, "bcmp", "ppd"),
+    (0x2a, "memcpy", "ppd"),
+    (0x2b, "memset", "pxd"),
+    (0x2c, "memmove", "ppd"),
+    (0x2d, "memcmp", "ppd"),
+    (0x2e, "memchr", "pxd"),
+    (0x2f, "rand", ""),
+    (0x30, "srand", "x"),
+    (0x31, "qsort", "pddp"),
+    (0x32, "strtod", "sp"),
+    (0x33, "malloc", "d"),
+    (0x34, "free", "p"),
+    (0x35, "lsearch", "ppddp"),
+    (0x36, "bsearch", "ppddp"),
+    (0x37, "calloc", "dd"),
+    (0x38, "realloc", "pd"),
+    (0x39, "InitHeap", "pd"),
+    (0x3a, "SystemErrorExit", "d"),
+    (0x3b, "std_in_getchar", ""),
+    (0x3c, "std_out_putchar", "c"),
+    (0x3d, "std_in_gets", "p"),
+    (0x3e, "std_out_puts", "s"),
+    (0x3f, "printf", "sxxx"),
+    (0x40, "SystemErrorUnresolvedException", ""),
+    (0x41, "LoadExeHeader", "sp"),
+    (0x42, "LoadExeFile", "sp"),
+    (0x43, "DoExecute", "pxx"),
+    (0x44, "FlushCache", ""),
+    (0x45, "init_a0_b0_c0_vectors", ""),
+    (0x46, "GPU_dw", "ddddp"),
+    (0x47, "gpu_send_dma", "ddddp"),
+    (0x48, "SendGP1Command", "x"),
+    (0x49, "GPU_cw", "x"),
+    (0x4a, "GPU_cwp", "pd"),
+    (0x4b, "send_gpu_linked_list", "p"),
+    (0x4c, "gpu_abort_dma", ""),
+    (0x4d, "GetGPUStatus", ""),
+    (0x4e, "gpu_sync", ""),
+    (0x51, "LoadAndExecute", "spx"),
+    (0x52, "GetSysSp", ""),
+    (0x54, "CdInit", ""),
+    (0x55, "_bu_init", ""),
+    (0x56, "CdRemove", ""),
+    (0x5b, "dev_tty_init", ""),
+    (0x5c, "dev_tty_open", "psx"),
+    (0x5d, "dev_tty_in_out", "px"),
+    (0x5e, "dev_tty_ioctl", "pxx"),
+    (0x5f, "dev_cd_open", "psx"),
+    (0x60, "dev_cd_read", "ppd"),
+    (0x61, "dev_cd_close", "p"),
+    (0x62, "dev_cd_firstfile", "psp"),
+    (0x63, "dev_cd_nextfile", "pp"),
+    (0x64, "dev_cd_chdir", "ps"),
+    (0x65, "dev_card_open", "psx"),
+    (0x66, "dev_card_read", "ppd"),
+    (0x67, "dev_card_write", "ppd"),
+    (0x68, "dev_card_close", "p"),
+    (0x69, "dev_card_firstfile", "psp"),
+    (0x6a, "dev_card_nextfile", "pp"),
+    (0x6b, "dev_card_erase", "ps"),
+    (0x6c, "dev_card_undelete", "ps"),
+    (0x6d, "dev_card_format", "p"),
+    (0x6e, "dev_card_rename", "psps"),
+    (0x6f, "card_clear_error", "p"),
+    (0x70, "_bu_init", ""),
+    (0x71, "CdInit", ""),
+    (0x72, "CdRemove", ""),
+    (0x78, "CdAsyncSeekL", "p"),
+    (0x7c, "CdAsyncGetStatus", "p"),
+    (0x7e, "CdAsyncReadSector", "dpx"),
+    (0x81, "CdAsyncSetMode", "x"),
+    (0x90, "CdromIoIrqFunc1", ""),
+    (0x91, "CdromDmaIrqFunc1", ""),
+    (0x92, "CdromIoIrqFunc2", ""),
+    (0x93, "CdromDmaIrqFunc2", ""),
+    (0x94, "CdromGetInt5errCode", "pp"),
+    (0x95, "CdInitSubFunc", ""),
+    (0x96, "AddCDROMDevice", ""),
+    (0x97, "AddMemCardDevice", ""),
+    (0x98, "AddDuartTtyDevice", ""),
+    (0x99, "AddDummyTtyDevice", ""),
+    (0x9c, "SetConf", "ddp"),
+    (0x9d, "GetConf", "ppp"),
+    (0x9e, "SetCdromIrqAutoAbort", "dd"),
+    (0x9f, "SetMemSize", "d"),
+    (0xa0, "WarmBoot", ""),
+    (0xa1, "SystemErrorBootOrDiskFailure", "cx"),
+    (0xa2, "EnqueueCdIntr", ""),
+    (0xa3, "DequeueCdIntr", ""),
+    (0xa4, "CdGetLbn", "s"),
+    (0xa5, "CdReadSector", "ddp"),
+    (0xa6, "CdGetStatus", ""),
+    (0xa7, "bu_callback_okay", ""),
+    (0xa8, "bu_callback_err_write", ""),
+    (0xa9, "bu_callback_err_busy", ""),
+    (0xaa, "bu_callback_err_eject", ""),
+    (0xab, "_card_info", "x"),
+    (0xac, "_card_async_load_directory", "x"),
+    (0xad, "set_card_auto_format", "d"),
+    (0xae, "bu_callback_err_prev_write", ""),
+    (0xaf, "card_write_test", "x"),
+    (0xb2, "ioabort_raw", "x"),
+    (0xb4, "GetSystemInfo", "x"),
+];
+
+const B0_FUNCTIONS: &[BiosFunction] = &[
+    (0x00, "alloc_kernel_memory", "d"),
+    (0x01, "free_kernel_memory", "p"),
+    (0x02, "init_timer", "dxx"),
+    (0x03, "get_timer", "d"),
+    (0x04, "enable_timer_irq", "d"),
+    (0x05, "disable_timer_irq", "d"),
+    (0x06, "restart_timer", "d"),
+    (0x07, "DeliverEvent", "xx"),
+    (0x08, "OpenEvent", "xxxp"),
+    (0x09, "CloseEvent", "x"),
+    (0x0a, "WaitEvent", "x"),
+    (0x0b, "TestEvent", "x"),
+    (0x0c, "EnableEvent", "x"),
+    (0x0d, "DisableEvent", "x"),
+    (0x0e, "OpenThread", "ppp"),
+    (0x0f, "CloseThread", "x"),
+    (0x10, "ChangeThread", "x"),
+    (0x11, "jump_to_00000000h", ""),
+    (0x12, "InitPad", "pdpd"),
+    (0x13, "StartPad", ""),
+    (0x14, "StopPad", ""),
+    (0x15, "OutdatedPadInitAndStart", "xpxx"),
+    (0x16, "OutdatedPadGetButtons", ""),
+    (0x17, "ReturnFromException", ""),
+    (0x18, "SetDefaultExitFromException", ""),
+    (0x19, "SetCustomExitFromException", "p"),
+    (0x20, "UnDeliverEvent", "xx"),
+    (0x32, "open", "sx"),
+    (0x33, "lseek", "ddd"),
+    (0x34, "read", "dpd"),
+    (0x35, "write", "dpd"),
+    (0x36, "close", "d"),
+    (0x37, "ioctl", "dxx"),
+    (0x38, "exit", "d"),
+    (0x39, "isatty", "d"),
+    (0x3a, "getc", "d"),
+    (0x3b, "putc", "cd"),
+    (0x3c, "std_in_getchar", ""),
+    (0x3d, "std_out_putchar", "c"),
+    (0x3e, "std_in_gets", "p"),
+    (0x3f, "std_out_puts", "s"),
+    (0x40, "chdir", "s"),
+    (0x41, "FormatDevice", "s"),
+    (0x42, "firstfile", "sp"),
+    (0x43, "nextfile", "p"),
+    (0x44, "rename", "ss"),
+    (0x45, "erase", "s"),
+    (0x46, "undelete", "s"),
+    (0x47, "AddDrv", "p"),
+    (0x48, "DelDrv", "s"),
+    (0x49, "PrintInstalledDevices", ""),
+    (0x4a, "InitCard2", "d"),
+    (0x4b, "StartCard2", ""),
+    (0x4c, "StopCard2", ""),
+    (0x4d, "_card_info_subfunc", "x"),
+    (0x4e, "write_card_sector", "xdp"),
+    (0x4f, "read_card_sector", "xdp"),
+    (0x50, "allow_new_card", ""),
+    (0x51, "Krom2RawAdd", "x"),
+    (0x53, "Krom2Offset", "x"),
+    (0x54, "GetLastError", ""),
+    (0x55, "GetLastFileError", "d"),
+    (0x56, "GetC0Table", ""),
+    (0x57, "GetB0Table", ""),
+    (0x58, "get_bu_callback_port", ""),
+    (0x59, "testdevice", "s"),
+    (0x5b, "ChangeClearPad", "d"),
+    (0x5c, "get_card_status", "d"),
+    (0x5d, "wait_card_status", "d"),
+];
+
+const C0_FUNCTIONS: &[BiosFunction] = &[
+    (0x00, "EnqueueTimerAndVblankIrqs", "d"),
+    (0x01, "EnqueueSyscallHandler", "d"),
+    (0x02, "SysEnqIntRP", "dp"),
+    (0x03, "SysDeqIntRP", "dp"),
+    (0x04, "get_free_EvCB_slot", ""),
+    (0x05, "get_free_TCB_slot", ""),
+    (0x06, "ExceptionHandler", ""),
+    (0x07, "InstallExceptionHandlers", ""),
+    (0x08, "SysInitMemory", "pd"),
+    (0x09, "SysInitKernelVariables", ""),
+    (0x0a, "ChangeClearRCnt", "dd"),
+    (0x0c, "InitDefInt", "d"),
+    (0x0d, "SetIrqAutoAck", "dd"),
+    (0x12, "InstallDevices", "d"),
+    (0x13, "FlushStdInOutPut", ""),
+    (0x15, "tty_cdevinput", "pc"),
+    (0x16, "tty_cdevscan", ""),
+    (0x17, "tty_circgetc", "p"),
+    (0x18, "tty_circputc", "cp"),
+    (0x19, "ioabort", "ss"),
+    (0x1a, "set_card_find_mode", "d"),
+    (0x1b, "KernelRedirect", "d"),
+    (0x1c, "AdjustA0Table", ""),
+    (0x1d, "get_card_find_mode", ""),
+];
+
+pub fn function_name(vector: u32, number: u32) -> Option<&'static str> {
+    let functions = match vector {
+        0xa0 => A0_FUNCTIONS,
+        0xb0 => B0_FUNCTIONS,
+        0xc0 => C0_FUNCTIONS,
+        _ => return None,
+    };
+
+    functions.iter().find(|f| f.0 == number).map(|f| f.1)
+}
+
+const MAX_STRING_LENGTH: usize = 64;
+
+// Calls that never return (exit, longjmp, exceptions) would otherwise pile up
+const MAX_PENDING_CALLS: usize = 32;
+
+struct PendingCall {
+    return_address: u32,
+    sp: u32,
+    name: String,
+}
+
+// Logs calls made through the A0, B0 and C0 kernel vectors, and optionally
+// the values they return
+pub struct BiosTracer {
+    writer: BufWriter<File>,
+
+    returns: bool,
+    pending: Vec<PendingCall>,
+}
+
+impl BiosTracer {
+    pub fn create(path: &Path, returns: bool) -> io::Result<BiosTracer> {
+        Ok(BiosTracer {
+            writer: BufWriter::new(File::create(path)?),
+
+            returns,
+            pending: Vec::new(),
+        })
+    }
+
+    // Called with the PC of the next instruction to execute
+    pub fn update(&mut self, pc: u32, regs: &[u32; 32], bus: &Bus) {
+        let result = match R3000A::translate_address(pc) {
+            0xa0 => self.log_call(0xa0, A0_FUNCTIONS, regs, bus),
+            0xb0 => self.log_call(0xb0, B0_FUNCTIONS, regs, bus),
+            0xc0 => self.log_call(0xc0, C0_FUNCTIONS, regs, bus),
+            _ => self.log_return(pc, regs),
+        };
+
+        if let Err(e) = result {
+            println!("[BIOS] [ERROR] Unable to write BIOS trace: {}", e);
+        }
+    }
+
+    fn log_call(&mut self,
+                vector: u32,
+                functions: &[BiosFunction],
+                regs: &[u32; 32],
+                bus: &Bus) -> io::Result<()> {
+        let number = regs[9] & 0xff;
+
+        let name = format!("{:02X}:{:02X}", vector, number);
+        let mut line = format!("{:08x} {} ", regs[31], name);
+
+        match functions.iter().find(|f| f.0 == number) {
+            Some(&(_, function, signature)) => {
+                let arguments: Vec<String> = signature.chars()
+                    .enumerate()
+                    .map(|(i, kind)| format_argument(kind, argument(i, regs, bus), bus))
+                    .collect();
+
+                line.push_str(&format!("{}({})", function, arguments.join(", ")));
+            },
+            None => line.push_str("unknown"),
+        };
+
+        writeln!(self.writer, "{}", line)?;
+
+        if self.returns {
+            if self.pending.len() >= MAX_PENDING_CALLS {
+                self.pending.remove(0);
+            }
+
+            self.pending.push(PendingCall {
+                return_address: regs[31],
+                sp: regs[29],
+                name,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn log_return(&mut self, pc: u32, regs: &[u32; 32]) -> io::Result<()> {
+        let returned = self.pending.last()
+            .is_some_and(|call| call.return_address == pc && call.sp == regs[29]);
+
+        if !returned {
+            return Ok(());
+        }
+
+        if let Some(call) = self.pending.pop() {
+            writeln!(self.writer, "{:08x} {} = 0x{:08x}", pc, call.name, regs[2])?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for BiosTracer {
+    fn drop(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            println!("[BIOS] [ERROR] Unable to write BIOS trace: {}", e);
+        }
+    }
+}
+
+// The first four arguments are passed in $a0-$a3, the rest on the stack
+// after the space reserved for them
+fn argument(index: usize, regs: &[u32; 32], bus: &Bus) -> u32 {
+    if index < 4 {
+        return regs[4 + index];
+    }
+
+    let address = regs[29].wrapping_add(4 * index as u32);
+
+    (0..4).fold(0, |value, i| {
+        let byte = bus.peek(R3000A::translate_address(address.wrapping_add(i))).unwrap_or(0);
+        value | ((byte as u32) << (8 * i))
+    })
+}
+
+fn format_argument(kind: char, value: u32, bus: &Bus) -> String {
+    match kind {
+        's' => read_string(value, bus),
+        'c' => format!("{:?}", (value & 0xff) as u8 as char),
+        'd' => format!("{}", value as i32),
+        _ => format!("0x{:08x}", value),
+    }
+}
+
+fn read_string(address: u32, bus: &Bus) -> String {
+    if address == 0 {
+        return "NULL".to_string();
+    }
+
+    let mut bytes = Vec::new();
+
+    for i in 0..(MAX_STRING_LENGTH as u32 + 1) {
+        match bus.peek(R3000A::translate_address(address.wrapping_add(i))) {
+            Some(0) => break,
+            Some(byte) => bytes.push(byte),
+            None => return format!("0x{:08x}", address),
+        };
+    }
+
+    let mut string = format!("{:?}", String::from_utf8_lossy(&bytes[..bytes.len().min(MAX_STRING_LENGTH)]));
+
+    if bytes.len() > MAX_STRING_LENGTH {
+        string.push_str("...");
+    }
+
+    string
+}
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Exception {
     Interrupt = 0,
     AddrLoad = 4,
@@ -395,6 +395,10 @@ impl Cop0 {
         self.bad_vaddr = value;
     }
 
+    pub fn set_epc(&mut self, value: u32) {
+        self.epc = value;
+    }
+
     pub fn iec(&self) -> bool {
         self.status.interrupt_enable_current
     }
@@ -415,8 +419,7 @@ impl Cop0 {
         let kernel = test >= 0x8000_0000;
         let user = !kernel;
 
-        if !(self.dcic.kernel_debug && kernel)
-            && !(self.dcic.user_debug && user) {
+        if !((self.dcic.kernel_debug && kernel) || (self.dcic.user_debug && user)) {
             return false;
         }
 
@@ -442,8 +445,7 @@ impl Cop0 {
         let kernel = test >= 0x8000_0000;
         let user = !kernel;
 
-        if !(self.dcic.kernel_debug && kernel)
-            && !(self.dcic.user_debug && user) {
+        if !((self.dcic.kernel_debug && kernel) || (self.dcic.user_debug && user)) {
             return false;
         }
 
@@ -480,8 +482,7 @@ impl Cop0 {
         let kernel = test >= 0x8000_0000;
         let user = !kernel;
 
-        if !(self.dcic.kernel_debug && kernel)
-            && !(self.dcic.user_debug && user) {
+        if !((self.dcic.kernel_debug && kernel) || (self.dcic.user_debug && user)) {
             return false;
         }
 
@@ -0,0 +1,106 @@
+use super::R3000A;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    Breakpoint,
+    Watchpoint(WatchKind, u32),
+    Step,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Watchpoint {
+    address: u32,
+    length: u32,
+    kind: WatchKind,
+}
+
+// Breakpoints and watchpoints for an attached debugger. Addresses are
+// physical so that KUSEG/KSEG0/KSEG1 aliases of the same location match.
+#[derive(Default)]
+pub struct Debugger {
+    enabled: bool,
+
+    breakpoints: Vec<u32>,
+    watchpoints: Vec<Watchpoint>,
+
+    hit: Option<StopReason>,
+}
+
+impl Debugger {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+
+        if !enabled {
+            self.breakpoints.clear();
+            self.watchpoints.clear();
+            self.hit = None;
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, address: u32) {
+        if !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u32) {
+        self.breakpoints.retain(|&b| b != address);
+    }
+
+    pub fn add_watchpoint(&mut self, address: u32, length: u32, kind: WatchKind) {
+        let watchpoint = Watchpoint {
+            address,
+            length: length.max(1),
+            kind,
+        };
+
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, address: u32, length: u32, kind: WatchKind) {
+        self.watchpoints.retain(|w| w.address != address || w.length != length.max(1) || w.kind != kind);
+    }
+
+    pub fn check_breakpoint(&self, address: u32) -> bool {
+        self.breakpoints.contains(&address)
+    }
+
+    // Hits report the virtual address so the debugger can match it
+    pub fn check_access(&mut self, virtual_address: u32, length: u32, write: bool) {
+        if self.hit.is_some() {
+            return;
+        }
+
+        let address = R3000A::translate_address(virtual_address);
+
+        for w in self.watchpoints.iter() {
+            let matches = match w.kind {
+                WatchKind::Read => !write,
+                WatchKind::Write => write,
+                WatchKind::Access => true,
+            };
+
+            if matches && address < w.address.wrapping_add(w.length) && w.address < address.wrapping_add(length) {
+                self.hit = Some(StopReason::Watchpoint(w.kind, virtual_address));
+                return;
+            }
+        }
+    }
+
+    pub fn take_hit(&mut self) -> Option<StopReason> {
+        self.hit.take()
+    }
+}
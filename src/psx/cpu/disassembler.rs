@@ -0,0 +1,271 @@
+use super::instruction::Instruction;
+
+const REGISTERS: [&str; 32] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3",
+    "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7",
+    "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7",
+    "t8", "t9", "k0", "k1", "gp", "sp", "fp", "ra",
+];
+
+const COP0_REGISTERS: [&str; 16] = [
+    "r0", "r1", "r2", "bpc", "r4", "bda", "jumpdest", "dcic",
+    "badvaddr", "bdam", "r10", "bpcm", "sr", "cause", "epc", "prid",
+];
+
+const GTE_DATA_REGISTERS: [&str; 32] = [
+    "vxy0", "vz0", "vxy1", "vz1", "vxy2", "vz2", "rgbc", "otz",
+    "ir0", "ir1", "ir2", "ir3", "sxy0", "sxy1", "sxy2", "sxyp",
+    "sz0", "sz1", "sz2", "sz3", "rgb0", "rgb1", "rgb2", "res1",
+    "mac0", "mac1", "mac2", "mac3", "irgb", "orgb", "lzcs", "lzcr",
+];
+
+const GTE_CONTROL_REGISTERS: [&str; 32] = [
+    "rt11rt12", "rt13rt21", "rt22rt23", "rt31rt32", "rt33", "trx", "try", "trz",
+    "l11l12", "l13l21", "l22l23", "l31l32", "l33", "rbk", "gbk", "bbk",
+    "lr1lr2", "lr3lg1", "lg2lg3", "lb1lb2", "lb3", "rfc", "gfc", "bfc",
+    "ofx", "ofy", "h", "dqa", "dqb", "zsf3", "zsf4", "flag",
+];
+
+fn reg(index: usize) -> String {
+    format!("${}", REGISTERS[index])
+}
+
+fn signed(value: u32) -> String {
+    let value = value as i32;
+
+    match value < 0 {
+        true => format!("-0x{:x}", -value),
+        false => format!("0x{:x}", value),
+    }
+}
+
+fn cop0_reg(index: usize) -> String {
+    match COP0_REGISTERS.get(index) {
+        Some(name) => format!("${}", name),
+        None => format!("${}", index),
+    }
+}
+
+// Conventional MIPS assembly for a single instruction. The address is used
+// to resolve branch and jump targets.
+pub fn disassemble(address: u32, instruction: u32) -> String {
+    let i = Instruction(instruction);
+
+    let branch_target = address.wrapping_add(4).wrapping_add(i.imm_se() << 2);
+    let jump_target = (address.wrapping_add(4) & 0xf000_0000) | (i.target() << 2);
+
+    let immediate = |name: &str| format!("{} {}, {}, {}", name, reg(i.rt()), reg(i.rs()), signed(i.imm_se()));
+    let logical = |name: &str| format!("{} {}, {}, 0x{:x}", name, reg(i.rt()), reg(i.rs()), i.imm());
+    let memory = |name: &str, rt: String| format!("{} {}, {}({})", name, rt, signed(i.imm_se()), reg(i.rs()));
+
+    match i.opcode() {
+        0x00 => disassemble_special(i),
+        0x01 => {
+            let link = (i.rt() & 0x1e) == 0x10;
+
+            let name = match (i.rt() & 0x01 != 0, link) {
+                (false, false) => "bltz",
+                (true, false) => "bgez",
+                (false, true) => "bltzal",
+                (true, true) => "bgezal",
+            };
+
+            format!("{} {}, 0x{:08x}", name, reg(i.rs()), branch_target)
+        },
+        0x02 => format!("j 0x{:08x}", jump_target),
+        0x03 => format!("jal 0x{:08x}", jump_target),
+        0x04 => format!("beq {}, {}, 0x{:08x}", reg(i.rs()), reg(i.rt()), branch_target),
+        0x05 => format!("bne {}, {}, 0x{:08x}", reg(i.rs()), reg(i.rt()), branch_target),
+        0x06 => format!("blez {}, 0x{:08x}", reg(i.rs()), branch_target),
+        0x07 => format!("bgtz {}, 0x{:08x}", reg(i.rs()), branch_target),
+        0x08 => immediate("addi"),
+        0x09 => immediate("addiu"),
+        0x0a => immediate("slti"),
+        0x0b => immediate("sltiu"),
+        0x0c => logical("andi"),
+        0x0d => logical("ori"),
+        0x0e => logical("xori"),
+        0x0f => format!("lui {}, 0x{:x}", reg(i.rt()), i.imm()),
+        0x10 => disassemble_cop0(i),
+        0x11 => format!("cop1 0x{:07x}", i.target()),
+        0x12 => disassemble_cop2(i),
+        0x13 => format!("cop3 0x{:07x}", i.target()),
+        0x20 => memory("lb", reg(i.rt())),
+        0x21 => memory("lh", reg(i.rt())),
+        0x22 => memory("lwl", reg(i.rt())),
+        0x23 => memory("lw", reg(i.rt())),
+        0x24 => memory("lbu", reg(i.rt())),
+        0x25 => memory("lhu", reg(i.rt())),
+        0x26 => memory("lwr", reg(i.rt())),
+        0x28 => memory("sb", reg(i.rt())),
+        0x29 => memory("sh", reg(i.rt())),
+        0x2a => memory("swl", reg(i.rt())),
+        0x2b => memory("sw", reg(i.rt())),
+        0x2e => memory("swr", reg(i.rt())),
+        0x30 => memory("lwc0", format!("${}", i.rt())),
+        0x31 => memory("lwc1", format!("${}", i.rt())),
+        0x32 => memory("lwc2", format!("${}", GTE_DATA_REGISTERS[i.rt()])),
+        0x33 => memory("lwc3", format!("${}", i.rt())),
+        0x38 => memory("swc0", format!("${}", i.rt())),
+        0x39 => memory("swc1", format!("${}", i.rt())),
+        0x3a => memory("swc2", format!("${}", GTE_DATA_REGISTERS[i.rt()])),
+        0x3b => memory("swc3", format!("${}", i.rt())),
+        _ => format!("illegal 0x{:08x}", instruction),
+    }
+}
+
+fn disassemble_special(i: Instruction) -> String {
+    if i.0 == 0 {
+        return "nop".to_string();
+    }
+
+    let shift = |name: &str| format!("{} {}, {}, {}", name, reg(i.rd()), reg(i.rt()), i.shift());
+    let shift_variable = |name: &str| format!("{} {}, {}, {}", name, reg(i.rd()), reg(i.rt()), reg(i.rs()));
+    let arithmetic = |name: &str| format!("{} {}, {}, {}", name, reg(i.rd()), reg(i.rs()), reg(i.rt()));
+    let multiply = |name: &str| format!("{} {}, {}", name, reg(i.rs()), reg(i.rt()));
+
+    let code = (i.0 >> 6) & 0xf_ffff;
+
+    match i.function() {
+        0x00 => shift("sll"),
+        0x02 => shift("srl"),
+        0x03 => shift("sra"),
+        0x04 => shift_variable("sllv"),
+        0x06 => shift_variable("srlv"),
+        0x07 => shift_variable("srav"),
+        0x08 => format!("jr {}", reg(i.rs())),
+        0x09 => match i.rd() {
+            31 => format!("jalr {}", reg(i.rs())),
+            rd => format!("jalr {}, {}", reg(rd), reg(i.rs())),
+        },
+        0x0c => match code {
+            0 => "syscall".to_string(),
+            _ => format!("syscall 0x{:x}", code),
+        },
+        0x0d => match code {
+            0 => "break".to_string(),
+            _ => format!("break 0x{:x}", code),
+        },
+        0x10 => format!("mfhi {}", reg(i.rd())),
+        0x11 => format!("mthi {}", reg(i.rs())),
+        0x12 => format!("mflo {}", reg(i.rd())),
+        0x13 => format!("mtlo {}", reg(i.rs())),
+        0x18 => multiply("mult"),
+        0x19 => multiply("multu"),
+        0x1a => multiply("div"),
+        0x1b => multiply("divu"),
+        0x20 => arithmetic("add"),
+        0x21 => arithmetic("addu"),
+        0x22 => arithmetic("sub"),
+        0x23 => arithmetic("subu"),
+        0x24 => arithmetic("and"),
+        0x25 => arithmetic("or"),
+        0x26 => arithmetic("xor"),
+        0x27 => arithmetic("nor"),
+        0x2a => arithmetic("slt"),
+        0x2b => arithmetic("sltu"),
+        _ => format!("illegal 0x{:08x}", i.0),
+    }
+}
+
+fn disassemble_cop0(i: Instruction) -> String {
+    match i.rs() {
+        0x00 => format!("mfc0 {}, {}", reg(i.rt()), cop0_reg(i.rd())),
+        0x04 => format!("mtc0 {}, {}", reg(i.rt()), cop0_reg(i.rd())),
+        0x10 => "rfe".to_string(),
+        _ => format!("cop0 0x{:07x}", i.target()),
+    }
+}
+
+fn disassemble_cop2(i: Instruction) -> String {
+    if i.rs() & 0x10 != 0 {
+        return disassemble_gte(i.target());
+    }
+
+    let data = format!("${}", GTE_DATA_REGISTERS[i.rd()]);
+    let control = format!("${}", GTE_CONTROL_REGISTERS[i.rd()]);
+
+    match i.rs() & 0x0f {
+        0x00 => format!("mfc2 {}, {}", reg(i.rt()), data),
+        0x02 => format!("cfc2 {}, {}", reg(i.rt()), control),
+        0x04 => format!("mtc2 {}, {}", reg(i.rt()), data),
+        0x06 => format!("ctc2 {}, {}", reg(i.rt()), control),
+        _ => format!("cop2 0x{:07x}", i.target()),
+    }
+}
+
+fn disassemble_gte(command: u32) -> String {
+    let sf = (command >> 19) & 0x1;
+    let lm = (command >> 10) & 0x1;
+
+    let name = match command & 0x3f {
+        0x01 => "rtps",
+        0x06 => "nclip",
+        0x0c => "op",
+        0x10 => "dpcs",
+        0x11 => "intpl",
+        0x12 => "mvmva",
+        0x13 => "ncds",
+        0x14 => "cdp",
+        0x16 => "ncdt",
+        0x1b => "nccs",
+        0x1c => "cc",
+        0x1e => "ncs",
+        0x20 => "nct",
+        0x28 => "sqr",
+        0x29 => "dcpl",
+        0x2a => "dpct",
+        0x2d => "avsz3",
+        0x2e => "avsz4",
+        0x30 => "rtpt",
+        0x3d => "gpf",
+        0x3e => "gpl",
+        0x3f => "ncct",
+        _ => return format!("cop2 0x{:07x}", command),
+    };
+
+    if name == "mvmva" {
+        let mx = ["rt", "ll", "lc", "mx3"][((command >> 17) & 0x3) as usize];
+        let v = ["v0", "v1", "v2", "ir"][((command >> 15) & 0x3) as usize];
+        let cv = ["tr", "bk", "fc", "none"][((command >> 13) & 0x3) as usize];
+
+        return format!("mvmva sf={}, mx={}, v={}, cv={}, lm={}", sf, mx, v, cv, lm);
+    }
+
+    format!("{} sf={}, lm={}", name, sf, lm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disassemble;
+
+    #[test]
+    fn instructions() {
+        let table = [
+            // Branches backwards
+            (0x8001_0010, 0x1085_fffc, "beq $a0, $a1, 0x80010004"),
+            (0x0000_1000, 0x0480_ffff, "bltz $a0, 0x00001000"),
+            // The jump region comes from the delay slot, not the jump itself
+            (0x8fff_fffc, 0x0800_0100, "j 0x90000400"),
+            (0x8fff_fffc, 0x0c00_0100, "jal 0x90000400"),
+            // Only rt values of 0x10 and 0x11 link, the rest are plain branches
+            (0x0000_0000, 0x0490_0001, "bltzal $a0, 0x00000008"),
+            (0x0000_0000, 0x0491_0001, "bgezal $a0, 0x00000008"),
+            (0x0000_0000, 0x0492_0001, "bltz $a0, 0x00000008"),
+            (0x0000_0000, 0x0493_0001, "bgez $a0, 0x00000008"),
+            (0x0000_0000, 0x0481_0001, "bgez $a0, 0x00000008"),
+            // jalr only shows rd when it is not $ra
+            (0x0000_0000, 0x0100_f809, "jalr $t0"),
+            (0x0000_0000, 0x0100_1009, "jalr $v0, $t0"),
+            (0x0000_0000, 0x4a00_0012, "mvmva sf=0, mx=rt, v=v0, cv=tr, lm=0"),
+            (0x0000_0000, 0x4a0b_e412, "mvmva sf=1, mx=ll, v=ir, cv=none, lm=1"),
+            (0x0000_0000, 0x4a48_6012, "mvmva sf=1, mx=rt, v=v0, cv=none, lm=0"),
+            (0x0000_0000, 0xfc00_0000, "illegal 0xfc000000"),
+            (0x0000_0000, 0x0000_0001, "illegal 0x00000001"),
+        ];
+
+        for &(address, instruction, expected) in table.iter() {
+            assert_eq!(disassemble(address, instruction), expected, "0x{:08x} at 0x{:08x}", instruction, address);
+        }
+    }
+}
@@ -23,6 +23,8 @@ pub enum Direction {
     FromRam,
 }
 
+// Named the way the hardware documentation names them
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub enum DmacPort {
     MDECIn,
@@ -514,7 +516,7 @@ impl Dmac {
 
     pub fn chopping_enabled(&self) -> bool {
         let channel = DmacPort::from(self.active_port.unwrap());
-        return self.channels[channel].chopping_enabled;
+        self.channels[channel].chopping_enabled
     }
 
     pub fn tick_gap(&mut self, ticks: usize) {
@@ -868,14 +868,12 @@ impl Gte {
 
         self.push_sz(sz3);
 
-        let h_div_sz;
-
-        if sz3 > (self.h / 2) {
-            h_div_sz = Gte::divide(self.h, sz3);
+        let h_div_sz = if sz3 > (self.h / 2) {
+            Gte::divide(self.h, sz3)
         } else {
             self.flags |= 0x2_0000;
-            h_div_sz = 0x1_ffff;
-        }
+            0x1_ffff
+        };
 
         let ir1 = self.ir[1] as i64;
         let ir2 = self.ir[2] as i64;
@@ -1627,7 +1625,7 @@ impl Gte {
             17 => self.sz_fifo[1] as u32,
             18 => self.sz_fifo[2] as u32,
             19 => self.sz_fifo[3] as u32,
-            20 | 21 | 22 => {
+            20..=22 => {
                 let mut value = 0;
 
                 value |= (self.rgb_fifo[index - 20].c as u32) << 24;
@@ -1721,7 +1719,7 @@ impl Gte {
             17 => self.sz_fifo[1] = value as u16,
             18 => self.sz_fifo[2] = value as u16,
             19 => self.sz_fifo[3] = value as u16,
-            20 | 21 | 22 => {
+            20..=22 => {
                 self.rgb_fifo[index - 20].r = value as u8;
                 self.rgb_fifo[index - 20].g = (value >> 8) as u8;
                 self.rgb_fifo[index - 20].b = (value >> 16) as u8;
@@ -23,7 +23,7 @@ impl Instruction {
     }
 
     pub fn imm(self) -> u32 {
-        (self.0 & 0xffff) as u32
+        self.0 & 0xffff
     }
 
     pub fn imm_se(self) -> u32 {
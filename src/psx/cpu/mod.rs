@@ -1,18 +1,41 @@
+mod bios_tracer;
 mod cop0;
+mod debugger;
+mod disassembler;
 mod dmac;
 mod gte;
 mod instruction;
+mod tracer;
+
+use std::mem;
 
 use serde::{Deserialize, Serialize};
 use serde_big_array::BigArray;
 
 use super::bus::{Bus, BusWidth};
+use super::hle;
 use super::timekeeper::Timekeeper;
 
+pub use self::bios_tracer::{function_name, BiosTracer};
 use self::cop0::{Cop0, Exception};
+pub use self::debugger::{Debugger, StopReason, WatchKind};
+pub use self::disassembler::disassemble;
 use self::dmac::Dmac;
 use self::gte::Gte;
 use self::instruction::Instruction;
+pub use self::tracer::{TraceTrigger, Tracer};
+
+// What a call to run_instruction did
+#[derive(Clone, Copy, PartialEq)]
+enum Step {
+    // The DMA controller had the bus
+    Stalled,
+
+    // An exception was taken before the instruction at pc could run
+    Exception,
+
+    Executed,
+}
 
 #[derive(Clone, Copy, Deserialize, Serialize)]
 struct ICacheLine {
@@ -72,6 +95,17 @@ pub struct R3000A {
     gte: Gte,
 
     dmac: Dmac,
+
+    hle: bool,
+
+    #[serde(skip)]
+    debugger: Debugger,
+
+    #[serde(skip)]
+    tracer: Option<Box<Tracer>>,
+
+    #[serde(skip)]
+    bios_tracer: Option<Box<BiosTracer>>,
 }
 
 impl R3000A {
@@ -102,6 +136,14 @@ impl R3000A {
             gte: Gte::new(),
 
             dmac: Dmac::new(),
+
+            hle: false,
+
+            debugger: Debugger::default(),
+
+            tracer: None,
+
+            bios_tracer: None,
         }
     }
 
@@ -113,7 +155,74 @@ impl R3000A {
         self.cop0.reset();
     }
 
-    pub fn run(&mut self, bus: &mut Bus, tk: &mut Timekeeper) {
+    pub fn run(&mut self, bus: &mut Bus, tk: &mut Timekeeper) -> bool {
+        let step = match self.tracer.is_some() {
+            true => self.run_traced(bus, tk),
+            false => self.run_instruction(bus, tk),
+        };
+
+        let executed = step != Step::Stalled;
+
+        if executed {
+            if let Some(bios_tracer) = self.bios_tracer.as_mut() {
+                bios_tracer.update(self.pc, &self.regs, bus);
+            }
+        }
+
+        executed
+    }
+
+    fn run_traced(&mut self, bus: &mut Bus, tk: &mut Timekeeper) -> Step {
+        let pc = self.pc;
+        let before = self.trace_registers();
+
+        let step = self.run_instruction(bus, tk);
+        let after = self.trace_registers();
+        let instruction = self.current_instruction;
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            match step {
+                Step::Stalled => (),
+                Step::Exception => tracer.record_exception(),
+                Step::Executed => tracer.record(pc, instruction, &before, &after),
+            }
+        }
+
+        step
+    }
+
+    fn trace_registers(&self) -> [u32; 34] {
+        let mut registers = [0; 34];
+
+        registers[..32].copy_from_slice(&self.regs);
+        registers[32] = self.hi;
+        registers[33] = self.lo;
+
+        registers
+    }
+
+    // Debugging tools are not part of save states, keep them across loads
+    pub fn take_debug_state(&mut self, other: &mut R3000A) {
+        mem::swap(&mut self.debugger, &mut other.debugger);
+        mem::swap(&mut self.tracer, &mut other.tracer);
+        mem::swap(&mut self.bios_tracer, &mut other.bios_tracer);
+    }
+
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer.map(Box::new);
+    }
+
+    pub fn set_bios_tracer(&mut self, bios_tracer: Option<BiosTracer>) {
+        self.bios_tracer = bios_tracer.map(Box::new);
+    }
+
+    pub fn trace_frame(&mut self) {
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.next_frame();
+        }
+    }
+
+    fn run_instruction(&mut self, bus: &mut Bus, tk: &mut Timekeeper) -> Step {
         if self.dmac.active() {
             if self.dmac.gap_started() {
                 tk.sync_dmac();
@@ -124,12 +233,12 @@ impl R3000A {
                 self.dmac.tick_gap(cycles);
 
                 if !self.dmac.chopping_enabled() {
-                    return;
+                    return Step::Stalled;
                 }
             } else {
                 let dma_time = self.dmac.tick(bus);
                 tk.tick(dma_time as u64);
-                return;
+                return Step::Stalled;
             }
         }
 
@@ -147,7 +256,7 @@ impl R3000A {
 
             self.execute_load_delay();
 
-            return;
+            return Step::Exception;
         }
 
         self.update_irq(bus);
@@ -168,14 +277,14 @@ impl R3000A {
             }
 
             self.execute_load_delay();
-            return;
+            return Step::Exception;
         }
 
         if cop0_break {
             self.cop0_break();
 
             self.execute_load_delay();
-            return;
+            return Step::Exception;
         }
 
         self.branch_delay = false;
@@ -185,7 +294,7 @@ impl R3000A {
             self.enter_exception(Exception::IBusError);
 
             self.execute_load_delay();
-            return;
+            return Step::Exception;
         }
 
         self.pc = self.new_pc;
@@ -193,10 +302,171 @@ impl R3000A {
 
         if ins == 0 {
             self.execute_load_delay();
-            return;
+            return Step::Executed;
         }
 
         self.execute(bus, tk, instruction);
+
+        Step::Executed
+    }
+
+    pub fn debugger(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    // Checked after every run() when a debugger is attached. Breakpoints stop
+    // before the instruction at the breakpoint is executed.
+    pub fn debug_stop(&mut self, executed: bool) -> Option<StopReason> {
+        if let Some(hit) = self.debugger.take_hit() {
+            return Some(hit);
+        }
+
+        if executed && self.debugger.check_breakpoint(R3000A::translate_address(self.pc)) {
+            return Some(StopReason::Breakpoint);
+        }
+
+        None
+    }
+
+    // Register numbering follows GDB's MIPS layout, with EPC appended
+    pub fn debug_register(&self, index: usize) -> Option<u32> {
+        let value = match index {
+            0..=31 => self.regs[index],
+            32 => self.cop0.read(12),
+            33 => self.lo,
+            34 => self.hi,
+            35 => self.cop0.read(8),
+            36 => self.cop0.read(13),
+            37 => self.pc,
+            38..=71 => 0,
+            72 => self.cop0.read(14),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    pub fn set_debug_register(&mut self, index: usize, value: u32) -> bool {
+        match index {
+            0..=31 => self.set_reg(index, value),
+            32 => self.cop0.write(12, value),
+            33 => self.lo = value,
+            34 => self.hi = value,
+            35 => self.cop0.set_bad_vaddr(value),
+            36 => self.cop0.write(13, value),
+            37 => {
+                self.pc = value;
+                self.new_pc = value.wrapping_add(4);
+            },
+            38..=71 => (),
+            72 => self.cop0.set_epc(value),
+            _ => return false,
+        };
+
+        true
+    }
+
+    pub fn flush_icache(&mut self) {
+        for line in self.icache.lines.iter_mut() {
+            line.valid = 4;
+        }
+    }
+
+    pub fn set_hle(&mut self, enabled: bool) {
+        self.hle = enabled;
+    }
+
+    pub fn current_pc(&self) -> u32 {
+        self.current_pc
+    }
+
+    pub fn hi_lo(&self) -> (u32, u32) {
+        (self.hi, self.lo)
+    }
+
+    pub fn set_hi_lo(&mut self, hi: u32, lo: u32) {
+        self.hi = hi;
+        self.lo = lo;
+    }
+
+    pub fn status(&self) -> u32 {
+        self.cop0.read(12)
+    }
+
+    pub fn set_status(&mut self, value: u32) {
+        self.cop0.write(12, value);
+    }
+
+    pub fn cause(&self) -> u32 {
+        self.cop0.read(13)
+    }
+
+    pub fn epc(&self) -> u32 {
+        self.cop0.read(14)
+    }
+
+    pub fn jump(&mut self, address: u32) {
+        self.pc = address;
+        self.new_pc = address.wrapping_add(4);
+
+        self.branch_delay = false;
+        self.branch_taken = false;
+    }
+
+    // Runs guest code until it returns to return_address, with interrupts
+    // disabled. Registers are restored afterwards and $v0 is returned.
+    pub fn call_function(&mut self,
+                         bus: &mut Bus,
+                         tk: &mut Timekeeper,
+                         address: u32,
+                         args: &[u32],
+                         return_address: u32) -> u32 {
+        let (pc, new_pc) = (self.pc, self.new_pc);
+        let (current_pc, current_instruction) = (self.current_pc, self.current_instruction);
+        let (hi, lo) = (self.hi, self.lo);
+        let regs = self.regs;
+        let status = self.cop0.read(12);
+
+        self.cop0.write(12, status & !0x1);
+
+        for (i, &arg) in args.iter().take(4).enumerate() {
+            self.regs[4 + i] = arg;
+        }
+
+        self.regs[31] = return_address;
+        self.jump(address);
+
+        let mut instructions = 0;
+
+        while self.pc != return_address {
+            self.run_instruction(bus, tk);
+
+            if tk.elapsed() >= 128 {
+                tk.sync_all(bus);
+            }
+
+            instructions += 1;
+
+            if instructions > 100_000_000 {
+                println!("[CPU] [ERROR] Function at 0x{:08x} did not return", address);
+                break;
+            }
+        }
+
+        let result = self.regs[2];
+
+        self.execute_load_delay();
+        self.cop0.write(12, status);
+
+        self.regs = regs;
+        self.hi = hi;
+        self.lo = lo;
+        self.current_pc = current_pc;
+        self.current_instruction = current_instruction;
+        self.jump(pc);
+        self.new_pc = new_pc;
+
+        result
     }
 
     fn update_irq(&mut self, bus: &mut Bus) {
@@ -252,6 +522,7 @@ impl R3000A {
             0x39 => self.op_swcx(bus, tk, i.rt(), i.rs(), i.imm_se()),
             0x3a => self.op_swc2(bus, tk, i.rt(), i.rs(), i.imm_se()),
             0x3b => self.op_swcx(bus, tk, i.rt(), i.rs(), i.imm_se()),
+            0x3f if self.hle => self.op_hle(bus, tk, i.target()),
             _ => self.op_illegal(),
         }
     }
@@ -697,7 +968,8 @@ impl R3000A {
             0x00 => self.op_mfc0(i.rd(), i.rt()),
             0x04 => self.op_mtc0(i.rd(), i.rt()),
             0x10 => self.op_rfe(),
-            _ => panic!("[CPU] [ERROR] Unrecognised instruction 0x{:08x}", i.0),
+            _ => panic!("[CPU] [ERROR] Unrecognised instruction 0x{:08x} ({}) at 0x{:08x}",
+                        i.0, disassemble(self.current_pc, i.0), self.current_pc),
         };
     }
 
@@ -740,7 +1012,8 @@ impl R3000A {
                 0x02 => self.op_cfc2(i.rd(), i.rt()),
                 0x04 => self.op_mtc2(i.rd(), i.rt()),
                 0x06 => self.op_ctc2(i.rd(), i.rt()),
-                _ => panic!("[CPU] [ERROR] Unrecognised instruction 0x{:08x}", i.0),
+                _ => panic!("[CPU] [ERROR] Unrecognised instruction 0x{:08x} ({}) at 0x{:08x}",
+                            i.0, disassemble(self.current_pc, i.0), self.current_pc),
             },
             0x10 => self.op_cop2_command(i.target()),
             _ => unreachable!(),
@@ -1221,7 +1494,6 @@ impl R3000A {
 
         if err {
             self.enter_exception(Exception::DBusError);
-            return;
         }
     }
 
@@ -1285,7 +1557,6 @@ impl R3000A {
 
         if err {
             self.enter_exception(Exception::DBusError);
-            return;
         }
     }
 
@@ -1317,10 +1588,16 @@ impl R3000A {
 
         if err {
             self.enter_exception(Exception::DBusError);
-            return;
         }
     }
 
+    // Traps into the HLE kernel, only decoded when it replaces the BIOS
+    fn op_hle(&mut self, bus: &mut Bus, tk: &mut Timekeeper, code: u32) {
+        self.execute_load_delay();
+
+        hle::trap(self, bus, tk, code);
+    }
+
     fn op_illegal(&mut self) {
         self.execute_load_delay();
 
@@ -1367,6 +1644,10 @@ impl R3000A {
                println!("[CPU] [WARN] Unexpected exception: {:#?}", exception);
            }
 
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.exception(exception, epc);
+        }
+
         self.cop0.enter_exception(epc, exception, bd, bt, cop);
 
         if self.cop0.exception_vectors() {
@@ -1424,7 +1705,7 @@ impl R3000A {
                 let mut address = (physical_address & !0xf) + (0x4 * index as u32);
 
                 for i in index..4 {
-                    let data = bus.load(tk, BusWidth::WORD, address);
+                    let data = bus.fetch(tk, address);
 
                     if data.1 {
                         return (0, true);
@@ -1444,7 +1725,7 @@ impl R3000A {
         }
 
         tk.tick(5);
-        bus.load(tk, BusWidth::WORD, physical_address)
+        bus.fetch(tk, physical_address)
     }
 
     pub fn translate_address(virtual_address: u32) -> u32 {
@@ -1471,6 +1752,10 @@ impl R3000A {
             width: BusWidth, address: u32) -> (u32, bool) {
         let physical_address = R3000A::translate_address(address);
 
+        if self.debugger.enabled() {
+            self.debugger.check_access(address, width.size(), false);
+        }
+
         if self.cop0.isolate_cache() {
             let line = ((address & 0xff0) >> 4) as usize;
             let index = ((address & 0xc) >> 2) as usize;
@@ -1486,8 +1771,20 @@ impl R3000A {
 
         tk.tick(5);
 
+        if bus.monitor().enabled() {
+            bus.monitor().set_pc(self.current_pc);
+        }
+
         match physical_address {
-            0x1f80_1080..=0x1f80_10ff => (self.dmac.read(address), false),
+            0x1f80_1080..=0x1f80_10ff => {
+                let value = self.dmac.read(address);
+
+                if bus.monitor().enabled() {
+                    bus.monitor().access(physical_address, &width, value, false);
+                }
+
+                (value, false)
+            },
             0xfffe_0130 => (self.cache_control, false),
             _ => bus.load(tk, width, physical_address),
         }
@@ -1524,6 +1821,10 @@ impl R3000A {
              width: BusWidth, address: u32, value: u32) -> bool {
         let physical_address = R3000A::translate_address(address);
 
+        if self.debugger.enabled() {
+            self.debugger.check_access(address, width.size(), true);
+        }
+
         if self.cop0.isolate_cache() {
             let line = ((address & 0xff0) >> 4) as usize;
             let index = ((address & 0xc) >> 2) as usize;
@@ -1545,8 +1846,16 @@ impl R3000A {
 
         tk.tick(5);
 
+        if bus.monitor().enabled() {
+            bus.monitor().set_pc(self.current_pc);
+        }
+
         match physical_address {
             0x1f80_1080..=0x1f80_10ff => {
+                if bus.monitor().enabled() {
+                    bus.monitor().access(physical_address, &width, value, true);
+                }
+
                 self.dmac.write(bus.intc(), address, value);
                 false
             },
@@ -0,0 +1,326 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::cop0::Exception;
+use super::disassembler::disassemble;
+
+const REGISTER_NAMES: [&str; 34] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3",
+    "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7",
+    "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7",
+    "t8", "t9", "k0", "k1", "gp", "sp", "fp", "ra",
+    "hi", "lo",
+];
+
+// An instruction can write at most a GPR, HI/LO and a delayed load
+const MAX_CHANGES: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceTrigger {
+    Pc(u32),
+    Frame(u64),
+    Instruction(u64),
+}
+
+// Triggers are written as pc:ADDRESS (hex), frame:N or insn:N
+impl FromStr for TraceTrigger {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<TraceTrigger, String> {
+        let error = || format!("invalid trace trigger: {}", value);
+
+        let (kind, argument) = value.split_once(':').ok_or_else(error)?;
+
+        match kind {
+            "pc" => {
+                let address = argument.trim_start_matches("0x");
+                u32::from_str_radix(address, 16).map(TraceTrigger::Pc).map_err(|_| error())
+            },
+            "frame" => argument.parse().map(TraceTrigger::Frame).map_err(|_| error()),
+            "insn" => argument.parse().map(TraceTrigger::Instruction).map_err(|_| error()),
+            _ => Err(error()),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct TraceEntry {
+    pc: u32,
+
+    // None for an exception taken in place of the instruction at pc
+    instruction: Option<u32>,
+
+    changes: [(u8, u32); MAX_CHANGES],
+    change_count: usize,
+
+    exception: Option<(Exception, u32)>,
+}
+
+impl TraceEntry {
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        if let Some(instruction) = self.instruction {
+            let mut line = format!("{:08x}: {:08x}  {:<40}", self.pc, instruction, disassemble(self.pc, instruction));
+
+            for &(index, value) in self.changes[..self.change_count].iter() {
+                line.push_str(&format!(" {}={:08x}", REGISTER_NAMES[index as usize], value));
+            }
+
+            writeln!(writer, "{}", line.trim_end())?;
+        }
+
+        if let Some((exception, epc)) = self.exception {
+            writeln!(writer, "-- {:?} exception, epc {:08x} --", exception, epc)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Logs executed instructions and the registers they changed. In ring mode
+// only the most recent instructions are kept, and they are written out when
+// an unexpected exception is raised, on a panic or when the tracer is dropped.
+pub struct Tracer {
+    writer: BufWriter<File>,
+
+    ring: Option<VecDeque<TraceEntry>>,
+    ring_size: usize,
+
+    start: Option<TraceTrigger>,
+    stop: Option<TraceTrigger>,
+
+    active: bool,
+    finished: bool,
+
+    exception: Option<(Exception, u32)>,
+
+    instructions: u64,
+    frames: u64,
+}
+
+impl Tracer {
+    pub fn create(path: &Path,
+                  start: Option<TraceTrigger>,
+                  stop: Option<TraceTrigger>,
+                  ring_size: Option<usize>) -> io::Result<Tracer> {
+        let writer = BufWriter::new(File::create(path)?);
+
+        Ok(Tracer {
+            writer,
+
+            ring: ring_size.map(VecDeque::with_capacity),
+            ring_size: ring_size.unwrap_or(0),
+
+            start,
+            stop,
+
+            active: start.is_none(),
+            finished: false,
+
+            exception: None,
+
+            instructions: 0,
+            frames: 0,
+        })
+    }
+
+    fn triggered(&self, trigger: Option<TraceTrigger>, pc: u32) -> bool {
+        match trigger {
+            Some(TraceTrigger::Pc(address)) => address == pc,
+            Some(TraceTrigger::Frame(frame)) => self.frames >= frame,
+            Some(TraceTrigger::Instruction(count)) => self.instructions >= count,
+            None => false,
+        }
+    }
+
+    fn update_triggers(&mut self, pc: u32) {
+        if !self.active && !self.finished && self.triggered(self.start, pc) {
+            self.active = true;
+        }
+
+        if self.active && self.triggered(self.stop, pc) {
+            self.active = false;
+            self.finished = true;
+
+            self.flush();
+        }
+    }
+
+    // The register snapshot is 32 GPRs followed by HI and LO
+    pub fn record(&mut self, pc: u32, instruction: u32, before: &[u32; 34], after: &[u32; 34]) {
+        let exception = self.exception.take();
+
+        self.update_triggers(pc);
+        self.instructions += 1;
+
+        if !self.active {
+            return;
+        }
+
+        let mut entry = TraceEntry {
+            pc,
+            instruction: Some(instruction),
+
+            changes: [(0, 0); MAX_CHANGES],
+            change_count: 0,
+
+            exception,
+        };
+
+        for (i, (&old, &new)) in before.iter().zip(after.iter()).enumerate() {
+            if old != new && entry.change_count < MAX_CHANGES {
+                entry.changes[entry.change_count] = (i as u8, new);
+                entry.change_count += 1;
+            }
+        }
+
+        self.push(entry);
+    }
+
+    // An exception taken before the instruction at pc could run gets an
+    // entry of its own, it does not count as an instruction
+    pub fn record_exception(&mut self) {
+        let exception = match self.exception.take() {
+            Some(exception) if self.active => exception,
+            _ => return,
+        };
+
+        self.push(TraceEntry {
+            pc: exception.1,
+            instruction: None,
+
+            changes: [(0, 0); MAX_CHANGES],
+            change_count: 0,
+
+            exception: Some(exception),
+        });
+    }
+
+    fn push(&mut self, entry: TraceEntry) {
+        let exception = entry.exception;
+
+        match self.ring.as_mut() {
+            Some(ring) => {
+                if ring.len() >= self.ring_size {
+                    ring.pop_front();
+                }
+
+                ring.push_back(entry);
+
+                // Interrupts and syscalls are routine, anything else is worth a look
+                if let Some((exception, _)) = exception {
+                    if exception != Exception::Interrupt && exception != Exception::Syscall {
+                        self.dump_ring();
+                    }
+                }
+            },
+            None => {
+                if let Err(e) = entry.write(&mut self.writer) {
+                    println!("[TRACE] [ERROR] Unable to write trace: {}", e);
+                    self.finished = true;
+                    self.active = false;
+                }
+            },
+        }
+    }
+
+    pub fn next_frame(&mut self) {
+        self.frames += 1;
+    }
+
+    // Attached to the instruction that raised it, which is recorded next,
+    // or to an entry of its own when no instruction ran
+    pub fn exception(&mut self, exception: Exception, epc: u32) {
+        self.exception = Some((exception, epc));
+    }
+
+    fn dump_ring(&mut self) {
+        if let Some(ring) = self.ring.as_mut() {
+            for entry in ring.drain(..) {
+                if entry.write(&mut self.writer).is_err() {
+                    break;
+                }
+            }
+        }
+
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            println!("[TRACE] [ERROR] Unable to write trace: {}", e);
+        }
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            let _ = writeln!(self.writer, "-- panic --");
+        }
+
+        self.dump_ring();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::super::cop0::Exception;
+    use super::{TraceTrigger, Tracer};
+
+    #[test]
+    fn parse_trigger() {
+        let cases = [
+            ("pc:80010000", TraceTrigger::Pc(0x8001_0000)),
+            ("pc:0xBFC00000", TraceTrigger::Pc(0xbfc0_0000)),
+            ("frame:0", TraceTrigger::Frame(0)),
+            ("frame:120", TraceTrigger::Frame(120)),
+            ("insn:1000000", TraceTrigger::Instruction(1_000_000)),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(text.parse::<TraceTrigger>(), Ok(expected), "{:?}", text);
+        }
+
+        let invalid = ["", "pc", "pc:", "pc:xyz", "pc:100000000", "frame:-1", "frame:0x10", "insn:", "cycle:10", "PC:80010000"];
+
+        for text in invalid {
+            assert!(text.parse::<TraceTrigger>().is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn exception_without_instruction() {
+        let path = env::temp_dir().join(format!("rpsx-trace-{}.txt", process::id()));
+
+        let mut tracer = Tracer::create(&path, None, None, None).unwrap();
+        let mut after = [0; 34];
+        after[2] = 1;
+
+        // addiu v0, zero, 1
+        tracer.record(0x8001_0000, 0x2402_0001, &[0; 34], &after);
+
+        tracer.exception(Exception::Interrupt, 0x8001_0004);
+        tracer.record_exception();
+
+        // Nothing pending, so nothing is written
+        tracer.record_exception();
+        drop(tracer);
+
+        let trace = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let lines: Vec<&str> = trace.lines().collect();
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("80010000: 24020001"));
+        assert!(lines[0].ends_with("v0=00000001"));
+        assert_eq!(lines[1], "-- Interrupt exception, epc 80010004 --");
+    }
+}
@@ -1,5 +1,3 @@
-use std::str;
-
 use serde::{Deserialize, Serialize};
 
 const DUART_SRA: u32 = 0x1f802021;
@@ -10,23 +8,46 @@ const DUART_SR_TXRDY: u8 = 0x4;
 #[derive(Deserialize, Serialize)]
 pub struct Exp2 {
     tx_buf: Vec<u8>,
+
+    #[serde(skip)]
+    capture: bool,
+    #[serde(skip)]
+    output: String,
 }
 
 impl Exp2 {
     pub fn new() -> Exp2 {
         Exp2 {
             tx_buf: Vec::new(),
+
+            capture: false,
+            output: String::new(),
         }
     }
 
-    fn tx_byte(&mut self, byte: u8) {
+    pub fn set_capture(&mut self, enabled: bool) {
+        self.capture = enabled;
+    }
+
+    pub fn take_output(&mut self) -> String {
+        std::mem::take(&mut self.output)
+    }
+
+    pub fn tx_byte(&mut self, byte: u8) {
         if byte == 0xd {
             return;
         }
 
         if byte == 0xa {
-            if self.tx_buf.len() != 0 {
-                println!("{}", str::from_utf8(&self.tx_buf).unwrap());
+            if !self.tx_buf.is_empty() {
+                let line = String::from_utf8_lossy(&self.tx_buf);
+                println!("{}", line);
+
+                if self.capture {
+                    self.output.push_str(&line);
+                    self.output.push('\n');
+                }
+
                 self.tx_buf.clear();
             }
 
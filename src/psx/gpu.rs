@@ -1,6 +1,8 @@
 use std::cmp;
 use std::fs::File;
 use std::io::Write;
+use std::mem;
+use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 use serde_big_array::BigArray;
@@ -13,11 +15,23 @@ use super::intc::{Intc, Interrupt};
 use super::rasteriser::{Colour, Vector2i, Vector3i};
 use super::timers::Timers;
 
-// TODO: selectable dithering
-
-#[allow(dead_code)]
+// Offsets added to 8-bit colours before they are cut down to 5 bits
 pub const DITHER_TABLE: [i32; 16] = [-4, 0, -3, 1, 2, -2, 3, -1, -3, 1, -4, 0, 3, -1, 2, -2];
 
+// Lines per frame alternate between these and one fewer for the half line
+// of each field, the last lines of a frame are vertical blanking
+const NTSC_LINES: usize = 263;
+const NTSC_VBLANK_LINES: usize = 20;
+const PAL_LINES: usize = 314;
+const PAL_VBLANK_LINES: usize = 25;
+
+// The GPU is clocked at 11/7 of the CPU's 33.8688MHz
+const VIDEO_CLOCK: f64 = 33_868_800.0 * 11.0 / 7.0;
+
+// First line of the picture on a TV with the usual GP1(07h) settings
+const NTSC_DISPLAY_START: u32 = 0x10;
+const PAL_DISPLAY_START: u32 = 0x23;
+
 pub const CMD_SIZE: [usize; 256] = [
     1, 1, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
     4, 4, 4, 4, 7, 7, 7, 7, 5, 5, 5, 5, 9, 9, 9, 9, 6, 6, 6, 6, 9, 9, 9, 9, 8, 8, 8, 8, 12, 12, 12,
@@ -155,6 +169,38 @@ impl CacheEntry {
     }
 }
 
+// How shaded colours are reduced to what VRAM holds
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Dithering {
+    // The dither matrix whenever GP0(E1h) asks for it, like the console
+    #[default]
+    Hardware,
+    // Plain truncation to 15 bits
+    Off,
+    // No dithering and the full 24 bits kept for display and blending
+    TrueColour,
+}
+
+impl FromStr for Dithering {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Dithering, String> {
+        match value {
+            "hardware" => Ok(Dithering::Hardware),
+            "off" => Ok(Dithering::Off),
+            "true-colour" => Ok(Dithering::TrueColour),
+            _ => Err(format!("unknown dithering mode: {}", value)),
+        }
+    }
+}
+
+// A GP0 command as it was submitted, for debugging views
+#[derive(Clone)]
+pub struct GpuCommandRecord {
+    pub words: Vec<u32>,
+    pub drawing_offset: (i32, i32),
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct Gpu {
     vram: Box<[u8]>,
@@ -251,6 +297,20 @@ pub struct Gpu {
     vertical_display_end: u32,
 
     frame_complete: bool,
+
+    #[serde(skip)]
+    capture_commands: bool,
+    #[serde(skip)]
+    commands: Vec<GpuCommandRecord>,
+    #[serde(skip)]
+    captured_commands: Vec<GpuCommandRecord>,
+
+    #[serde(skip)]
+    dithering: Dithering,
+    // 24-bit copies of what was drawn to VRAM, only trusted while the VRAM
+    // pixel still matches so other writes need no tracking
+    #[serde(skip)]
+    true_colour: Vec<u32>,
 }
 
 impl Gpu {
@@ -263,7 +323,7 @@ impl Gpu {
 
             scanline: 0,
             video_cycle: 0,
-            lines: 263,
+            lines: NTSC_LINES,
 
             dotclock_cycle: 0,
 
@@ -346,6 +406,13 @@ impl Gpu {
             vertical_display_end: 256,
 
             frame_complete: false,
+
+            capture_commands: false,
+            commands: Vec::new(),
+            captured_commands: Vec::new(),
+
+            dithering: Dithering::Hardware,
+            true_colour: Vec::new(),
         }
     }
 
@@ -368,27 +435,31 @@ impl Gpu {
 
             timers.tick_hblank(intc);
 
-            if self.vres == 240 && self.vertical_interlace {
+            if !self.double_height() && self.vertical_interlace {
                 self.interlace_line = !self.interlace_line;
             }
 
             self.scanline += 1;
 
-            if self.scanline == (self.lines - 20) {
+            if self.scanline == self.vblank_start() {
                 self.frame_complete = true;
                 intc.assert_irq(Interrupt::Vblank);
-            }
 
-            if self.scanline == self.lines {
-                if self.lines == 263 {
-                    self.lines = 262;
-                } else {
-                    self.lines = 263;
+                if self.capture_commands {
+                    self.captured_commands = mem::take(&mut self.commands);
                 }
+            }
+
+            // Switching video mode mid frame can leave us past the new length
+            if self.scanline >= self.lines {
+                self.lines = match self.lines == self.frame_lines() {
+                    true => self.frame_lines() - 1,
+                    false => self.frame_lines(),
+                };
 
                 self.scanline = 0;
 
-                if self.vres == 480 && self.vertical_interlace {
+                if self.double_height() && self.vertical_interlace {
                     self.interlace_line = !self.interlace_line;
                 }
 
@@ -421,6 +492,12 @@ impl Gpu {
         }
     }
 
+    // Frames per second of emulated time, fields alternate with a half line
+    pub fn refresh_rate(&self) -> f64 {
+        let lines = self.frame_lines() as f64 - 0.5;
+        VIDEO_CLOCK / (self.horizontal_length() as f64 * lines)
+    }
+
     fn horizontal_length(&self) -> usize {
         match self.video_mode {
             true => 3406,
@@ -428,13 +505,31 @@ impl Gpu {
         }
     }
 
+    fn frame_lines(&self) -> usize {
+        match self.video_mode {
+            true => PAL_LINES,
+            false => NTSC_LINES,
+        }
+    }
+
+    fn vblank_start(&self) -> usize {
+        match self.video_mode {
+            true => self.lines - PAL_VBLANK_LINES,
+            false => self.lines - NTSC_VBLANK_LINES,
+        }
+    }
+
+    fn double_height(&self) -> bool {
+        matches!(self.vres, 480 | 576)
+    }
+
     pub fn in_hblank(&self) -> bool {
         self.video_cycle < self.horizontal_display_start as usize
             || self.video_cycle >= self.horizontal_display_end as usize
     }
 
     pub fn in_vblank(&self) -> bool {
-        self.scanline >= (self.lines - 20)
+        self.scanline >= self.vblank_start()
     }
 
     pub fn get_dotclock(&self) -> u32 {
@@ -460,13 +555,11 @@ impl Gpu {
         let ystart = self.vertical_display_start;
         let yend = self.vertical_display_end;
 
-        let xdiff;
-
-        if xstart <= xend {
-            xdiff = xend - xstart;
+        let xdiff = if xstart <= xend {
+            xend - xstart
         } else {
-            xdiff = 50;
-        }
+            50
+        };
 
         let x = ((xdiff / dotclock) + 2) & !0x3;
         let mut y = yend - ystart;
@@ -478,6 +571,20 @@ impl Gpu {
         (x, y)
     }
 
+    // Height of the whole picture on a TV, the display area only covers part
+    // of it when games use fewer lines
+    pub fn get_standard_height(&self) -> u32 {
+        let height = match self.video_mode {
+            true => 288,
+            false => 240,
+        };
+
+        match self.vertical_interlace {
+            true => height * 2,
+            false => height,
+        }
+    }
+
     pub fn get_framebuffer(&self,
                            framebuffer: &mut [u8],
                            draw_full_vram: bool) {
@@ -487,14 +594,19 @@ impl Gpu {
             let (mut x, mut y) = self.get_display_origin();
 
             // Adjust start based on CRTC registers
-            x += (self.horizontal_display_start - 608) / self.get_dotclock();
-            y += (self.vertical_display_start - 16) * 2;
+            x += self.horizontal_display_start.saturating_sub(608) / self.get_dotclock();
+            let display_start = match self.video_mode {
+                true => PAL_DISPLAY_START,
+                false => NTSC_DISPLAY_START,
+            };
+
+            y += self.vertical_display_start.saturating_sub(display_start) * 2;
 
             (x, y)
         };
 
         let (w, h) = if draw_full_vram {
-            (0, 0)
+            (1024, 512)
         } else {
             self.get_display_size()
         };
@@ -503,23 +615,14 @@ impl Gpu {
 
         for y in ys..ys + h {
             for x in xs..xs + w {
-                let address = match !draw_full_vram && self.colour_depth {
-                    true => Gpu::vram_address_24bit(x, y),
-                    false => Gpu::vram_address(x, y),
+                let col = match !draw_full_vram && self.colour_depth {
+                    true => {
+                        let address = Gpu::vram_address_24bit(x, y);
+                        Colour::new(self.vram[address], self.vram[address + 1], self.vram[address + 2], false)
+                    },
+                    false => self.read_pixel(x, y),
                 };
 
-                let col;
-
-                if !draw_full_vram && self.colour_depth {
-                    let r = self.vram[address];
-                    let g = self.vram[address + 1];
-                    let b = self.vram[address + 2];
-                    col = Colour::new(r, g, b, false);
-                } else {
-                    let colour = LittleEndian::read_u16(&self.vram[address..]);
-                    col = Colour::from_u16(colour);
-                }
-
                 framebuffer[framebuffer_address] = col.r;
                 framebuffer[framebuffer_address + 1] = col.g;
                 framebuffer[framebuffer_address + 2] = col.b;
@@ -528,11 +631,42 @@ impl Gpu {
         }
     }
 
+    pub fn dithering(&self) -> Dithering {
+        self.dithering
+    }
+
+    pub fn set_dithering(&mut self, dithering: Dithering) {
+        if dithering == self.dithering {
+            return;
+        }
+
+        self.dithering = dithering;
+
+        self.true_colour = match dithering {
+            Dithering::TrueColour => vec![0; 1024 * 512],
+            _ => Vec::new(),
+        };
+    }
+
     pub fn dump_vram(&self) {
         let mut file = File::create("vram.bin").unwrap();
         file.write_all(&self.vram).unwrap();
     }
 
+    pub fn set_capture_commands(&mut self, enabled: bool) {
+        self.capture_commands = enabled;
+
+        if !enabled {
+            self.commands.clear();
+            self.captured_commands.clear();
+        }
+    }
+
+    // Commands executed during the last complete frame
+    pub fn captured_commands(&self) -> &[GpuCommandRecord] {
+        &self.captured_commands
+    }
+
     pub fn frame_complete(&mut self) -> bool {
         if self.frame_complete {
             self.frame_complete = false;
@@ -577,11 +711,7 @@ impl Gpu {
         value |= (self.vertical_interlace as u32) << 22;
         value |= (self.colour_depth as u32) << 21;
         value |= (self.video_mode as u32) << 20;
-        value |= match self.vres {
-            480 => 1 << 19,
-            240 => 0,
-            _ => unreachable!(),
-        };
+        value |= (self.double_height() as u32) << 19;
         value |= match self.hres {
             256 => 0x00,
             320 => 0x02,
@@ -620,7 +750,8 @@ impl Gpu {
         }
 
         if self.polyline {
-            if (word & 0x50005000) == 0x50005000 {
+            // Any word of the form 5xxx5xxx ends a polyline, usually 0x55555555
+            if (word & 0xf000_f000) == 0x5000_5000 {
                 self.polyline = false;
                 return;
             }
@@ -631,17 +762,16 @@ impl Gpu {
             self.polyline_remaining -= 1;
 
             if self.polyline_remaining == 0 {
-                let coords = [self.polyline_coord; 2];
+                let mut coords = [self.polyline_coord; 2];
                 let mut colours = [self.polyline_colour; 2];
 
                 if self.shaded {
                     colours[1] = Colour::from_u32(self.command_buffer[0]);
                 }
 
-                let _coord2 = self.command_buffer[1];
-                //coords[1] = self.get_coord(coord2);
+                coords[1] = self.to_coord(self.command_buffer[self.command_buffer_index - 1]);
 
-                //self.rasterise_line(coords, colours);
+                self.rasterise_line(coords, colours, self.shaded, self.semi_tranparent);
 
                 self.polyline_coord = coords[1];
                 self.polyline_colour = colours[1];
@@ -751,6 +881,13 @@ impl Gpu {
         let command_word = self.command_buffer[0];
         let command = command_word >> 24;
 
+        if self.capture_commands {
+            self.commands.push(GpuCommandRecord {
+                words: self.command_buffer[..self.command_buffer_index].to_vec(),
+                drawing_offset: (self.drawing_x_offset, self.drawing_y_offset),
+            });
+        }
+
         match command {
             0x00 => {} // NOP
             0x01 => self.invalidate_cache(),
@@ -987,7 +1124,10 @@ impl Gpu {
                 self.skip_masked_pixels = false;
                 self.set_mask_bit = false;
             }
-            0x01 => self.command_buffer_index = 0,
+            0x01 => {
+                self.command_buffer_index = 0;
+                self.polyline = false;
+            }
             0x02 => self.irq = false,
             0x03 => self.display_disable = (command_word & 0x1) != 0,
             0x04 => {
@@ -1017,9 +1157,11 @@ impl Gpu {
                 self.colour_depth = (command_word & 0x10) != 0;
                 self.video_mode = (command_word & 0x8) != 0;
 
-                self.vres = match (self.vertical_interlace, (command_word & 0x4) != 0) {
-                    (true, true) => 480,
-                    _ => 240,
+                self.vres = match (self.video_mode, self.vertical_interlace && (command_word & 0x4) != 0) {
+                    (false, false) => 240,
+                    (false, true) => 480,
+                    (true, false) => 288,
+                    (true, true) => 576,
                 };
 
                 self.hres = match ((command_word & 0x40) != 0, command_word & 0x3) {
@@ -1168,9 +1310,27 @@ impl Gpu {
 
         let shaded = (command & 0x10) != 0;
         let polyline = (command & 0x8) != 0;
-        let _transparency = (command & 0x2) != 0;
+        let transparency = (command & 0x2) != 0;
+
+        let mut colours = [Colour::from_u32(self.command_buffer[0]); 2];
+
+        let coords = match shaded {
+            true => {
+                colours[1] = Colour::from_u32(self.command_buffer[2]);
+                [self.to_coord(self.command_buffer[1]), self.to_coord(self.command_buffer[3])]
+            },
+            false => [self.to_coord(self.command_buffer[1]), self.to_coord(self.command_buffer[2])],
+        };
+
+        self.rasterise_line(coords, colours, shaded, transparency);
+
+        // Polylines carry on from the last vertex until the terminator
+        self.shaded = shaded;
+        self.semi_tranparent = transparency;
 
         self.polyline = polyline;
+        self.polyline_coord = coords[1];
+        self.polyline_colour = colours[1];
         self.polyline_remaining = match shaded {
             false => 1,
             true => 2,
@@ -1291,6 +1451,7 @@ impl Gpu {
         (y < 0) || ((x < 0) && (y == 0))
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn rasterise_triangle(&mut self,
                           vertices: &[Vector2i],
                           colours: &[Colour],
@@ -1358,6 +1519,8 @@ impl Gpu {
 
         let mut colour = c[0];
 
+        let dither = self.dithering_enabled() && (shaded || (textured && blend));
+
         while p.y < maxy {
             let mut w0 = w0_row;
             let mut w1 = w1_row;
@@ -1399,6 +1562,10 @@ impl Gpu {
                         output = texture;
                     }
 
+                    if dither {
+                        output = Gpu::dither(p, output);
+                    }
+
                     self.render_pixel(p, output, transparency, !textured);
                 }
 
@@ -1417,10 +1584,114 @@ impl Gpu {
         }
     }
 
+    // Steps along the major axis in 32.32 fixed point like the hardware, which
+    // draws both end points and drops lines 1024 or more wide or 512 tall
+    fn rasterise_line(&mut self,
+                      coords: [Vector2i; 2],
+                      colours: [Colour; 2],
+                      shaded: bool, transparency: bool) {
+        let (mut v0, mut v1) = (coords[0], coords[1]);
+        let (mut c0, mut c1) = (colours[0], colours[1]);
+
+        let dx = (v1.x - v0.x).abs();
+        let dy = (v1.y - v0.y).abs();
+
+        if dx >= 1024 || dy >= 512 {
+            return;
+        }
+
+        let k = cmp::max(dx, dy);
+
+        // Lines are always drawn left to right
+        if v0.x >= v1.x && k != 0 {
+            mem::swap(&mut v0, &mut v1);
+            mem::swap(&mut c0, &mut c1);
+        }
+
+        let step_x = Gpu::line_step(v1.x - v0.x, k);
+        let step_y = Gpu::line_step(v1.y - v0.y, k);
+
+        let mut x = ((v0.x as i64) << 32) | (1 << 31);
+        let mut y = ((v0.y as i64) << 32) | (1 << 31);
+
+        x -= 1024;
+
+        if step_y < 0 {
+            y -= 1024;
+        }
+
+        // Colours in 20.12 fixed point
+        let colour_step = |a: i32, b: i32| match k {
+            0 => 0,
+            _ => ((b - a) << 12) / k,
+        };
+
+        let step_r = colour_step(c0.r(), c1.r());
+        let step_g = colour_step(c0.g(), c1.g());
+        let step_b = colour_step(c0.b(), c1.b());
+
+        let mut r = (c0.r() << 12) | (1 << 11);
+        let mut g = (c0.g() << 12) | (1 << 11);
+        let mut b = (c0.b() << 12) | (1 << 11);
+
+        for _ in 0..=k {
+            let p = Vector2i::new(((x >> 32) as i32) & 0x7ff, ((y >> 32) as i32) & 0x7ff);
+
+            if (p.x >= self.drawing_x_begin) && (p.x <= self.drawing_x_end)
+               && (p.y >= self.drawing_y_begin) && (p.y <= self.drawing_y_end) {
+                let mut colour = c0;
+
+                if shaded {
+                    colour = Colour::new((r >> 12) as u8, (g >> 12) as u8, (b >> 12) as u8, false);
+
+                    if self.dithering_enabled() {
+                        colour = Gpu::dither(p, colour);
+                    }
+                }
+
+                self.render_pixel(p, colour, transparency, true);
+            }
+
+            x += step_x;
+            y += step_y;
+
+            r += step_r;
+            g += step_g;
+            b += step_b;
+        }
+    }
+
+    // Rounds away from zero so the far end point is reached exactly
+    fn line_step(delta: i32, k: i32) -> i64 {
+        if k == 0 {
+            return 0;
+        }
+
+        let k = k as i64;
+        let mut delta = (delta as i64) << 32;
+
+        if delta < 0 {
+            delta -= k - 1;
+        } else if delta > 0 {
+            delta += k - 1;
+        }
+
+        delta / k
+    }
+
+    fn dither(p: Vector2i, c: Colour) -> Colour {
+        let offset = DITHER_TABLE[(((p.y & 0x3) << 2) | (p.x & 0x3)) as usize];
+
+        let r = util::clip(c.r() + offset, 0, 255) as u8;
+        let g = util::clip(c.g() + offset, 0, 255) as u8;
+        let b = util::clip(c.b() + offset, 0, 255) as u8;
+
+        Colour::new(r, g, b, c.a)
+    }
+
     fn render_pixel(&mut self, p: Vector2i, c: Colour,
                     transparency: bool, force_blend: bool) {
-        let address = Gpu::vram_address(p.x as u32, p.y as u32);
-        let back = Colour::from_u16(LittleEndian::read_u16(&self.vram[address..]));
+        let back = self.read_pixel(p.x as u32, p.y as u32);
 
         let mut colour = c;
 
@@ -1463,7 +1734,45 @@ impl Gpu {
             colour.a = true;
         }
 
-        LittleEndian::write_u16(&mut self.vram[address..], colour.to_u16());
+        self.write_pixel(p.x as u32, p.y as u32, colour);
+    }
+
+    fn read_pixel(&self, x: u32, y: u32) -> Colour {
+        let address = Gpu::vram_address(x, y);
+        let pixel = LittleEndian::read_u16(&self.vram[address..]);
+
+        if !self.true_colour.is_empty() {
+            let colour = Gpu::unpack_colour(self.true_colour[address / 2]);
+
+            if colour.to_u16() == pixel {
+                return colour;
+            }
+        }
+
+        Colour::from_u16(pixel)
+    }
+
+    fn write_pixel(&mut self, x: u32, y: u32, c: Colour) {
+        let address = Gpu::vram_address(x, y);
+        LittleEndian::write_u16(&mut self.vram[address..], c.to_u16());
+
+        if !self.true_colour.is_empty() {
+            self.true_colour[address / 2] = Gpu::pack_colour(c);
+        }
+    }
+
+    fn pack_colour(c: Colour) -> u32 {
+        (c.r as u32) | ((c.g as u32) << 8) | ((c.b as u32) << 16) | ((c.a as u32) << 24)
+    }
+
+    fn unpack_colour(value: u32) -> Colour {
+        Colour::new(value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) != 0)
+    }
+
+    // Shaded and texture blended primitives are dithered when GP0(E1h) enables
+    // it, unless the user has chosen otherwise
+    fn dithering_enabled(&self) -> bool {
+        self.texpage.dithering_enable && self.dithering == Dithering::Hardware
     }
 
     fn get_texture(&mut self, uv: Vector2i, clut: Vector2i) -> (Colour, bool) {
@@ -0,0 +1,271 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+use super::super::bus::BusWidth;
+use super::super::cdrom::SystemConfig;
+use super::super::{DEFAULT_EVENTS, DEFAULT_STACK, DEFAULT_THREADS, SHELL_ENTRY};
+use super::{exceptions, files, trap_instruction, Kernel};
+use super::{A0_COUNT, A0_STUBS, A0_TABLE, B0_COUNT, B0_STUBS, B0_TABLE, C0_COUNT, C0_STUBS, C0_TABLE};
+use super::{CALL_RETURN, DISPATCH, EXCEPTION_VECTOR, EXEC_RETURN, HALT, I_MASK, I_STAT, KERNEL_END, KERNEL_HEAP};
+use super::{RAM_SIZE, STUB_SIZE, TRAP_EXCEPTION, TRAP_EXEC_RETURN, TRAP_SHELL, USER_STACK, VARIABLES};
+use super::{TRAP_A0, TRAP_B0, TRAP_C0};
+use super::{VAR_AUTO_ACK, VAR_CLEAR_PAD, VAR_EXEC_HEADER, VAR_HEADER, VAR_KERNEL_HEAP, VAR_RAND_SEED};
+
+// Kernel and user mode with interrupts and the GTE enabled
+const EXEC_STATUS: u32 = 0x4000_0401;
+
+// MIPS encodings for the little code the kernel needs in RAM
+fn addiu(rt: u32, rs: u32, imm: u32) -> u32 {
+    (0x09 << 26) | (rs << 21) | (rt << 16) | (imm & 0xffff)
+}
+
+fn andi(rt: u32, rs: u32, imm: u32) -> u32 {
+    (0x0c << 26) | (rs << 21) | (rt << 16) | (imm & 0xffff)
+}
+
+fn j(address: u32) -> u32 {
+    (0x02 << 26) | ((address >> 2) & 0x3ff_ffff)
+}
+
+const T0: u32 = 8;
+const T1: u32 = 9;
+const RA: u32 = 31;
+
+const SLL_T1_T1_2: u32 = (T1 << 16) | (T1 << 11) | (2 << 6);
+const ADDU_T0_T0_T1: u32 = (T0 << 21) | (T1 << 16) | (T0 << 11) | 0x21;
+const LW_T0_T0: u32 = (0x23 << 26) | (T0 << 21) | (T0 << 16);
+const JR_T0: u32 = (T0 << 21) | 0x08;
+const JR_RA: u32 = (RA << 21) | 0x08;
+const BRANCH_SELF: u32 = (0x04 << 26) | 0xffff;
+const NOP: u32 = 0;
+
+// Runs from the reset vector, sets up the kernel area then enters the shell
+pub fn boot(k: &mut Kernel) {
+    k.fill(0x8000_0000, 0, KERNEL_END - 0x8000_0000);
+
+    k.write32(EXCEPTION_VECTOR, trap_instruction(TRAP_EXCEPTION));
+    k.write32(EXCEPTION_VECTOR + 4, NOP);
+
+    let vectors = [
+        (0x8000_00a0, A0_TABLE, A0_STUBS, A0_COUNT, TRAP_A0),
+        (0x8000_00b0, B0_TABLE, B0_STUBS, B0_COUNT, TRAP_B0),
+        (0x8000_00c0, C0_TABLE, C0_STUBS, C0_COUNT, TRAP_C0),
+    ];
+
+    for &(vector, table, stubs, count, trap) in vectors.iter() {
+        k.write32(vector, addiu(T0, 0, table));
+        k.write32(vector + 4, j(DISPATCH));
+        k.write32(vector + 8, andi(T1, T1, 0xff));
+        k.write32(vector + 12, NOP);
+
+        for i in 0..count {
+            let stub = stubs + i * STUB_SIZE;
+
+            k.write32(stub, trap_instruction(trap | i));
+            k.write32(stub + 4, JR_RA);
+            k.write32(stub + 8, NOP);
+
+            k.write32(table + i * 4, stub);
+        }
+    }
+
+    let dispatch = [SLL_T1_T1_2, ADDU_T0_T0_T1, LW_T0_T0, NOP, JR_T0, NOP];
+
+    for (i, &instruction) in dispatch.iter().enumerate() {
+        k.write32(DISPATCH + 4 * i as u32, instruction);
+    }
+
+    k.write32(CALL_RETURN, BRANCH_SELF);
+    k.write32(EXEC_RETURN, trap_instruction(TRAP_EXEC_RETURN));
+    k.write32(HALT, BRANCH_SELF);
+
+    k.write32(RAM_SIZE, 2);
+
+    k.write_var(VAR_RAND_SEED, 1);
+    k.write_var(VAR_AUTO_ACK, 0x71);
+    k.write_var(VAR_CLEAR_PAD, 1);
+    k.write_var(VAR_KERNEL_HEAP, KERNEL_HEAP);
+
+    exceptions::init_tables(k, DEFAULT_EVENTS, DEFAULT_THREADS);
+
+    k.store_io(BusWidth::WORD, I_MASK, 0);
+    k.store_io(BusWidth::WORD, I_STAT, 0);
+
+    // Leaves the boot exception vectors so exceptions reach the kernel, the
+    // shell runs with interrupts on like it does with a real BIOS
+    k.cpu.set_status(EXEC_STATUS);
+
+    k.write32(SHELL_ENTRY, trap_instruction(TRAP_SHELL));
+    k.write32(SHELL_ENTRY + 4, BRANCH_SELF);
+    k.write32(SHELL_ENTRY + 8, NOP);
+
+    k.cpu.regs[29] = USER_STACK;
+    k.cpu.regs[30] = USER_STACK;
+
+    k.cpu.flush_icache();
+    k.cpu.jump(SHELL_ENTRY);
+}
+
+// Boots the disc like the shell does once the logo has been shown
+pub fn shell(k: &mut Kernel) {
+    let config = match files::read_file(k, "cdrom:\\SYSTEM.CNF;1") {
+        Ok(data) => SystemConfig::parse(&String::from_utf8_lossy(&data)),
+        Err(_) => SystemConfig::parse(""),
+    };
+
+    let events = config.events.unwrap_or(DEFAULT_EVENTS);
+    let threads = config.threads.unwrap_or(DEFAULT_THREADS);
+
+    exceptions::init_tables(k, events, threads);
+
+    let header = VARIABLES + VAR_HEADER;
+
+    if let Err(e) = load_exe(k, &config.boot, header) {
+        println!("[HLE] [ERROR] Unable to boot {}: {}", config.boot, e);
+        k.halt();
+        return;
+    }
+
+    println!("[HLE] [INFO] Booting {}", config.boot);
+
+    // Like the BIOS, always hand LoadExec the SYSTEM.CNF stack or its default
+    k.write32(header + 0x20, config.stack.unwrap_or(DEFAULT_STACK));
+    k.write32(header + 0x24, 0);
+
+    start_exe(k, header, 0, 0);
+}
+
+fn load_exe(k: &mut Kernel, path: &str, header: u32) -> Result<(), String> {
+    let data = files::read_file(k, path)?;
+
+    if data.len() < 0x800 || &data[..8] != b"PS-X EXE" {
+        return Err("missing PS-X EXE header".to_string());
+    }
+
+    let text_dest = LittleEndian::read_u32(&data[0x18..]);
+    let text_size = LittleEndian::read_u32(&data[0x1c..]) as usize;
+
+    let text = &data[0x800..(0x800 + text_size).min(data.len())];
+    k.write_bytes(text_dest, text);
+
+    k.write_bytes(header, &data[0x10..0x4c]);
+    k.cpu.flush_icache();
+
+    Ok(())
+}
+
+// Header offsets: 00 pc, 04 gp, 08 text, 18 bss, 20 stack, 28 saved registers
+fn start_exe(k: &mut Kernel, header: u32, argc: u32, argv: u32) {
+    let bss_dest = k.read32(header + 0x18);
+    let bss_size = k.read32(header + 0x1c);
+    k.fill(bss_dest, 0, bss_size);
+
+    let saved = [k.cpu.regs[29], k.cpu.regs[30], k.cpu.regs[28], k.cpu.regs[31]];
+
+    for (i, &value) in saved.iter().enumerate() {
+        k.write32(header + 0x28 + 4 * i as u32, value);
+    }
+
+    let stack_base = k.read32(header + 0x20);
+    let stack_size = k.read32(header + 0x24);
+
+    if stack_base != 0 {
+        k.cpu.regs[29] = stack_base.wrapping_add(stack_size);
+        k.cpu.regs[30] = k.cpu.regs[29];
+    }
+
+    k.cpu.regs[28] = k.read32(header + 0x04);
+    k.cpu.regs[4] = argc;
+    k.cpu.regs[5] = argv;
+    k.cpu.regs[31] = EXEC_RETURN;
+
+    k.write_var(VAR_EXEC_HEADER, header);
+
+    let pc = k.read32(header);
+    k.cpu.jump(pc);
+}
+
+pub fn exec_return(k: &mut Kernel) {
+    let header = k.read_var(VAR_EXEC_HEADER);
+
+    k.cpu.regs[29] = k.read32(header + 0x28);
+    k.cpu.regs[30] = k.read32(header + 0x2c);
+    k.cpu.regs[28] = k.read32(header + 0x30);
+    k.cpu.regs[31] = k.read32(header + 0x34);
+    k.cpu.regs[2] = 1;
+
+    let ra = k.cpu.regs[31];
+    k.cpu.jump(ra);
+}
+
+pub fn load_test(k: &mut Kernel) -> u32 {
+    let path = String::from_utf8_lossy(&k.read_string(k.arg(0))).to_string();
+    let header = k.arg(1);
+
+    match files::read_file(k, &path) {
+        Ok(data) if data.len() >= 0x800 && &data[..8] == b"PS-X EXE" => {
+            k.write_bytes(header, &data[0x10..0x4c]);
+            LittleEndian::read_u32(&data[0x10..])
+        },
+        _ => 0,
+    }
+}
+
+pub fn load(k: &mut Kernel) -> u32 {
+    let path = String::from_utf8_lossy(&k.read_string(k.arg(0))).to_string();
+    let header = k.arg(1);
+
+    match load_exe(k, &path, header) {
+        Ok(()) => 1,
+        Err(e) => {
+            println!("[HLE] [WARN] Unable to load {}: {}", path, e);
+            0
+        },
+    }
+}
+
+pub fn exec(k: &mut Kernel) -> u32 {
+    let (header, argc, argv) = (k.arg(0), k.arg(1), k.arg(2));
+
+    start_exe(k, header, argc, argv);
+    0
+}
+
+pub fn load_exec(k: &mut Kernel) -> u32 {
+    let path = String::from_utf8_lossy(&k.read_string(k.arg(0))).to_string();
+    let (stack_base, stack_size) = (k.arg(1), k.arg(2));
+
+    let header = VARIABLES + VAR_HEADER;
+
+    if let Err(e) = load_exe(k, &path, header) {
+        println!("[HLE] [ERROR] Unable to load {}: {}", path, e);
+        k.halt();
+        return 0;
+    }
+
+    if stack_base != 0 {
+        k.write32(header + 0x20, stack_base);
+        k.write32(header + 0x24, stack_size);
+    }
+
+    start_exe(k, header, 0, 0);
+    0
+}
+
+pub fn exit(k: &mut Kernel) -> u32 {
+    println!("[HLE] [INFO] Program exited with code {}", k.arg(0) as i32);
+
+    k.halt();
+    0
+}
+
+pub fn warm_boot(k: &mut Kernel) -> u32 {
+    boot(k);
+    0
+}
+
+pub fn system_error(k: &mut Kernel) -> u32 {
+    println!("[HLE] [ERROR] System error {}, {:x}", (k.arg(0) & 0xff) as u8 as char, k.arg(1));
+
+    k.halt();
+    0
+}
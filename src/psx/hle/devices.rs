@@ -0,0 +1,258 @@
+use super::super::bus::BusWidth;
+use super::Kernel;
+use super::{I_MASK, VAR_AUTO_ACK, VAR_CLEAR_PAD, VAR_PAD_BUFFERS, VAR_PAD_BUTTONS, VAR_PAD_SIZES, VAR_PAD_STARTED};
+
+const GP0: u32 = 0x1f80_1810;
+const GP1: u32 = 0x1f80_1814;
+
+const TIMERS: u32 = 0x1f80_1100;
+
+// Root counters 0-2 are the timers, 3 is the vblank interrupt
+fn rcnt_interrupt(counter: u32) -> u32 {
+    match counter {
+        3 => 0x1,
+        _ => 0x10 << counter,
+    }
+}
+
+pub fn set_rcnt(k: &mut Kernel) -> u32 {
+    let (counter, target, mode) = (k.arg(0) & 0x3, k.arg(1), k.arg(2));
+
+    if counter == 3 {
+        return 0;
+    }
+
+    let mut value = 0;
+
+    if (mode & 0x1000) != 0 {
+        value |= 0x50;
+    }
+
+    if (mode & 0x0100) != 0 {
+        value |= 0x08;
+    }
+
+    if (mode & 0x0010) != 0 {
+        value |= 0x01;
+    }
+
+    if (mode & 0x0001) != 0 {
+        value |= if counter == 2 { 0x200 } else { 0x100 };
+    }
+
+    let base = TIMERS + 0x10 * counter;
+
+    k.store_io(BusWidth::HALF, base + 8, target & 0xffff);
+    k.store_io(BusWidth::HALF, base + 4, value);
+
+    1
+}
+
+pub fn get_rcnt(k: &mut Kernel) -> u32 {
+    let counter = k.arg(0) & 0x3;
+
+    if counter == 3 {
+        return 0;
+    }
+
+    k.load_io(TIMERS + 0x10 * counter) & 0xffff
+}
+
+pub fn reset_rcnt(k: &mut Kernel) -> u32 {
+    let counter = k.arg(0) & 0x3;
+
+    if counter != 3 {
+        k.store_io(BusWidth::HALF, TIMERS + 0x10 * counter, 0);
+    }
+
+    1
+}
+
+fn update_mask(k: &mut Kernel, set: u32, clear: u32) {
+    let mask = (k.load_io(I_MASK) | set) & !clear;
+    k.store_io(BusWidth::WORD, I_MASK, mask);
+}
+
+pub fn start_rcnt(k: &mut Kernel) -> u32 {
+    let interrupt = rcnt_interrupt(k.arg(0) & 0x3);
+
+    update_mask(k, interrupt, 0);
+    1
+}
+
+pub fn stop_rcnt(k: &mut Kernel) -> u32 {
+    let interrupt = rcnt_interrupt(k.arg(0) & 0x3);
+
+    update_mask(k, 0, interrupt);
+    1
+}
+
+// Whether the kernel acknowledges the interrupt itself, returns the old value
+pub fn change_clear_rcnt(k: &mut Kernel) -> u32 {
+    let (interrupt, clear) = (rcnt_interrupt(k.arg(0) & 0x3), k.arg(1));
+
+    let auto_ack = k.read_var(VAR_AUTO_ACK);
+
+    match clear {
+        0 => k.write_var(VAR_AUTO_ACK, auto_ack & !interrupt),
+        _ => k.write_var(VAR_AUTO_ACK, auto_ack | interrupt),
+    };
+
+    ((auto_ack & interrupt) != 0) as u32
+}
+
+pub fn change_clear_pad(k: &mut Kernel) -> u32 {
+    let clear = k.arg(0);
+
+    k.write_var(VAR_CLEAR_PAD, clear);
+    0
+}
+
+pub fn init_pad(k: &mut Kernel) -> u32 {
+    let (buffer1, size1, buffer2, size2) = (k.arg(0), k.arg(1), k.arg(2), k.arg(3));
+
+    k.write_var(VAR_PAD_BUFFERS, buffer1);
+    k.write_var(VAR_PAD_BUFFERS + 4, buffer2);
+    k.write_var(VAR_PAD_SIZES, size1);
+    k.write_var(VAR_PAD_SIZES + 4, size2);
+
+    2
+}
+
+pub fn start_pad(k: &mut Kernel) -> u32 {
+    k.write_var(VAR_PAD_STARTED, 1);
+    update_mask(k, 0x1, 0);
+
+    1
+}
+
+pub fn stop_pad(k: &mut Kernel) -> u32 {
+    k.write_var(VAR_PAD_STARTED, 0);
+
+    1
+}
+
+// The old pad interface, a single word of active high buttons
+pub fn init_pad_buttons(k: &mut Kernel) -> u32 {
+    let destination = k.arg(1);
+
+    k.write_var(VAR_PAD_BUTTONS, destination);
+    k.write_var(VAR_PAD_STARTED, 1);
+    update_mask(k, 0x1, 0);
+
+    2
+}
+
+pub fn pad_buttons(k: &mut Kernel) -> u32 {
+    !(k.bus.sio0().controller().switch_state() as u32) & 0xffff
+}
+
+// Pads are read on every vblank, port 2 never has anything connected
+pub fn vblank(k: &mut Kernel) {
+    if k.read_var(VAR_PAD_STARTED) == 0 {
+        return;
+    }
+
+    let controller = k.bus.sio0().controller();
+    let (id, state) = (controller.id(), controller.switch_state());
+
+    let mut data = vec![0x00, id, state as u8, (state >> 8) as u8];
+
+    if id == 0x73 {
+        data.extend_from_slice(&[controller.axis_rx, controller.axis_ry, controller.axis_lx, controller.axis_ly]);
+    }
+
+    let (buffer1, size1) = (k.read_var(VAR_PAD_BUFFERS), k.read_var(VAR_PAD_SIZES));
+    let buffer2 = k.read_var(VAR_PAD_BUFFERS + 4);
+
+    if buffer1 != 0 {
+        if size1 != 0 {
+            data.truncate(size1 as usize);
+        }
+
+        k.write_bytes(buffer1, &data);
+    }
+
+    if buffer2 != 0 {
+        k.write8(buffer2, 0xff);
+    }
+
+    let destination = k.read_var(VAR_PAD_BUTTONS);
+
+    if destination != 0 {
+        let buttons = pad_buttons(k);
+        k.write32(destination, buttons);
+    }
+}
+
+fn gp0(k: &mut Kernel, value: u32) {
+    k.store_io(BusWidth::WORD, GP0, value);
+}
+
+pub fn gpu_dw(k: &mut Kernel) -> u32 {
+    let (x, y, width, height, source) = (k.arg(0), k.arg(1), k.arg(2), k.arg(3), k.arg(4));
+
+    gp0(k, 0xa000_0000);
+    gp0(k, ((y & 0xffff) << 16) | (x & 0xffff));
+    gp0(k, ((height & 0xffff) << 16) | (width & 0xffff));
+
+    let words = ((width & 0xffff) * (height & 0xffff)).div_ceil(2);
+
+    for i in 0..words {
+        let word = k.read32(source + 4 * i);
+        gp0(k, word);
+    }
+
+    source
+}
+
+pub fn gpu_gp1(k: &mut Kernel) -> u32 {
+    let command = k.arg(0);
+
+    k.store_io(BusWidth::WORD, GP1, command);
+    0
+}
+
+pub fn gpu_cw(k: &mut Kernel) -> u32 {
+    let command = k.arg(0);
+
+    gp0(k, command);
+    0
+}
+
+pub fn gpu_cwp(k: &mut Kernel) -> u32 {
+    let (source, count) = (k.arg(0), k.arg(1));
+
+    for i in 0..count {
+        let word = k.read32(source + 4 * i);
+        gp0(k, word);
+    }
+
+    0
+}
+
+// Ordering tables end with a next pointer of 0xffffff
+pub fn gpu_linked_list(k: &mut Kernel) -> u32 {
+    let mut address = k.arg(0) & 0x1f_fffc;
+
+    for _ in 0..0x10000 {
+        let header = k.read32(address);
+
+        for i in 1..=(header >> 24) {
+            let word = k.read32(address + 4 * i);
+            gp0(k, word);
+        }
+
+        if (header & 0x80_0000) != 0 {
+            break;
+        }
+
+        address = header & 0x1f_fffc;
+    }
+
+    0
+}
+
+pub fn gpu_status(k: &mut Kernel) -> u32 {
+    k.load_io(GP1)
+}
@@ -0,0 +1,500 @@
+use super::super::bus::BusWidth;
+use super::{devices, stdlib, Kernel};
+use super::{EVCBS, EXCB, I_MASK, I_STAT, KERNEL_HEAP_END, KERNEL_STACK, PCB, TABLE_OF_TABLES, TCBS};
+use super::{VAR_AUTO_ACK, VAR_CLEAR_PAD, VAR_CUSTOM_EXIT, VAR_EVENT_COUNT, VAR_KERNEL_HEAP, VAR_THREAD_COUNT};
+
+const EVCB_SIZE: u32 = 0x1c;
+const TCB_SIZE: u32 = 0xc0;
+
+const MAX_EVENTS: u32 = 64;
+const MAX_THREADS: u32 = 16;
+
+const EVENT_FREE: u32 = 0x0000;
+const EVENT_DISABLED: u32 = 0x1000;
+const EVENT_ENABLED: u32 = 0x2000;
+const EVENT_READY: u32 = 0x4000;
+
+const MODE_CALLBACK: u32 = 0x1000;
+const MODE_READY: u32 = 0x2000;
+
+const THREAD_FREE: u32 = 0x1000;
+const THREAD_USED: u32 = 0x4000;
+
+const EVENT_HANDLE: u32 = 0xf100_0000;
+const THREAD_HANDLE: u32 = 0xff00_0000;
+
+pub const CLASS_RCNT: u32 = 0xf200_0000;
+pub const CLASS_CARD_HW: u32 = 0xf000_0011;
+pub const CLASS_CARD_SW: u32 = 0xf400_0001;
+
+pub const SPEC_INTERRUPT: u32 = 0x0002;
+
+// Threads start with interrupts enabled once ReturnFromException pops the
+// status stack
+const THREAD_STATUS: u32 = 0x4000_0404;
+
+// TCB offsets: 00 status, 08 registers, 88 epc, 8c hi, 90 lo, 94 sr, 98 cause
+const TCB_REGS: u32 = 0x08;
+const TCB_EPC: u32 = 0x88;
+const TCB_HI: u32 = 0x8c;
+const TCB_LO: u32 = 0x90;
+const TCB_SR: u32 = 0x94;
+const TCB_CAUSE: u32 = 0x98;
+
+pub fn init_tables(k: &mut Kernel, events: u32, threads: u32) {
+    let events = events.clamp(1, MAX_EVENTS);
+    let threads = threads.clamp(1, MAX_THREADS);
+
+    k.fill(EVCBS, 0, MAX_EVENTS * EVCB_SIZE);
+    k.fill(TCBS, 0, MAX_THREADS * TCB_SIZE);
+    k.fill(EXCB, 0, 4 * 8);
+
+    for i in 0..threads {
+        k.write32(TCBS + i * TCB_SIZE, THREAD_FREE);
+    }
+
+    k.write32(TCBS, THREAD_USED);
+    k.write32(PCB, TCBS);
+
+    let tables = [
+        (EXCB, 4 * 8),
+        (PCB, 4),
+        (TCBS, threads * TCB_SIZE),
+        (0, 0),
+        (EVCBS, events * EVCB_SIZE),
+    ];
+
+    for (i, &(address, size)) in tables.iter().enumerate() {
+        k.write32(TABLE_OF_TABLES + 8 * i as u32, address);
+        k.write32(TABLE_OF_TABLES + 8 * i as u32 + 4, size);
+    }
+
+    k.write_var(VAR_EVENT_COUNT, events);
+    k.write_var(VAR_THREAD_COUNT, threads);
+}
+
+pub fn set_conf(k: &mut Kernel) -> u32 {
+    let (events, threads) = (k.arg(0), k.arg(1));
+
+    init_tables(k, events, threads);
+    0
+}
+
+pub fn get_conf(k: &mut Kernel) -> u32 {
+    let (events, threads, stack) = (k.arg(0), k.arg(1), k.arg(2));
+
+    let values = [k.read_var(VAR_EVENT_COUNT), k.read_var(VAR_THREAD_COUNT), 0];
+
+    for (&address, &value) in [events, threads, stack].iter().zip(values.iter()) {
+        if address != 0 {
+            k.write32(address, value);
+        }
+    }
+
+    0
+}
+
+pub fn alloc_kernel_memory(k: &mut Kernel) -> u32 {
+    let size = (k.arg(0) + 3) & !0x3;
+    let address = k.read_var(VAR_KERNEL_HEAP);
+
+    if address + size > KERNEL_HEAP_END {
+        return 0;
+    }
+
+    k.write_var(VAR_KERNEL_HEAP, address + size);
+    address
+}
+
+// The status register as it was before an exception pushed the mode stack
+fn pop_status(status: u32) -> u32 {
+    (status & !0xf) | ((status >> 2) & 0xf)
+}
+
+fn push_status(status: u32) -> u32 {
+    (status & !0x3f) | ((status << 2) & 0x3c)
+}
+
+fn save_context(k: &mut Kernel, epc: u32, status: u32, cause: u32) {
+    let tcb = k.read32(PCB);
+
+    for i in 0..32 {
+        let value = k.cpu.regs[i];
+        k.write32(tcb + TCB_REGS + 4 * i as u32, value);
+    }
+
+    let (hi, lo) = k.cpu.hi_lo();
+
+    k.write32(tcb + TCB_EPC, epc);
+    k.write32(tcb + TCB_HI, hi);
+    k.write32(tcb + TCB_LO, lo);
+    k.write32(tcb + TCB_SR, status);
+    k.write32(tcb + TCB_CAUSE, cause);
+}
+
+// Resumes the current thread, which is also how exceptions return
+fn restore_context(k: &mut Kernel) {
+    let tcb = k.read32(PCB);
+
+    for i in 1..32 {
+        k.cpu.regs[i] = k.read32(tcb + TCB_REGS + 4 * i as u32);
+    }
+
+    let (hi, lo) = (k.read32(tcb + TCB_HI), k.read32(tcb + TCB_LO));
+    k.cpu.set_hi_lo(hi, lo);
+
+    let status = k.read32(tcb + TCB_SR);
+    k.cpu.set_status(pop_status(status));
+
+    let epc = k.read32(tcb + TCB_EPC);
+    k.cpu.jump(epc);
+}
+
+pub fn exception(k: &mut Kernel) {
+    let (epc, status, cause) = (k.cpu.epc(), k.cpu.status(), k.cpu.cause());
+
+    save_context(k, epc, status, cause);
+
+    k.cpu.regs[29] = KERNEL_STACK;
+
+    match (cause >> 2) & 0x1f {
+        0x00 => interrupt(k),
+        0x08 => syscall(k),
+        code => {
+            println!("[HLE] [ERROR] Unhandled exception {} at 0x{:08x}", code, epc);
+            k.halt();
+        },
+    };
+}
+
+fn interrupt(k: &mut Kernel) {
+    let pending = k.load_io(I_STAT) & k.load_io(I_MASK);
+
+    if (pending & 0x1) != 0 {
+        devices::vblank(k);
+        deliver(k, CLASS_RCNT + 3, SPEC_INTERRUPT);
+    }
+
+    for i in 0..3 {
+        if (pending & (0x10 << i)) != 0 {
+            deliver(k, CLASS_RCNT + i, SPEC_INTERRUPT);
+        }
+    }
+
+    let mut acknowledge = pending & k.read_var(VAR_AUTO_ACK);
+
+    if k.read_var(VAR_CLEAR_PAD) != 0 {
+        acknowledge |= pending & 0x1;
+    }
+
+    if acknowledge != 0 {
+        k.store_io(BusWidth::WORD, I_STAT, !acknowledge);
+    }
+
+    // Handlers installed with SysEnqIntRP, the first function checks for
+    // the interrupt and the second handles it
+    for priority in 0..4 {
+        let mut entry = k.read32(EXCB + 8 * priority);
+        let mut count = 0;
+
+        while entry != 0 && count < 32 {
+            let next = k.read32(entry);
+            let handler = k.read32(entry + 4);
+            let verifier = k.read32(entry + 8);
+
+            if verifier != 0 {
+                let result = k.call(verifier, &[]);
+
+                if result != 0 && handler != 0 {
+                    k.call(handler, &[result]);
+                }
+            }
+
+            entry = next;
+            count += 1;
+        }
+    }
+
+    let custom_exit = k.read_var(VAR_CUSTOM_EXIT);
+
+    if custom_exit != 0 {
+        stdlib::longjmp_to(k, custom_exit, 1);
+        return;
+    }
+
+    restore_context(k);
+}
+
+fn syscall(k: &mut Kernel) {
+    let tcb = k.read32(PCB);
+    let (function, argument) = (k.cpu.regs[4], k.cpu.regs[5]);
+
+    let status = k.read32(tcb + TCB_SR);
+
+    let result = match function {
+        0 => 0,
+        // EnterCriticalSection, returns whether interrupts were enabled
+        1 => {
+            k.write32(tcb + TCB_SR, status & !0x404);
+            ((status & 0x404) == 0x404) as u32
+        },
+        // ExitCriticalSection
+        2 => {
+            k.write32(tcb + TCB_SR, status | 0x404);
+            0
+        },
+        3 => 1,
+        _ => {
+            println!("[HLE] [WARN] Unknown syscall {}", function);
+            0
+        },
+    };
+
+    let epc = k.read32(tcb + TCB_EPC);
+
+    k.write32(tcb + TCB_REGS + 2 * 4, result);
+    k.write32(tcb + TCB_EPC, epc.wrapping_add(4));
+
+    // ChangeThreadSubFunction, a1 is the TCB to switch to
+    if function == 3 {
+        k.write32(PCB, argument);
+    }
+
+    restore_context(k);
+}
+
+pub fn return_from_exception(k: &mut Kernel) -> u32 {
+    restore_context(k);
+    k.cpu.regs[2]
+}
+
+pub fn enqueue_interrupt(k: &mut Kernel) -> u32 {
+    let (priority, entry) = (k.arg(0) & 0x3, k.arg(1));
+    let head = EXCB + 8 * priority;
+
+    let next = k.read32(head);
+    k.write32(entry, next);
+    k.write32(head, entry);
+
+    0
+}
+
+pub fn dequeue_interrupt(k: &mut Kernel) -> u32 {
+    let (priority, entry) = (k.arg(0) & 0x3, k.arg(1));
+
+    let mut link = EXCB + 8 * priority;
+    let mut count = 0;
+
+    while k.read32(link) != 0 && count < 32 {
+        let current = k.read32(link);
+
+        if current == entry {
+            let next = k.read32(entry);
+            k.write32(link, next);
+            break;
+        }
+
+        link = current;
+        count += 1;
+    }
+
+    0
+}
+
+fn event_address(k: &Kernel, handle: u32) -> Option<u32> {
+    let index = handle & 0xffff;
+
+    if (handle & 0xffff_0000) != EVENT_HANDLE || index >= k.read_var(VAR_EVENT_COUNT) {
+        return None;
+    }
+
+    Some(EVCBS + index * EVCB_SIZE)
+}
+
+// EvCB offsets: 00 class, 04 status, 08 spec, 0c mode, 10 callback
+pub fn deliver(k: &mut Kernel, class: u32, spec: u32) {
+    for i in 0..k.read_var(VAR_EVENT_COUNT) {
+        let event = EVCBS + i * EVCB_SIZE;
+
+        if k.read32(event) != class || k.read32(event + 8) != spec || k.read32(event + 4) != EVENT_ENABLED {
+            continue;
+        }
+
+        match k.read32(event + 0xc) {
+            MODE_READY => k.write32(event + 4, EVENT_READY),
+            MODE_CALLBACK => {
+                let callback = k.read32(event + 0x10);
+
+                if callback != 0 {
+                    k.call(callback, &[]);
+                }
+            },
+            _ => (),
+        };
+    }
+}
+
+pub fn deliver_event(k: &mut Kernel) -> u32 {
+    let (class, spec) = (k.arg(0), k.arg(1));
+
+    deliver(k, class, spec);
+    0
+}
+
+pub fn undeliver_event(k: &mut Kernel) -> u32 {
+    let (class, spec) = (k.arg(0), k.arg(1));
+
+    for i in 0..k.read_var(VAR_EVENT_COUNT) {
+        let event = EVCBS + i * EVCB_SIZE;
+
+        if k.read32(event) == class && k.read32(event + 8) == spec
+           && k.read32(event + 4) == EVENT_READY && k.read32(event + 0xc) == MODE_READY {
+            k.write32(event + 4, EVENT_ENABLED);
+        }
+    }
+
+    0
+}
+
+pub fn open_event(k: &mut Kernel) -> u32 {
+    let (class, spec, mode, callback) = (k.arg(0), k.arg(1), k.arg(2), k.arg(3));
+
+    for i in 0..k.read_var(VAR_EVENT_COUNT) {
+        let event = EVCBS + i * EVCB_SIZE;
+
+        if k.read32(event + 4) != EVENT_FREE {
+            continue;
+        }
+
+        k.write32(event, class);
+        k.write32(event + 4, EVENT_DISABLED);
+        k.write32(event + 8, spec);
+        k.write32(event + 0xc, mode);
+        k.write32(event + 0x10, callback);
+
+        return EVENT_HANDLE | i;
+    }
+
+    println!("[HLE] [WARN] No free event control blocks");
+    0xffff_ffff
+}
+
+fn set_event_status(k: &mut Kernel, status: u32) -> u32 {
+    let event = match event_address(k, k.arg(0)) {
+        Some(event) => event,
+        None => return 0,
+    };
+
+    if status == EVENT_FREE || k.read32(event + 4) != EVENT_FREE {
+        k.write32(event + 4, status);
+    }
+
+    1
+}
+
+pub fn close_event(k: &mut Kernel) -> u32 {
+    set_event_status(k, EVENT_FREE)
+}
+
+pub fn enable_event(k: &mut Kernel) -> u32 {
+    set_event_status(k, EVENT_ENABLED)
+}
+
+pub fn disable_event(k: &mut Kernel) -> u32 {
+    set_event_status(k, EVENT_DISABLED)
+}
+
+pub fn test_event(k: &mut Kernel) -> u32 {
+    let event = match event_address(k, k.arg(0)) {
+        Some(event) => event,
+        None => return 0,
+    };
+
+    if k.read32(event + 4) != EVENT_READY {
+        return 0;
+    }
+
+    k.write32(event + 4, EVENT_ENABLED);
+    1
+}
+
+pub fn wait_event(k: &mut Kernel) -> u32 {
+    let event = match event_address(k, k.arg(0)) {
+        Some(event) => event,
+        None => return 0,
+    };
+
+    match k.read32(event + 4) {
+        EVENT_READY => {
+            k.write32(event + 4, EVENT_ENABLED);
+            1
+        },
+        // Interrupts are taken while the caller spins on the trap
+        EVENT_ENABLED => {
+            k.retry();
+            0
+        },
+        _ => 0,
+    }
+}
+
+fn thread_address(k: &Kernel, handle: u32) -> Option<u32> {
+    let index = handle & 0xffff;
+
+    if (handle & 0xffff_0000) != THREAD_HANDLE || index >= k.read_var(VAR_THREAD_COUNT) {
+        return None;
+    }
+
+    Some(TCBS + index * TCB_SIZE)
+}
+
+pub fn open_thread(k: &mut Kernel) -> u32 {
+    let (pc, sp, gp) = (k.arg(0), k.arg(1), k.arg(2));
+
+    for i in 0..k.read_var(VAR_THREAD_COUNT) {
+        let tcb = TCBS + i * TCB_SIZE;
+
+        if k.read32(tcb) == THREAD_USED {
+            continue;
+        }
+
+        k.fill(tcb, 0, TCB_SIZE);
+        k.write32(tcb, THREAD_USED);
+        k.write32(tcb + TCB_REGS + 28 * 4, gp);
+        k.write32(tcb + TCB_REGS + 29 * 4, sp);
+        k.write32(tcb + TCB_REGS + 30 * 4, sp);
+        k.write32(tcb + TCB_EPC, pc);
+        k.write32(tcb + TCB_SR, THREAD_STATUS);
+
+        return THREAD_HANDLE | i;
+    }
+
+    println!("[HLE] [WARN] No free thread control blocks");
+    0xffff_ffff
+}
+
+pub fn close_thread(k: &mut Kernel) -> u32 {
+    if let Some(tcb) = thread_address(k, k.arg(0)) {
+        k.write32(tcb, THREAD_FREE);
+    }
+
+    1
+}
+
+// The caller resumes later as if ChangeThread had returned 1
+pub fn change_thread(k: &mut Kernel) -> u32 {
+    let tcb = match thread_address(k, k.arg(0)) {
+        Some(tcb) if k.read32(tcb) == THREAD_USED => tcb,
+        _ => return 0xffff_ffff,
+    };
+
+    let (ra, status, cause) = (k.cpu.regs[31], k.cpu.status(), k.cpu.cause());
+
+    k.cpu.regs[2] = 1;
+    save_context(k, ra, push_status(status), cause);
+
+    k.write32(PCB, tcb);
+    restore_context(k);
+
+    k.cpu.regs[2]
+}
@@ -0,0 +1,633 @@
+use super::super::cdrom::SECTOR_SIZE as CD_SECTOR_SIZE;
+use super::super::sio0::memory_card::SECTOR_SIZE as CARD_SECTOR_SIZE;
+use super::exceptions::{self, CLASS_CARD_HW, CLASS_CARD_SW};
+use super::Kernel;
+use super::{FCBS, FIND, VAR_CARD_CHANNEL, VAR_ERRNO};
+
+const FCB_SIZE: u32 = 0x20;
+const MAX_FILES: u32 = 16;
+
+// fds 0 and 1 are the TTY
+const FIRST_FD: u32 = 2;
+
+// FCB offsets: 00 mode, 04 device, 08 position, 0c size, 10 start, 14 port
+const FCB_MODE: u32 = 0x00;
+const FCB_DEVICE: u32 = 0x04;
+const FCB_POSITION: u32 = 0x08;
+const FCB_SIZE_FIELD: u32 = 0x0c;
+const FCB_START: u32 = 0x10;
+const FCB_PORT: u32 = 0x14;
+
+const DEVICE_CDROM: u32 = 1;
+const DEVICE_CARD: u32 = 2;
+
+const MODE_READ: u32 = 0x0001;
+const MODE_WRITE: u32 = 0x0002;
+const MODE_CREATE: u32 = 0x0200;
+
+const ENOENT: u32 = 2;
+const EBADF: u32 = 9;
+const EEXIST: u32 = 17;
+const ENODEV: u32 = 19;
+const EINVAL: u32 = 22;
+const ENOSPC: u32 = 28;
+
+const SPEC_IOE: u32 = 0x0004;
+const SPEC_TIMEOUT: u32 = 0x0100;
+
+// Memory card directory frames 1-15 each describe the block of the same
+// number, files longer than a block chain through the next field
+const CARD_FRAMES: u32 = 15;
+const BLOCK_SIZE: u32 = 0x2000;
+const SECTORS_PER_BLOCK: u32 = BLOCK_SIZE / CARD_SECTOR_SIZE as u32;
+
+const FRAME_FREE: u32 = 0xa0;
+const FRAME_FIRST: u32 = 0x51;
+const FRAME_MIDDLE: u32 = 0x52;
+const FRAME_LAST: u32 = 0x53;
+const FRAME_NONE: u16 = 0xffff;
+
+const NAME_LENGTH: usize = 20;
+
+// Search state for firstfile/nextfile: 00 device, 04 index, 08 pattern
+const FIND_PATTERN_LENGTH: usize = 0x40;
+
+type Frame = [u8; CARD_SECTOR_SIZE];
+
+enum Device {
+    Cdrom,
+    Card(u32),
+}
+
+// Splits cdrom:\PATH or buXY:NAME into the device and the path on it
+fn parse_path(path: &str) -> Option<(Device, String)> {
+    let (device, name) = path.split_once(':')?;
+
+    match device.to_ascii_lowercase().as_str() {
+        "cdrom" => Some((Device::Cdrom, name.to_string())),
+        "bu00" => Some((Device::Card(0x00), name.to_string())),
+        "bu10" => Some((Device::Card(0x10), name.to_string())),
+        _ => None,
+    }
+}
+
+fn error(k: &mut Kernel, errno: u32) -> u32 {
+    k.write_var(VAR_ERRNO, errno);
+    0xffff_ffff
+}
+
+pub fn read_file(k: &mut Kernel, path: &str) -> Result<Vec<u8>, String> {
+    let cdrom = k.bus.cdrom();
+
+    let entry = cdrom.find_file(path)?;
+    cdrom.read_file(&entry)
+}
+
+// Only the first port has a card plugged in
+fn card_present(port: u32) -> bool {
+    port == 0x00
+}
+
+fn read_frame(k: &mut Kernel, sector: u32) -> Frame {
+    let mut frame = [0; CARD_SECTOR_SIZE];
+
+    k.bus.sio0().memory_card().read_sector(sector as usize, &mut frame);
+    frame
+}
+
+fn write_frame(k: &mut Kernel, sector: u32, frame: &Frame) {
+    k.bus.sio0().memory_card().write_sector(sector as usize, frame);
+}
+
+fn write_directory_frame(k: &mut Kernel, sector: u32, frame: &mut Frame) {
+    frame[0x7f] = frame[..0x7f].iter().fold(0, |checksum, &b| checksum ^ b);
+    write_frame(k, sector, frame);
+}
+
+fn frame_state(frame: &Frame) -> u32 {
+    u32::from_le_bytes([frame[0], frame[1], frame[2], frame[3]])
+}
+
+fn frame_next(frame: &Frame) -> u16 {
+    u16::from_le_bytes([frame[8], frame[9]])
+}
+
+fn frame_name(frame: &Frame) -> String {
+    let name = &frame[0x0a..0x0a + NAME_LENGTH];
+    let length = name.iter().position(|&c| c == 0).unwrap_or(NAME_LENGTH);
+
+    String::from_utf8_lossy(&name[..length]).to_string()
+}
+
+fn card_formatted(k: &mut Kernel) -> bool {
+    &read_frame(k, 0)[..2] == b"MC"
+}
+
+// Frame numbers of the files on the card with their name and size
+fn card_files(k: &mut Kernel) -> Vec<(u32, String, u32)> {
+    if !card_formatted(k) {
+        return Vec::new();
+    }
+
+    (1..=CARD_FRAMES)
+        .map(|i| (i, read_frame(k, i)))
+        .filter(|(_, frame)| frame_state(frame) == FRAME_FIRST)
+        .map(|(i, frame)| (i, frame_name(&frame), u32::from_le_bytes([frame[4], frame[5], frame[6], frame[7]])))
+        .collect()
+}
+
+fn find_card_file(k: &mut Kernel, name: &str) -> Option<(u32, u32)> {
+    card_files(k).into_iter().find(|(_, n, _)| n == name).map(|(frame, _, size)| (frame, size))
+}
+
+fn card_chain(k: &mut Kernel, first: u32) -> Vec<u32> {
+    let mut chain = vec![first];
+
+    while chain.len() < CARD_FRAMES as usize {
+        let next = frame_next(&read_frame(k, *chain.last().unwrap()));
+
+        if next == FRAME_NONE || next as u32 >= CARD_FRAMES {
+            break;
+        }
+
+        chain.push(next as u32 + 1);
+    }
+
+    chain
+}
+
+fn create_card_file(k: &mut Kernel, name: &str, blocks: u32) -> Option<u32> {
+    if !card_formatted(k) || name.is_empty() || name.len() > NAME_LENGTH {
+        return None;
+    }
+
+    let free: Vec<u32> = (1..=CARD_FRAMES)
+        .filter(|&i| (frame_state(&read_frame(k, i)) & 0xf0) == FRAME_FREE)
+        .take(blocks as usize)
+        .collect();
+
+    if free.len() < blocks as usize {
+        return None;
+    }
+
+    for (i, &frame_number) in free.iter().enumerate() {
+        let mut frame = [0; CARD_SECTOR_SIZE];
+
+        let state = match i {
+            0 => FRAME_FIRST,
+            i if i == free.len() - 1 => FRAME_LAST,
+            _ => FRAME_MIDDLE,
+        };
+
+        let next = free.get(i + 1).map_or(FRAME_NONE, |&next| (next - 1) as u16);
+
+        frame[0..4].copy_from_slice(&state.to_le_bytes());
+        frame[8..10].copy_from_slice(&next.to_le_bytes());
+
+        if i == 0 {
+            frame[4..8].copy_from_slice(&(blocks * BLOCK_SIZE).to_le_bytes());
+            frame[0x0a..0x0a + name.len()].copy_from_slice(name.as_bytes());
+        }
+
+        write_directory_frame(k, frame_number, &mut frame);
+    }
+
+    Some(free[0])
+}
+
+fn fcb_address(k: &Kernel, fd: u32) -> Option<u32> {
+    if !(FIRST_FD..MAX_FILES).contains(&fd) {
+        return None;
+    }
+
+    let fcb = FCBS + fd * FCB_SIZE;
+
+    match k.read32(fcb + FCB_MODE) {
+        0 => None,
+        _ => Some(fcb),
+    }
+}
+
+pub fn open(k: &mut Kernel) -> u32 {
+    let path = String::from_utf8_lossy(&k.read_string(k.arg(0))).to_string();
+    let mode = k.arg(1);
+
+    let fd = match (FIRST_FD..MAX_FILES).find(|&fd| k.read32(FCBS + fd * FCB_SIZE + FCB_MODE) == 0) {
+        Some(fd) => fd,
+        None => return error(k, EBADF),
+    };
+
+    let (device, start, size, port) = match parse_path(&path) {
+        Some((Device::Cdrom, _)) => {
+            match k.bus.cdrom().find_file(&path) {
+                Ok(entry) if !entry.directory => (DEVICE_CDROM, entry.lba as u32, entry.size as u32, 0),
+                _ => return error(k, ENOENT),
+            }
+        },
+        Some((Device::Card(port), name)) => {
+            if !card_present(port) {
+                return error(k, ENODEV);
+            }
+
+            match find_card_file(k, &name) {
+                Some(_) if (mode & MODE_CREATE) != 0 => return error(k, EEXIST),
+                Some((frame, size)) => (DEVICE_CARD, frame, size, port),
+                None if (mode & MODE_CREATE) != 0 => {
+                    let blocks = ((mode >> 16) & 0xf).max(1);
+
+                    match create_card_file(k, &name, blocks) {
+                        Some(frame) => (DEVICE_CARD, frame, blocks * BLOCK_SIZE, port),
+                        None => return error(k, ENOSPC),
+                    }
+                },
+                None => return error(k, ENOENT),
+            }
+        },
+        None => return error(k, ENODEV),
+    };
+
+    let fcb = FCBS + fd * FCB_SIZE;
+
+    k.write32(fcb + FCB_MODE, mode | MODE_READ);
+    k.write32(fcb + FCB_DEVICE, device);
+    k.write32(fcb + FCB_POSITION, 0);
+    k.write32(fcb + FCB_SIZE_FIELD, size);
+    k.write32(fcb + FCB_START, start);
+    k.write32(fcb + FCB_PORT, port);
+
+    fd
+}
+
+pub fn lseek(k: &mut Kernel) -> u32 {
+    let (fd, offset, whence) = (k.arg(0), k.arg(1), k.arg(2));
+
+    let fcb = match fcb_address(k, fd) {
+        Some(fcb) => fcb,
+        None => return error(k, EBADF),
+    };
+
+    let position = match whence {
+        0 => offset,
+        1 => k.read32(fcb + FCB_POSITION).wrapping_add(offset),
+        _ => return error(k, EINVAL),
+    };
+
+    k.write32(fcb + FCB_POSITION, position);
+    position
+}
+
+// Reads or writes card file data a sector at a time through the block chain
+fn card_transfer(k: &mut Kernel, fcb: u32, buffer: u32, length: u32, write: bool) -> u32 {
+    let (position, size) = (k.read32(fcb + FCB_POSITION), k.read32(fcb + FCB_SIZE_FIELD));
+    let length = length.min(size.saturating_sub(position));
+
+    let chain = card_chain(k, k.read32(fcb + FCB_START));
+    let mut done = 0;
+
+    while done < length {
+        let offset = position + done;
+        let block = (offset / BLOCK_SIZE) as usize;
+
+        let frame_number = match chain.get(block) {
+            Some(&frame) => frame,
+            None => break,
+        };
+
+        let sector = frame_number * SECTORS_PER_BLOCK + (offset % BLOCK_SIZE) / CARD_SECTOR_SIZE as u32;
+        let start = (offset % CARD_SECTOR_SIZE as u32) as usize;
+        let count = ((CARD_SECTOR_SIZE - start) as u32).min(length - done) as usize;
+
+        let mut frame = read_frame(k, sector);
+
+        if write {
+            let data = k.read_bytes(buffer + done, count);
+            frame[start..start + count].copy_from_slice(&data);
+            write_frame(k, sector, &frame);
+        } else {
+            k.write_bytes(buffer + done, &frame[start..start + count]);
+        }
+
+        done += count as u32;
+    }
+
+    k.write32(fcb + FCB_POSITION, position + done);
+    done
+}
+
+fn cdrom_read(k: &mut Kernel, fcb: u32, buffer: u32, length: u32) -> u32 {
+    let (position, size) = (k.read32(fcb + FCB_POSITION), k.read32(fcb + FCB_SIZE_FIELD));
+    let length = length.min(size.saturating_sub(position));
+
+    let start = k.read32(fcb + FCB_START) as usize;
+    let mut data = [0; CD_SECTOR_SIZE];
+    let mut done = 0;
+
+    while done < length {
+        let offset = (position + done) as usize;
+        let lba = start + offset / CD_SECTOR_SIZE;
+
+        if k.bus.cdrom().read_data_sector(lba, &mut data).is_err() {
+            break;
+        }
+
+        let begin = offset % CD_SECTOR_SIZE;
+        let count = (CD_SECTOR_SIZE - begin).min((length - done) as usize);
+
+        k.write_bytes(buffer + done, &data[begin..begin + count]);
+        done += count as u32;
+    }
+
+    k.write32(fcb + FCB_POSITION, position + done);
+    done
+}
+
+pub fn read(k: &mut Kernel) -> u32 {
+    let (fd, buffer, length) = (k.arg(0), k.arg(1), k.arg(2));
+
+    let fcb = match fcb_address(k, fd) {
+        Some(fcb) => fcb,
+        None => return error(k, EBADF),
+    };
+
+    match k.read32(fcb + FCB_DEVICE) {
+        DEVICE_CDROM => cdrom_read(k, fcb, buffer, length),
+        _ => {
+            let done = card_transfer(k, fcb, buffer, length, false);
+
+            exceptions::deliver(k, CLASS_CARD_SW, SPEC_IOE);
+            done
+        },
+    }
+}
+
+pub fn write(k: &mut Kernel) -> u32 {
+    let (fd, buffer, length) = (k.arg(0), k.arg(1), k.arg(2));
+
+    if fd < FIRST_FD {
+        for c in k.read_bytes(buffer, length as usize) {
+            k.putchar(c);
+        }
+
+        return length;
+    }
+
+    let fcb = match fcb_address(k, fd) {
+        Some(fcb) => fcb,
+        None => return error(k, EBADF),
+    };
+
+    if k.read32(fcb + FCB_DEVICE) != DEVICE_CARD || (k.read32(fcb + FCB_MODE) & MODE_WRITE) == 0 {
+        return error(k, EBADF);
+    }
+
+    let done = card_transfer(k, fcb, buffer, length, true);
+
+    exceptions::deliver(k, CLASS_CARD_SW, SPEC_IOE);
+    done
+}
+
+pub fn close(k: &mut Kernel) -> u32 {
+    let fd = k.arg(0);
+
+    match fcb_address(k, fd) {
+        Some(fcb) => {
+            k.fill(fcb, 0, FCB_SIZE);
+            fd
+        },
+        None => error(k, EBADF),
+    }
+}
+
+pub fn ioctl(_k: &mut Kernel) -> u32 {
+    1
+}
+
+pub fn format(k: &mut Kernel) -> u32 {
+    let path = String::from_utf8_lossy(&k.read_string(k.arg(0))).to_string();
+
+    let port = match parse_path(&path) {
+        Some((Device::Card(port), _)) if card_present(port) => port,
+        _ => {
+            error(k, ENODEV);
+            return 0;
+        },
+    };
+
+    let mut header = [0; CARD_SECTOR_SIZE];
+    header[..2].copy_from_slice(b"MC");
+    write_directory_frame(k, 0, &mut header);
+
+    for i in 1..=CARD_FRAMES {
+        let mut frame = [0; CARD_SECTOR_SIZE];
+
+        frame[0..4].copy_from_slice(&FRAME_FREE.to_le_bytes());
+        frame[8..10].copy_from_slice(&FRAME_NONE.to_le_bytes());
+
+        write_directory_frame(k, i, &mut frame);
+    }
+
+    // The broken sector list and its spares are left unused
+    for i in 16..36 {
+        let mut frame = [0; CARD_SECTOR_SIZE];
+
+        frame[0..4].copy_from_slice(&0xffff_ffffu32.to_le_bytes());
+        frame[8..10].copy_from_slice(&FRAME_NONE.to_le_bytes());
+
+        write_directory_frame(k, i, &mut frame);
+    }
+
+    write_directory_frame(k, 63, &mut header);
+
+    k.write_var(VAR_CARD_CHANNEL, port);
+    1
+}
+
+// '?' matches any character and '*' anything that follows
+fn pattern_matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.as_bytes(), name.as_bytes());
+
+    for (i, &p) in pattern.iter().enumerate() {
+        match p {
+            b'*' => return true,
+            b'?' if i < name.len() => (),
+            p if name.get(i).is_some_and(|&c| c.eq_ignore_ascii_case(&p)) => (),
+            _ => return false,
+        };
+    }
+
+    pattern.len() == name.len()
+}
+
+// Directory entries: 00 name, 14 attributes, 18 size, 1c next, 20 first sector
+fn write_direntry(k: &mut Kernel, direntry: u32, name: &str, size: u32, start: u32) {
+    let name = &name.as_bytes()[..name.len().min(NAME_LENGTH - 1)];
+
+    k.fill(direntry, 0, 0x28);
+    k.write_string(direntry, name);
+    k.write32(direntry + 0x14, 0x50);
+    k.write32(direntry + 0x18, size);
+    k.write32(direntry + 0x20, start);
+}
+
+fn find_next(k: &mut Kernel, direntry: u32) -> u32 {
+    let pattern = String::from_utf8_lossy(&k.read_string(FIND + 8)).to_string();
+    let index = k.read32(FIND + 4) as usize;
+
+    let matches: Vec<(String, u32, u32)> = match parse_path(&pattern) {
+        Some((Device::Cdrom, path)) => {
+            let (directory, name) = path.rsplit_once(['\\', '/']).unwrap_or(("", &path));
+            let cdrom = k.bus.cdrom();
+
+            let entries = cdrom.find_file(directory).and_then(|entry| cdrom.list_directory(&entry));
+
+            entries.unwrap_or_default().into_iter()
+                .filter(|e| pattern_matches(name, &e.name))
+                .map(|e| (e.name, e.size as u32, e.lba as u32))
+                .collect()
+        },
+        Some((Device::Card(port), name)) if card_present(port) => {
+            card_files(k).into_iter()
+                .filter(|(_, n, _)| pattern_matches(&name, n))
+                .map(|(frame, n, size)| (n, size, frame * SECTORS_PER_BLOCK))
+                .collect()
+        },
+        _ => Vec::new(),
+    };
+
+    match matches.get(index) {
+        Some((name, size, start)) => {
+            write_direntry(k, direntry, name, *size, *start);
+            k.write32(FIND + 4, index as u32 + 1);
+
+            direntry
+        },
+        None => 0,
+    }
+}
+
+pub fn firstfile(k: &mut Kernel) -> u32 {
+    let (pattern, direntry) = (k.arg(0), k.arg(1));
+
+    let mut pattern = k.read_string(pattern);
+    pattern.truncate(FIND_PATTERN_LENGTH - 1);
+
+    k.write32(FIND + 4, 0);
+    k.write_string(FIND + 8, &pattern);
+
+    find_next(k, direntry)
+}
+
+pub fn nextfile(k: &mut Kernel) -> u32 {
+    let direntry = k.arg(0);
+
+    find_next(k, direntry)
+}
+
+pub fn erase(k: &mut Kernel) -> u32 {
+    let path = String::from_utf8_lossy(&k.read_string(k.arg(0))).to_string();
+
+    let frame = match parse_path(&path) {
+        Some((Device::Card(port), name)) if card_present(port) => find_card_file(k, &name),
+        _ => None,
+    };
+
+    let first = match frame {
+        Some((first, _)) => first,
+        None => {
+            error(k, ENOENT);
+            return 0;
+        },
+    };
+
+    // Deleted frames keep their contents, 0x51-0x53 become 0xa1-0xa3
+    for frame_number in card_chain(k, first) {
+        let mut frame = read_frame(k, frame_number);
+        frame[0] = (frame[0] & 0x0f) | 0xa0;
+
+        write_directory_frame(k, frame_number, &mut frame);
+    }
+
+    1
+}
+
+pub fn rename(k: &mut Kernel) -> u32 {
+    let old = String::from_utf8_lossy(&k.read_string(k.arg(0))).to_string();
+    let new = String::from_utf8_lossy(&k.read_string(k.arg(1))).to_string();
+
+    let (first, name) = match (parse_path(&old), parse_path(&new)) {
+        (Some((Device::Card(old_port), old_name)), Some((Device::Card(new_port), new_name)))
+            if old_port == new_port && card_present(old_port) && new_name.len() <= NAME_LENGTH => {
+            if find_card_file(k, &new_name).is_some() {
+                error(k, EEXIST);
+                return 0;
+            }
+
+            match find_card_file(k, &old_name) {
+                Some((first, _)) => (first, new_name),
+                None => {
+                    error(k, ENOENT);
+                    return 0;
+                },
+            }
+        },
+        _ => {
+            error(k, EINVAL);
+            return 0;
+        },
+    };
+
+    let mut frame = read_frame(k, first);
+
+    frame[0x0a..0x0a + NAME_LENGTH].fill(0);
+    frame[0x0a..0x0a + name.len()].copy_from_slice(name.as_bytes());
+
+    write_directory_frame(k, first, &mut frame);
+    1
+}
+
+// The low level card functions finish straight away and report it through
+// both card event classes
+fn card_done(k: &mut Kernel, port: u32) {
+    let spec = match card_present(port & 0x10) {
+        true => SPEC_IOE,
+        false => SPEC_TIMEOUT,
+    };
+
+    k.write_var(VAR_CARD_CHANNEL, port);
+
+    exceptions::deliver(k, CLASS_CARD_HW, spec);
+    exceptions::deliver(k, CLASS_CARD_SW, spec);
+}
+
+pub fn card_info(k: &mut Kernel) -> u32 {
+    let port = k.arg(0);
+
+    card_done(k, port);
+    1
+}
+
+pub fn card_read(k: &mut Kernel) -> u32 {
+    let (port, sector, buffer) = (k.arg(0), k.arg(1), k.arg(2));
+
+    if card_present(port & 0x10) {
+        let frame = read_frame(k, sector);
+        k.write_bytes(buffer, &frame);
+    }
+
+    card_done(k, port);
+    1
+}
+
+pub fn card_write(k: &mut Kernel) -> u32 {
+    let (port, sector, buffer) = (k.arg(0), k.arg(1), k.arg(2));
+
+    if card_present(port & 0x10) {
+        let mut frame = [0; CARD_SECTOR_SIZE];
+        frame.copy_from_slice(&k.read_bytes(buffer, CARD_SECTOR_SIZE));
+
+        write_frame(k, sector, &frame);
+    }
+
+    card_done(k, port);
+    1
+}
@@ -0,0 +1,453 @@
+mod boot;
+mod devices;
+mod exceptions;
+mod files;
+mod stdlib;
+
+use super::bus::{Bus, BusWidth};
+use super::cpu::{function_name, R3000A};
+use super::timekeeper::Timekeeper;
+use super::BIOS_SIZE;
+
+// The HLE kernel replaces the BIOS with traps into the emulator. Everything
+// the games can see (vectors, function tables, events, threads) lives in the
+// kernel area of RAM like it does on hardware, the emulator side keeps no
+// state of its own so save states need nothing extra.
+
+const TRAP_OPCODE: u32 = 0xfc00_0000;
+
+const TRAP_BOOT: u32 = 0x0001;
+const TRAP_SHELL: u32 = 0x0002;
+const TRAP_EXCEPTION: u32 = 0x0003;
+const TRAP_EXEC_RETURN: u32 = 0x0004;
+const TRAP_A0: u32 = 0xa000;
+const TRAP_B0: u32 = 0xb000;
+const TRAP_C0: u32 = 0xc000;
+
+const EXCEPTION_VECTOR: u32 = 0x8000_0080;
+
+const RAM_SIZE: u32 = 0x8000_0060;
+const TABLE_OF_TABLES: u32 = 0x8000_0100;
+
+const A0_TABLE: u32 = 0x8000_0200;
+const C0_TABLE: u32 = 0x8000_0674;
+const B0_TABLE: u32 = 0x8000_0874;
+
+const A0_COUNT: u32 = 0xc0;
+const B0_COUNT: u32 = 0x60;
+const C0_COUNT: u32 = 0x20;
+
+const VARIABLES: u32 = 0x8000_0a00;
+const PCB: u32 = 0x8000_0c00;
+const EXCB: u32 = 0x8000_0c80;
+const DISPATCH: u32 = 0x8000_0d00;
+const CALL_RETURN: u32 = 0x8000_0e00;
+const EXEC_RETURN: u32 = 0x8000_0e10;
+const HALT: u32 = 0x8000_0e20;
+
+// Each function has a stub of trap, jr ra, nop that the tables point to
+const A0_STUBS: u32 = 0x8000_1000;
+const B0_STUBS: u32 = 0x8000_1c00;
+const C0_STUBS: u32 = 0x8000_2200;
+const STUB_SIZE: u32 = 0x10;
+
+const FCBS: u32 = 0x8000_2400;
+const FIND: u32 = 0x8000_2600;
+const EVCBS: u32 = 0x8000_3000;
+const TCBS: u32 = 0x8000_3800;
+
+const KERNEL_STACK: u32 = 0x8000_7ff0;
+const KERNEL_HEAP: u32 = 0x8000_8000;
+const KERNEL_HEAP_END: u32 = 0x8000_f000;
+const KERNEL_END: u32 = 0x8001_0000;
+
+const USER_STACK: u32 = 0x801f_fff0;
+
+const I_STAT: u32 = 0x1f80_1070;
+const I_MASK: u32 = 0x1f80_1074;
+
+// Kernel variables, offsets from VARIABLES
+const VAR_HEAP_START: u32 = 0x00;
+const VAR_HEAP_END: u32 = 0x04;
+const VAR_RAND_SEED: u32 = 0x08;
+const VAR_CUSTOM_EXIT: u32 = 0x0c;
+const VAR_PAD_BUFFERS: u32 = 0x10;
+const VAR_PAD_SIZES: u32 = 0x18;
+const VAR_PAD_STARTED: u32 = 0x20;
+const VAR_PAD_BUTTONS: u32 = 0x24;
+const VAR_AUTO_ACK: u32 = 0x28;
+const VAR_CLEAR_PAD: u32 = 0x2c;
+const VAR_EVENT_COUNT: u32 = 0x30;
+const VAR_THREAD_COUNT: u32 = 0x34;
+const VAR_KERNEL_HEAP: u32 = 0x38;
+const VAR_STRTOK: u32 = 0x3c;
+const VAR_ERRNO: u32 = 0x40;
+const VAR_CARD_CHANNEL: u32 = 0x44;
+const VAR_EXEC_HEADER: u32 = 0x48;
+const VAR_HEADER: u32 = 0x80;
+const VAR_WARNED: u32 = 0x100;
+
+pub fn trap(cpu: &mut R3000A, bus: &mut Bus, tk: &mut Timekeeper, code: u32) {
+    let mut kernel = Kernel {
+        cpu,
+        bus,
+        tk,
+    };
+
+    let (vector, number) = match code & 0xff00 {
+        TRAP_A0 => (0xa0, code & 0xff),
+        TRAP_B0 => (0xb0, code & 0xff),
+        TRAP_C0 => (0xc0, code & 0xff),
+        _ => {
+            match code {
+                TRAP_BOOT => boot::boot(&mut kernel),
+                TRAP_SHELL => boot::shell(&mut kernel),
+                TRAP_EXCEPTION => exceptions::exception(&mut kernel),
+                TRAP_EXEC_RETURN => boot::exec_return(&mut kernel),
+                _ => println!("[HLE] [ERROR] Unknown trap 0x{:x}", code),
+            };
+
+            return;
+        },
+    };
+
+    let result = match vector {
+        0xa0 => a0_function(&mut kernel, number),
+        0xb0 => b0_function(&mut kernel, number),
+        _ => c0_function(&mut kernel, number),
+    };
+
+    let result = match result {
+        Some(result) => result,
+        None => {
+            kernel.unimplemented(vector, number);
+            0
+        },
+    };
+
+    kernel.cpu.regs[2] = result;
+}
+
+fn a0_function(k: &mut Kernel, number: u32) -> Option<u32> {
+    let result = match number {
+        0x00 => files::open(k),
+        0x01 => files::lseek(k),
+        0x02 => files::read(k),
+        0x03 => files::write(k),
+        0x04 => files::close(k),
+        0x05 => files::ioctl(k),
+        0x06 => boot::exit(k),
+        0x08 => stdlib::getc(k),
+        0x09 => stdlib::putc(k),
+        0x0a => stdlib::todigit(k),
+        0x0c => stdlib::strtoul(k),
+        0x0d => stdlib::strtol(k),
+        0x0e | 0x0f => stdlib::abs(k),
+        0x10 | 0x11 => stdlib::atoi(k),
+        0x12 => stdlib::atob(k),
+        0x13 => stdlib::setjmp(k),
+        0x14 => stdlib::longjmp(k),
+        0x15 => stdlib::strcat(k),
+        0x16 => stdlib::strncat(k),
+        0x17 => stdlib::strcmp(k),
+        0x18 => stdlib::strncmp(k),
+        0x19 => stdlib::strcpy(k),
+        0x1a => stdlib::strncpy(k),
+        0x1b => stdlib::strlen(k),
+        0x1c | 0x1e => stdlib::strchr(k),
+        0x1d | 0x1f => stdlib::strrchr(k),
+        0x20 => stdlib::strpbrk(k),
+        0x21 => stdlib::strspn(k),
+        0x22 => stdlib::strcspn(k),
+        0x23 => stdlib::strtok(k),
+        0x24 => stdlib::strstr(k),
+        0x25 => stdlib::toupper(k),
+        0x26 => stdlib::tolower(k),
+        0x27 => stdlib::bcopy(k),
+        0x28 => stdlib::bzero(k),
+        0x29 | 0x2d => stdlib::memcmp(k),
+        0x2a => stdlib::memcpy(k),
+        0x2b => stdlib::memset(k),
+        0x2c => stdlib::memmove(k),
+        0x2e => stdlib::memchr(k),
+        0x2f => stdlib::rand(k),
+        0x30 => stdlib::srand(k),
+        0x31 => stdlib::qsort(k),
+        0x33 => stdlib::malloc(k),
+        0x34 => stdlib::free(k),
+        0x37 => stdlib::calloc(k),
+        0x38 => stdlib::realloc(k),
+        0x39 => stdlib::init_heap(k),
+        0x3a => boot::exit(k),
+        0x3b => stdlib::getchar(k),
+        0x3c => stdlib::putchar(k),
+        0x3d => 0,
+        0x3e => stdlib::puts(k),
+        0x3f => stdlib::printf(k),
+        0x41 => boot::load_test(k),
+        0x42 => boot::load(k),
+        0x43 => boot::exec(k),
+        0x44 => {
+            k.cpu.flush_icache();
+            0
+        },
+        0x46 | 0x47 => devices::gpu_dw(k),
+        0x48 => devices::gpu_gp1(k),
+        0x49 => devices::gpu_cw(k),
+        0x4a => devices::gpu_cwp(k),
+        0x4b => devices::gpu_linked_list(k),
+        0x4d => devices::gpu_status(k),
+        0x4e => 0,
+        0x51 => boot::load_exec(k),
+        0x9c => exceptions::set_conf(k),
+        0x9d => exceptions::get_conf(k),
+        0x9f => {
+            let megabytes = k.arg(0);
+            k.write32(RAM_SIZE, megabytes);
+            0
+        },
+        0xa0 => boot::warm_boot(k),
+        0xa1 => boot::system_error(k),
+        0xab | 0xac => files::card_info(k),
+        // Device installers and CD interrupt hooks have nothing to set up
+        0x45 | 0x56 | 0x57 | 0x70 | 0x71 | 0x72 | 0x96..=0x99 | 0xa2 | 0xa3 | 0xad => 0,
+        _ => return None,
+    };
+
+    Some(result)
+}
+
+fn b0_function(k: &mut Kernel, number: u32) -> Option<u32> {
+    let result = match number {
+        0x00 => exceptions::alloc_kernel_memory(k),
+        0x01 => 0,
+        0x02 => devices::set_rcnt(k),
+        0x03 => devices::get_rcnt(k),
+        0x04 => devices::start_rcnt(k),
+        0x05 => devices::stop_rcnt(k),
+        0x06 => devices::reset_rcnt(k),
+        0x07 => exceptions::deliver_event(k),
+        0x08 => exceptions::open_event(k),
+        0x09 => exceptions::close_event(k),
+        0x0a => exceptions::wait_event(k),
+        0x0b => exceptions::test_event(k),
+        0x0c => exceptions::enable_event(k),
+        0x0d => exceptions::disable_event(k),
+        0x0e => exceptions::open_thread(k),
+        0x0f => exceptions::close_thread(k),
+        0x10 => exceptions::change_thread(k),
+        0x12 => devices::init_pad(k),
+        0x13 => devices::start_pad(k),
+        0x14 => devices::stop_pad(k),
+        0x15 => devices::init_pad_buttons(k),
+        0x16 => devices::pad_buttons(k),
+        0x17 => exceptions::return_from_exception(k),
+        0x18 => {
+            k.write_var(VAR_CUSTOM_EXIT, 0);
+            0
+        },
+        0x19 => {
+            let buffer = k.arg(0);
+            k.write_var(VAR_CUSTOM_EXIT, buffer);
+            0
+        },
+        0x20 => exceptions::undeliver_event(k),
+        0x32 => files::open(k),
+        0x33 => files::lseek(k),
+        0x34 => files::read(k),
+        0x35 => files::write(k),
+        0x36 => files::close(k),
+        0x37 => files::ioctl(k),
+        0x38 => boot::exit(k),
+        0x3a => stdlib::getc(k),
+        0x3b => stdlib::putc(k),
+        0x3c => stdlib::getchar(k),
+        0x3d => stdlib::putchar(k),
+        0x3e => 0,
+        0x3f => stdlib::puts(k),
+        0x40 => 1,
+        0x41 => files::format(k),
+        0x42 => files::firstfile(k),
+        0x43 => files::nextfile(k),
+        0x44 => files::rename(k),
+        0x45 => files::erase(k),
+        0x4a..=0x4c | 0x50 => 1,
+        0x4d => 0,
+        0x4e => files::card_write(k),
+        0x4f => files::card_read(k),
+        0x54 => k.read_var(VAR_ERRNO),
+        0x55 => 0,
+        0x56 => C0_TABLE,
+        0x57 => B0_TABLE,
+        0x58 => k.read_var(VAR_CARD_CHANNEL),
+        0x5b => devices::change_clear_pad(k),
+        0x5c => 0x11,
+        0x5d => 1,
+        0x47 | 0x48 => 0,
+        _ => return None,
+    };
+
+    Some(result)
+}
+
+fn c0_function(k: &mut Kernel, number: u32) -> Option<u32> {
+    let result = match number {
+        0x02 => exceptions::enqueue_interrupt(k),
+        0x03 => exceptions::dequeue_interrupt(k),
+        0x0a => devices::change_clear_rcnt(k),
+        // The kernel's own handlers are built in, there is nothing to install
+        0x00 | 0x01 | 0x07 | 0x08 | 0x0c | 0x12 | 0x1c => 0,
+        _ => return None,
+    };
+
+    Some(result)
+}
+
+fn trap_instruction(code: u32) -> u32 {
+    TRAP_OPCODE | code
+}
+
+// The ROM only has to get the kernel booted, everything else is in RAM
+pub fn build_rom() -> Vec<u8> {
+    let mut rom = vec![0; BIOS_SIZE];
+
+    rom[0..4].copy_from_slice(&trap_instruction(TRAP_BOOT).to_le_bytes());
+
+    // Kernel date, some games print it
+    rom[0x100..0x104].copy_from_slice(&0x1995_1204u32.to_le_bytes());
+    rom[0x108..0x118].copy_from_slice(b"rpsx HLE kernel\0");
+
+    rom
+}
+
+pub struct Kernel<'a> {
+    cpu: &'a mut R3000A,
+    bus: &'a mut Bus,
+    tk: &'a mut Timekeeper,
+}
+
+impl<'a> Kernel<'a> {
+    fn read8(&self, address: u32) -> u8 {
+        self.bus.peek(R3000A::translate_address(address)).unwrap_or(0)
+    }
+
+    fn read16(&self, address: u32) -> u16 {
+        (self.read8(address) as u16) | ((self.read8(address.wrapping_add(1)) as u16) << 8)
+    }
+
+    fn read32(&self, address: u32) -> u32 {
+        (self.read16(address) as u32) | ((self.read16(address.wrapping_add(2)) as u32) << 16)
+    }
+
+    fn write8(&mut self, address: u32, value: u8) {
+        self.bus.poke(R3000A::translate_address(address), value);
+    }
+
+    fn write16(&mut self, address: u32, value: u16) {
+        self.write8(address, value as u8);
+        self.write8(address.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    fn write32(&mut self, address: u32, value: u32) {
+        self.write16(address, value as u16);
+        self.write16(address.wrapping_add(2), (value >> 16) as u16);
+    }
+
+    fn read_bytes(&self, address: u32, length: usize) -> Vec<u8> {
+        (0..length as u32).map(|i| self.read8(address.wrapping_add(i))).collect()
+    }
+
+    fn write_bytes(&mut self, address: u32, data: &[u8]) {
+        for (i, &byte) in data.iter().enumerate() {
+            self.write8(address.wrapping_add(i as u32), byte);
+        }
+    }
+
+    fn fill(&mut self, address: u32, value: u8, length: u32) {
+        for i in 0..length {
+            self.write8(address.wrapping_add(i), value);
+        }
+    }
+
+    // Strings are capped so a missing terminator cannot run through memory
+    fn read_string(&self, address: u32) -> Vec<u8> {
+        let mut string = Vec::new();
+
+        if address == 0 {
+            return string;
+        }
+
+        for i in 0..0x1000 {
+            match self.read8(address.wrapping_add(i)) {
+                0 => break,
+                c => string.push(c),
+            };
+        }
+
+        string
+    }
+
+    fn write_string(&mut self, address: u32, string: &[u8]) {
+        self.write_bytes(address, string);
+        self.write8(address.wrapping_add(string.len() as u32), 0);
+    }
+
+    fn read_var(&self, offset: u32) -> u32 {
+        self.read32(VARIABLES + offset)
+    }
+
+    fn write_var(&mut self, offset: u32, value: u32) {
+        self.write32(VARIABLES + offset, value);
+    }
+
+    // The first four arguments are in $a0-$a3, the rest on the stack after
+    // the space reserved for them
+    fn arg(&self, index: usize) -> u32 {
+        if index < 4 {
+            return self.cpu.regs[4 + index];
+        }
+
+        self.read32(self.cpu.regs[29].wrapping_add(4 * index as u32))
+    }
+
+    fn load_io(&mut self, address: u32) -> u32 {
+        self.bus.load(self.tk, BusWidth::WORD, address).0
+    }
+
+    fn store_io(&mut self, width: BusWidth, address: u32, value: u32) {
+        self.bus.store(self.tk, width, address, value);
+    }
+
+    fn call(&mut self, address: u32, args: &[u32]) -> u32 {
+        self.cpu.call_function(self.bus, self.tk, address, args, CALL_RETURN)
+    }
+
+    fn putchar(&mut self, c: u8) {
+        self.bus.exp2().tx_byte(c);
+    }
+
+    fn halt(&mut self) {
+        self.cpu.jump(HALT);
+    }
+
+    // Blocks the caller by running the trap again on the next instruction
+    fn retry(&mut self) {
+        let pc = self.cpu.current_pc();
+        self.cpu.jump(pc);
+    }
+
+    fn unimplemented(&mut self, vector: u32, number: u32) {
+        let index = ((vector - 0xa0) >> 4) * 0x100 + number;
+        let address = VARIABLES + VAR_WARNED + index / 8;
+        let warned = self.read8(address);
+
+        if (warned & (1 << (index % 8))) != 0 {
+            return;
+        }
+
+        self.write8(address, warned | (1 << (index % 8)));
+
+        let name = function_name(vector, number).unwrap_or("unknown");
+        println!("[HLE] [WARN] Unimplemented function {:02X}:{:02X} {}", vector, number, name);
+    }
+}
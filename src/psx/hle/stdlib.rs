@@ -0,0 +1,807 @@
+use super::Kernel;
+use super::{VAR_HEAP_END, VAR_HEAP_START, VAR_RAND_SEED, VAR_STRTOK};
+
+pub fn getc(_k: &mut Kernel) -> u32 {
+    0xffff_ffff
+}
+
+pub fn getchar(_k: &mut Kernel) -> u32 {
+    0xffff_ffff
+}
+
+pub fn putc(k: &mut Kernel) -> u32 {
+    let c = k.arg(0);
+
+    k.putchar(c as u8);
+    c
+}
+
+pub fn putchar(k: &mut Kernel) -> u32 {
+    putc(k)
+}
+
+pub fn puts(k: &mut Kernel) -> u32 {
+    let string = k.read_string(k.arg(0));
+
+    for c in string {
+        k.putchar(c);
+    }
+
+    1
+}
+
+pub fn printf(k: &mut Kernel) -> u32 {
+    let format = k.read_string(k.arg(0));
+
+    let mut arg = 1;
+    let next_arg = || {
+        arg += 1;
+        k.arg(arg - 1)
+    };
+
+    let output = format_string(&format, next_arg, |address| k.read_string(address));
+
+    for &c in output.iter() {
+        k.putchar(c);
+    }
+
+    output.len() as u32
+}
+
+// Guest supplied widths and precisions are capped so a bad format string
+// cannot have us allocate without bound
+const MAX_FIELD_WIDTH: usize = 1024;
+
+// Reads a run of digits, saturating at the field width limit
+fn parse_field_width(format: &[u8], i: &mut usize) -> usize {
+    let mut value: usize = 0;
+
+    while let Some(&d) = format.get(*i).filter(|d| d.is_ascii_digit()) {
+        value = value.checked_mul(10)
+            .and_then(|v| v.checked_add((d - b'0') as usize))
+            .unwrap_or(usize::MAX)
+            .min(MAX_FIELD_WIDTH);
+
+        *i += 1;
+    }
+
+    value
+}
+
+fn format_string(format: &[u8],
+                 mut next_arg: impl FnMut() -> u32,
+                 read_string: impl Fn(u32) -> Vec<u8>) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut i = 0;
+
+    while i < format.len() {
+        let c = format[i];
+        i += 1;
+
+        if c != b'%' {
+            output.push(c);
+            continue;
+        }
+
+        let (mut left, mut zero, mut plus, mut space, mut alternate) = (false, false, false, false, false);
+
+        while i < format.len() {
+            match format[i] {
+                b'-' => left = true,
+                b'0' => zero = true,
+                b'+' => plus = true,
+                b' ' => space = true,
+                b'#' => alternate = true,
+                _ => break,
+            };
+
+            i += 1;
+        }
+
+        let mut width = 0;
+
+        // A negative width from the arguments means left justified
+        if format.get(i) == Some(&b'*') {
+            let value = next_arg() as i32;
+
+            left |= value < 0;
+            width = (value.unsigned_abs() as usize).min(MAX_FIELD_WIDTH);
+            i += 1;
+        }
+
+        if format.get(i).is_some_and(|d| d.is_ascii_digit()) {
+            width = parse_field_width(format, &mut i);
+        }
+
+        let mut precision = None;
+
+        if format.get(i) == Some(&b'.') {
+            i += 1;
+
+            // A negative precision from the arguments means no precision
+            if format.get(i) == Some(&b'*') {
+                let value = next_arg() as i32;
+
+                if value >= 0 {
+                    precision = Some((value as usize).min(MAX_FIELD_WIDTH));
+                }
+
+                i += 1;
+            } else {
+                precision = Some(parse_field_width(format, &mut i));
+            }
+        }
+
+        while let Some(b'h') | Some(b'l') = format.get(i) {
+            i += 1;
+        }
+
+        let conversion = match format.get(i) {
+            Some(&c) => c,
+            None => break,
+        };
+
+        i += 1;
+
+        let (prefix, mut digits) = match conversion {
+            b'd' | b'i' => {
+                let value = next_arg() as i32;
+
+                let sign = match value < 0 {
+                    true => "-",
+                    false if plus => "+",
+                    false if space => " ",
+                    false => "",
+                };
+
+                (sign.to_string(), value.unsigned_abs().to_string())
+            },
+            b'u' => (String::new(), next_arg().to_string()),
+            b'x' | b'p' => {
+                let value = next_arg();
+                (if alternate && value != 0 { "0x" } else { "" }.to_string(), format!("{:x}", value))
+            },
+            b'X' => {
+                let value = next_arg();
+                (if alternate && value != 0 { "0X" } else { "" }.to_string(), format!("{:X}", value))
+            },
+            b'o' => {
+                let value = next_arg();
+                (if alternate && value != 0 { "0" } else { "" }.to_string(), format!("{:o}", value))
+            },
+            b'c' => (String::new(), ((next_arg() & 0xff) as u8 as char).to_string()),
+            b's' => {
+                let mut string = read_string(next_arg());
+
+                if let Some(precision) = precision {
+                    string.truncate(precision);
+                }
+
+                (String::new(), String::from_utf8_lossy(&string).to_string())
+            },
+            b'%' => {
+                output.push(b'%');
+                continue;
+            },
+            c => {
+                output.push(b'%');
+                output.push(c);
+                continue;
+            },
+        };
+
+        let numeric = !matches!(conversion, b'c' | b's');
+
+        if numeric {
+            if let Some(precision) = precision {
+                if precision == 0 && digits == "0" {
+                    digits.clear();
+                }
+
+                while digits.len() < precision {
+                    digits.insert(0, '0');
+                }
+            }
+        }
+
+        let length = prefix.len() + digits.len();
+        let padding = width.saturating_sub(length);
+
+        let field = if left {
+            format!("{}{}{}", prefix, digits, " ".repeat(padding))
+        } else if zero && numeric && precision.is_none() {
+            format!("{}{}{}", prefix, "0".repeat(padding), digits)
+        } else {
+            format!("{}{}{}", " ".repeat(padding), prefix, digits)
+        };
+
+        output.extend_from_slice(field.as_bytes());
+    }
+
+    output
+}
+
+pub fn todigit(k: &mut Kernel) -> u32 {
+    let c = (k.arg(0) & 0xff) as u8;
+
+    match c {
+        b'0'..=b'9' => (c - b'0') as u32,
+        b'a'..=b'z' => (c - b'a') as u32 + 10,
+        b'A'..=b'Z' => (c - b'A') as u32 + 10,
+        _ => 9_999_999,
+    }
+}
+
+// Returns the value and the address after the last character used
+fn parse_integer(k: &Kernel, address: u32, base: u32) -> (u32, u32) {
+    let mut address = address;
+
+    while (k.read8(address) as char).is_ascii_whitespace() {
+        address += 1;
+    }
+
+    let negative = match k.read8(address) {
+        b'-' => {
+            address += 1;
+            true
+        },
+        b'+' => {
+            address += 1;
+            false
+        },
+        _ => false,
+    };
+
+    let hex_prefix = k.read8(address) == b'0' && (k.read8(address + 1) | 0x20) == b'x';
+
+    let base = match base {
+        0 if hex_prefix => 16,
+        0 if k.read8(address) == b'0' => 8,
+        0 => 10,
+        base => base,
+    };
+
+    if base == 16 && hex_prefix {
+        address += 2;
+    }
+
+    let mut value: u32 = 0;
+
+    loop {
+        let digit = match (k.read8(address) as char).to_digit(36) {
+            Some(digit) if digit < base => digit,
+            _ => break,
+        };
+
+        value = value.wrapping_mul(base).wrapping_add(digit);
+        address += 1;
+    }
+
+    match negative {
+        true => (value.wrapping_neg(), address),
+        false => (value, address),
+    }
+}
+
+pub fn strtol(k: &mut Kernel) -> u32 {
+    let (string, end, base) = (k.arg(0), k.arg(1), k.arg(2));
+
+    let (value, after) = parse_integer(k, string, base);
+
+    if end != 0 {
+        k.write32(end, after);
+    }
+
+    value
+}
+
+pub fn strtoul(k: &mut Kernel) -> u32 {
+    strtol(k)
+}
+
+pub fn atoi(k: &mut Kernel) -> u32 {
+    parse_integer(k, k.arg(0), 10).0
+}
+
+pub fn atob(k: &mut Kernel) -> u32 {
+    let (string, destination) = (k.arg(0), k.arg(1));
+
+    let (value, after) = parse_integer(k, string, 10);
+    k.write32(destination, value);
+
+    after
+}
+
+pub fn abs(k: &mut Kernel) -> u32 {
+    (k.arg(0) as i32).unsigned_abs()
+}
+
+// jmp_buf layout: ra, sp, fp, s0-s7, gp
+const JMP_BUF_REGISTERS: [usize; 12] = [31, 29, 30, 16, 17, 18, 19, 20, 21, 22, 23, 28];
+
+pub fn setjmp(k: &mut Kernel) -> u32 {
+    let buffer = k.arg(0);
+
+    for (i, &register) in JMP_BUF_REGISTERS.iter().enumerate() {
+        let value = k.cpu.regs[register];
+        k.write32(buffer + 4 * i as u32, value);
+    }
+
+    0
+}
+
+pub fn longjmp_to(k: &mut Kernel, buffer: u32, value: u32) {
+    for (i, &register) in JMP_BUF_REGISTERS.iter().enumerate() {
+        k.cpu.regs[register] = k.read32(buffer + 4 * i as u32);
+    }
+
+    k.cpu.regs[2] = value;
+
+    let ra = k.cpu.regs[31];
+    k.cpu.jump(ra);
+}
+
+pub fn longjmp(k: &mut Kernel) -> u32 {
+    let (buffer, value) = (k.arg(0), k.arg(1));
+
+    longjmp_to(k, buffer, value);
+    value
+}
+
+pub fn strcat(k: &mut Kernel) -> u32 {
+    let (destination, source) = (k.arg(0), k.arg(1));
+
+    if destination == 0 || source == 0 {
+        return 0;
+    }
+
+    let length = k.read_string(destination).len() as u32;
+    let string = k.read_string(source);
+
+    k.write_string(destination + length, &string);
+    destination
+}
+
+pub fn strncat(k: &mut Kernel) -> u32 {
+    let (destination, source, count) = (k.arg(0), k.arg(1), k.arg(2) as usize);
+
+    if destination == 0 || source == 0 {
+        return 0;
+    }
+
+    let length = k.read_string(destination).len() as u32;
+    let mut string = k.read_string(source);
+    string.truncate(count);
+
+    k.write_string(destination + length, &string);
+    destination
+}
+
+fn compare_strings(a: &[u8], b: &[u8], count: usize) -> u32 {
+    for i in 0..count {
+        let (ca, cb) = (*a.get(i).unwrap_or(&0), *b.get(i).unwrap_or(&0));
+
+        if ca != cb {
+            return (ca as i32 - cb as i32) as u32;
+        }
+
+        if ca == 0 {
+            break;
+        }
+    }
+
+    0
+}
+
+pub fn strcmp(k: &mut Kernel) -> u32 {
+    let (a, b) = (k.read_string(k.arg(0)), k.read_string(k.arg(1)));
+
+    compare_strings(&a, &b, usize::MAX)
+}
+
+pub fn strncmp(k: &mut Kernel) -> u32 {
+    let (a, b) = (k.read_string(k.arg(0)), k.read_string(k.arg(1)));
+
+    compare_strings(&a, &b, k.arg(2) as usize)
+}
+
+pub fn strcpy(k: &mut Kernel) -> u32 {
+    let (destination, source) = (k.arg(0), k.arg(1));
+
+    if destination == 0 || source == 0 {
+        return 0;
+    }
+
+    let string = k.read_string(source);
+    k.write_string(destination, &string);
+
+    destination
+}
+
+pub fn strncpy(k: &mut Kernel) -> u32 {
+    let (destination, source, count) = (k.arg(0), k.arg(1), k.arg(2));
+
+    if destination == 0 || source == 0 {
+        return 0;
+    }
+
+    let string = k.read_string(source);
+
+    for i in 0..count {
+        let c = *string.get(i as usize).unwrap_or(&0);
+        k.write8(destination + i, c);
+    }
+
+    destination
+}
+
+pub fn strlen(k: &mut Kernel) -> u32 {
+    k.read_string(k.arg(0)).len() as u32
+}
+
+pub fn strchr(k: &mut Kernel) -> u32 {
+    let (string, c) = (k.arg(0), (k.arg(1) & 0xff) as u8);
+    let bytes = k.read_string(string);
+
+    match c {
+        0 => string + bytes.len() as u32,
+        c => bytes.iter().position(|&b| b == c).map_or(0, |i| string + i as u32),
+    }
+}
+
+pub fn strrchr(k: &mut Kernel) -> u32 {
+    let (string, c) = (k.arg(0), (k.arg(1) & 0xff) as u8);
+    let bytes = k.read_string(string);
+
+    match c {
+        0 => string + bytes.len() as u32,
+        c => bytes.iter().rposition(|&b| b == c).map_or(0, |i| string + i as u32),
+    }
+}
+
+pub fn strpbrk(k: &mut Kernel) -> u32 {
+    let (string, set) = (k.arg(0), k.read_string(k.arg(1)));
+
+    k.read_string(string).iter()
+        .position(|c| set.contains(c))
+        .map_or(0, |i| string + i as u32)
+}
+
+pub fn strspn(k: &mut Kernel) -> u32 {
+    let (string, set) = (k.read_string(k.arg(0)), k.read_string(k.arg(1)));
+
+    string.iter().take_while(|c| set.contains(c)).count() as u32
+}
+
+pub fn strcspn(k: &mut Kernel) -> u32 {
+    let (string, set) = (k.read_string(k.arg(0)), k.read_string(k.arg(1)));
+
+    string.iter().take_while(|c| !set.contains(c)).count() as u32
+}
+
+pub fn strtok(k: &mut Kernel) -> u32 {
+    let (string, delimiters) = (k.arg(0), k.read_string(k.arg(1)));
+
+    let mut start = match string {
+        0 => k.read_var(VAR_STRTOK),
+        string => string,
+    };
+
+    if start == 0 {
+        return 0;
+    }
+
+    while k.read8(start) != 0 && delimiters.contains(&k.read8(start)) {
+        start += 1;
+    }
+
+    if k.read8(start) == 0 {
+        k.write_var(VAR_STRTOK, 0);
+        return 0;
+    }
+
+    let mut end = start;
+
+    while k.read8(end) != 0 && !delimiters.contains(&k.read8(end)) {
+        end += 1;
+    }
+
+    if k.read8(end) == 0 {
+        k.write_var(VAR_STRTOK, 0);
+    } else {
+        k.write8(end, 0);
+        k.write_var(VAR_STRTOK, end + 1);
+    }
+
+    start
+}
+
+pub fn strstr(k: &mut Kernel) -> u32 {
+    let (string, substring) = (k.arg(0), k.read_string(k.arg(1)));
+    let bytes = k.read_string(string);
+
+    if substring.is_empty() {
+        return string;
+    }
+
+    bytes.windows(substring.len())
+        .position(|w| w == substring.as_slice())
+        .map_or(0, |i| string + i as u32)
+}
+
+pub fn toupper(k: &mut Kernel) -> u32 {
+    ((k.arg(0) & 0xff) as u8).to_ascii_uppercase() as u32
+}
+
+pub fn tolower(k: &mut Kernel) -> u32 {
+    ((k.arg(0) & 0xff) as u8).to_ascii_lowercase() as u32
+}
+
+fn copy(k: &mut Kernel, destination: u32, source: u32, length: u32) {
+    let data = k.read_bytes(source, length as usize);
+    k.write_bytes(destination, &data);
+}
+
+pub fn bcopy(k: &mut Kernel) -> u32 {
+    let (source, destination, length) = (k.arg(0), k.arg(1), k.arg(2));
+
+    copy(k, destination, source, length);
+    0
+}
+
+pub fn bzero(k: &mut Kernel) -> u32 {
+    let (destination, length) = (k.arg(0), k.arg(1));
+
+    k.fill(destination, 0, length);
+    0
+}
+
+pub fn memcpy(k: &mut Kernel) -> u32 {
+    let (destination, source, length) = (k.arg(0), k.arg(1), k.arg(2));
+
+    copy(k, destination, source, length);
+    destination
+}
+
+pub fn memmove(k: &mut Kernel) -> u32 {
+    memcpy(k)
+}
+
+pub fn memset(k: &mut Kernel) -> u32 {
+    let (destination, value, length) = (k.arg(0), k.arg(1), k.arg(2));
+
+    k.fill(destination, value as u8, length);
+    destination
+}
+
+pub fn memcmp(k: &mut Kernel) -> u32 {
+    let length = k.arg(2) as usize;
+    let (a, b) = (k.read_bytes(k.arg(0), length), k.read_bytes(k.arg(1), length));
+
+    a.iter().zip(b.iter())
+        .find(|(a, b)| a != b)
+        .map_or(0, |(&a, &b)| (a as i32 - b as i32) as u32)
+}
+
+pub fn memchr(k: &mut Kernel) -> u32 {
+    let (source, c, length) = (k.arg(0), (k.arg(1) & 0xff) as u8, k.arg(2));
+
+    (0..length).find(|&i| k.read8(source + i) == c).map_or(0, |i| source + i)
+}
+
+pub fn rand(k: &mut Kernel) -> u32 {
+    let seed = k.read_var(VAR_RAND_SEED).wrapping_mul(0x41c6_4e6d).wrapping_add(0x3039);
+
+    k.write_var(VAR_RAND_SEED, seed);
+    (seed >> 16) & 0x7fff
+}
+
+pub fn srand(k: &mut Kernel) -> u32 {
+    let seed = k.arg(0);
+
+    k.write_var(VAR_RAND_SEED, seed);
+    0
+}
+
+// Insertion sort in place, the comparison runs as guest code
+pub fn qsort(k: &mut Kernel) -> u32 {
+    let (base, count, size, compare) = (k.arg(0), k.arg(1), k.arg(2), k.arg(3));
+
+    for i in 1..count {
+        let mut j = i;
+
+        while j > 0 {
+            let a = base + (j - 1) * size;
+            let b = base + j * size;
+
+            if (k.call(compare, &[a, b]) as i32) <= 0 {
+                break;
+            }
+
+            let (first, second) = (k.read_bytes(a, size as usize), k.read_bytes(b, size as usize));
+            k.write_bytes(a, &second);
+            k.write_bytes(b, &first);
+
+            j -= 1;
+        }
+    }
+
+    0
+}
+
+// Heap blocks start with a word holding their size, bit 0 is set when used
+const BLOCK_USED: u32 = 0x1;
+
+pub fn init_heap(k: &mut Kernel) -> u32 {
+    let (address, size) = (k.arg(0), k.arg(1));
+
+    let start = (address + 3) & !0x3;
+    let end = (address + size) & !0x3;
+
+    k.write_var(VAR_HEAP_START, start);
+    k.write_var(VAR_HEAP_END, end);
+
+    if end > start + 4 {
+        k.write32(start, end - start);
+    }
+
+    0
+}
+
+fn heap_alloc(k: &mut Kernel, size: u32) -> u32 {
+    let (start, end) = (k.read_var(VAR_HEAP_START), k.read_var(VAR_HEAP_END));
+    let needed = ((size + 3) & !0x3).max(4) + 4;
+
+    let mut block = start;
+
+    while start != 0 && block + 4 <= end {
+        let header = k.read32(block);
+        let mut block_size = header & !0x3;
+
+        if block_size == 0 {
+            break;
+        }
+
+        if (header & BLOCK_USED) == 0 {
+            // Merge with any free blocks that follow
+            while block + block_size < end {
+                let next = k.read32(block + block_size);
+
+                if (next & BLOCK_USED) != 0 || (next & !0x3) == 0 {
+                    break;
+                }
+
+                block_size += next & !0x3;
+            }
+
+            if block_size >= needed {
+                if block_size - needed >= 8 {
+                    k.write32(block + needed, block_size - needed);
+                    block_size = needed;
+                }
+
+                k.write32(block, block_size | BLOCK_USED);
+                return block + 4;
+            }
+
+            k.write32(block, block_size);
+        }
+
+        block += block_size;
+    }
+
+    0
+}
+
+pub fn malloc(k: &mut Kernel) -> u32 {
+    let size = k.arg(0);
+
+    heap_alloc(k, size)
+}
+
+pub fn free(k: &mut Kernel) -> u32 {
+    let address = k.arg(0);
+
+    if address != 0 {
+        let header = k.read32(address - 4);
+        k.write32(address - 4, header & !BLOCK_USED);
+    }
+
+    0
+}
+
+pub fn calloc(k: &mut Kernel) -> u32 {
+    let size = k.arg(0).wrapping_mul(k.arg(1));
+    let address = heap_alloc(k, size);
+
+    if address != 0 {
+        k.fill(address, 0, size);
+    }
+
+    address
+}
+
+pub fn realloc(k: &mut Kernel) -> u32 {
+    let (address, size) = (k.arg(0), k.arg(1));
+
+    if address == 0 {
+        return heap_alloc(k, size);
+    }
+
+    let old_size = (k.read32(address - 4) & !0x3) - 4;
+
+    k.write32(address - 4, (old_size + 4) & !BLOCK_USED);
+
+    let new_address = heap_alloc(k, size);
+
+    if new_address != 0 && new_address != address {
+        copy(k, new_address, address, old_size.min(size));
+    }
+
+    new_address
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_string, MAX_FIELD_WIDTH};
+
+    fn format(format: &str, args: &[u32]) -> String {
+        let mut args = args.iter().copied();
+        let next_arg = || args.next().unwrap_or(0);
+
+        let read_string = |address| match address {
+            0x100 => b"hello".to_vec(),
+            _ => Vec::new(),
+        };
+
+        String::from_utf8(format_string(format.as_bytes(), next_arg, read_string)).unwrap()
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(format("%s %d", &[0x100, 42]), "hello 42");
+        assert_eq!(format("%d %i %u", &[-5i32 as u32, 7, 0xffff_ffff]), "-5 7 4294967295");
+        assert_eq!(format("%x %X %#x %o", &[0xbeef, 0xbeef, 0x10, 8]), "beef BEEF 0x10 10");
+        assert_eq!(format("%c%c 100%%", &[b'o' as u32, b'k' as u32]), "ok 100%");
+        assert_eq!(format("%+d % d %ld", &[3, 3, 3]), "+3  3 3");
+    }
+
+    #[test]
+    fn width_and_precision() {
+        assert_eq!(format("[%5d]", &[42]), "[   42]");
+        assert_eq!(format("[%-5d]", &[42]), "[42   ]");
+        assert_eq!(format("[%05x]", &[0xab]), "[000ab]");
+        assert_eq!(format("[%05d]", &[-42i32 as u32]), "[-0042]");
+        assert_eq!(format("[%.3d]", &[7]), "[007]");
+        assert_eq!(format("[%.0d]", &[0]), "[]");
+        assert_eq!(format("[%.2s]", &[0x100]), "[he]");
+        assert_eq!(format("[%-7s]", &[0x100]), "[hello  ]");
+        assert_eq!(format("[%*d]", &[4, 1]), "[   1]");
+        assert_eq!(format("[%.*d]", &[3, 1]), "[001]");
+    }
+
+    #[test]
+    fn negative_star_width_left_justifies() {
+        assert_eq!(format("[%*d]", &[-5i32 as u32, 42]), "[42   ]");
+        assert_eq!(format("[%*d]", &[i32::MIN as u32, 1]).len(), MAX_FIELD_WIDTH + 2);
+    }
+
+    #[test]
+    fn negative_star_precision_is_ignored() {
+        assert_eq!(format("[%.*d]", &[-1i32 as u32, 42]), "[42]");
+    }
+
+    #[test]
+    fn huge_fields_are_clamped() {
+        assert_eq!(format("%.*d", &[0x7fff_ffff, 1]).len(), MAX_FIELD_WIDTH);
+        assert_eq!(format("%99999999999999999999999d", &[1]).len(), MAX_FIELD_WIDTH);
+        assert_eq!(format("%.99999999999999999999999s", &[0x100]), "hello");
+    }
+
+    #[test]
+    fn truncated_format() {
+        assert_eq!(format("abc%", &[]), "abc");
+        assert_eq!(format("abc%5", &[]), "abc");
+        assert_eq!(format("%q", &[]), "%q");
+    }
+}
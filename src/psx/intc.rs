@@ -80,7 +80,7 @@ impl InterruptRegister {
         value |= (self.dma as u32) << 3;
         value |= (self.cdrom as u32) << 2;
         value |= (self.gpu as u32) << 1;
-        value |= (self.vblank as u32) << 0;
+        value |= self.vblank as u32;
 
         value
     }
@@ -176,7 +176,7 @@ impl Mdec {
 
         self.idct(blk);
 
-        return true;
+        true
     }
 
     fn idct(&mut self, blk: usize) {
@@ -233,10 +233,10 @@ impl Mdec {
                         data |= 0x8000;
                     }
 
-                    output[0 + ((x + xx) + (y + yy) * 16) * 2] = data as u8;
+                    output[((x + xx) + (y + yy) * 16) * 2] = data as u8;
                     output[1 + ((x + xx) + (y + yy) * 16) * 2] = (data >> 8) as u8;
                 } else if self.output_depth == 2 {
-                    output[0 + ((x + xx) + (y + yy) * 16) * 3] = r as u8;
+                    output[((x + xx) + (y + yy) * 16) * 3] = r as u8;
                     output[1 + ((x + xx) + (y + yy) * 16) * 3] = g as u8;
                     output[2 + ((x + xx) + (y + yy) * 16) * 3] = b as u8;
                 }
@@ -256,7 +256,7 @@ impl Mdec {
                 1 => {
                     let mut finished;
 
-                    while self.data_in.len() != 0 {
+                    while !self.data_in.is_empty() {
                         match self.current_block {
                             0 => {
                                 finished = self.decode_block(MDEC_BLK_Y, MDEC_QT_Y);
@@ -275,13 +275,9 @@ impl Mdec {
                                 self.yuv_to_rgb(&mut output, 8, 8);
 
                                 if self.output_depth == 2 {
-                                    for i in 0..768 {
-                                        self.data_out.push_back(output[i]);
-                                    }
+                                    self.data_out.extend(output[..768].iter().copied());
                                 } else if self.output_depth == 3 {
-                                    for i in 0..512 {
-                                        self.data_out.push_back(output[i]);
-                                    }
+                                    self.data_out.extend(output[..512].iter().copied());
                                 }
                             },
                             4 => finished = self.decode_block(MDEC_BLK_CR, MDEC_QT_UV),
@@ -4,26 +4,58 @@ mod cdrom;
 pub mod cpu;
 mod exp2;
 mod gpu;
+mod hle;
 mod intc;
 mod mdec;
+mod monitor;
+mod psexe;
 pub mod rasteriser;
+mod region;
 mod sio0;
 mod spu;
 mod timekeeper;
 mod timers;
 
-use std::fs::File;
-use std::io;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::path::Path;
 
+use byteorder::{ByteOrder, LittleEndian};
 use serde::{Deserialize, Serialize};
 
-use crate::util;
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
 
 use self::bus::Bus;
+use self::cdrom::{Cdrom, SystemConfig, NO_DISC_ID};
 use self::cpu::R3000A;
-use self::sio0::controller::Controller;
+use self::psexe::PsExe;
+use self::region::Region;
 use self::timekeeper::Timekeeper;
 
+pub use self::cdrom::{DirectoryEntry, Iso9660, Volume, XaAttributes};
+pub use self::cpu::{disassemble, BiosTracer, StopReason, TraceTrigger, Tracer, WatchKind};
+pub use self::gpu::{Dithering, GpuCommandRecord};
+pub use self::monitor::{BusWatchpoint, IoDevice, WatchHit};
+pub use self::sio0::controller::Controller;
+
+pub const BIOS_SIZE: usize = 0x80000;
+
+const SHELL_ENTRY: u32 = 0x8003_0000;
+
+// Retail BIOSes reach the shell within a second, ten gives up on ones that
+// never will
+const SHELL_TIMEOUT_CYCLES: u64 = 10 * 33_868_800;
+
+// What the BIOS uses when SYSTEM.CNF leaves them out
+const DEFAULT_EVENTS: u32 = 16;
+const DEFAULT_THREADS: u32 = 4;
+const DEFAULT_STACK: u32 = 0x801f_ff00;
+
+const PSEXE_ARGS_ADDRESS: u32 = 0x8000_0180;
+const PSEXE_ARGS_SIZE: usize = 0x80;
+
 #[derive(Deserialize, Serialize)]
 pub struct System {
     pub running: bool,
@@ -35,23 +67,153 @@ pub struct System {
 
     bios_filepath: String,
     game_filepath: String,
+
+    #[serde(skip)]
+    stop_reason: Option<StopReason>,
+    #[serde(skip)]
+    watch_hit: Option<WatchHit>,
 }
 
 impl System {
-    pub fn new(bios_filepath: String, game_filepath: String) -> System {
-        System {
+    pub fn new(bios_filepath: String, game_filepath: Option<String>) -> io::Result<System> {
+        let mut cdrom = match &game_filepath {
+            Some(path) => Cdrom::new(path)?,
+            None => Cdrom::empty(),
+        };
+
+        // An empty BIOS boots the built in HLE kernel instead, and a directory
+        // of dumps picks the one for the disc's region
+        let bios = match bios_filepath.as_str() {
+            "hle" => Vec::new(),
+            path if Path::new(path).is_dir() => System::find_bios(Path::new(path), cdrom.get_region())?,
+            path => fs::read(path)?,
+        };
+
+        System::create(bios, cdrom, bios_filepath, game_filepath.unwrap_or_default())
+    }
+
+    pub fn from_bytes(bios: Vec<u8>, game: Vec<u8>) -> io::Result<System> {
+        let cdrom = Cdrom::from_bytes(game);
+
+        System::create(bios, cdrom, String::new(), String::new())
+    }
+
+    fn create(bios: Vec<u8>,
+              cdrom: Cdrom,
+              bios_filepath: String,
+              game_filepath: String) -> io::Result<System> {
+        let hle = bios.is_empty();
+        let bios_region = Region::from_bios(&bios);
+
+        let bios = match hle {
+            true => hle::build_rom(),
+            false => System::patch_bios(bios)?,
+        };
+
+        let mut cpu = R3000A::new();
+        cpu.set_hle(hle);
+
+        let mut bus = Bus::new(bios.into_boxed_slice(), cdrom);
+
+        let disc_region = bus.cdrom().get_region();
+
+        if let (Some(console), Some(disc), false) = (bios_region, disc_region, hle) {
+            if console != disc {
+                println!("[SYSTEM] [WARN] {} disc with {} BIOS, it will refuse to boot", disc, console);
+            }
+        }
+
+        // The HLE kernel has no region of its own and takes the disc's
+        let region = bios_region.or(disc_region).unwrap_or(Region::NorthAmerica);
+        bus.cdrom().set_console_region(region);
+
+        Ok(System {
             running: true,
 
-            bus: Bus::new(bios_filepath.as_str(), game_filepath.as_str()),
-            cpu: R3000A::new(),
+            bus,
+            cpu,
 
             timekeeper: Timekeeper::new(),
 
-            bios_filepath: bios_filepath,
-            game_filepath: game_filepath,
+            bios_filepath,
+            game_filepath,
+
+            stop_reason: None,
+            watch_hit: None,
+        })
+    }
+
+    // The first dump of the right size for the region, or any dump if none match
+    fn find_bios(directory: &Path, region: Option<Region>) -> io::Result<Vec<u8>> {
+        let mut paths = fs::read_dir(directory)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        paths.sort();
+
+        let mut fallback = None;
+
+        for path in paths {
+            if !path.is_file() || fs::metadata(&path)?.len() != BIOS_SIZE as u64 {
+                continue;
+            }
+
+            let bios = fs::read(&path)?;
+            let bios_region = Region::from_bios(&bios);
+
+            if region.is_none() || bios_region == region {
+                println!("[SYSTEM] [INFO] Using BIOS {}", path.display());
+                return Ok(bios);
+            }
+
+            if fallback.is_none() {
+                fallback = Some((path, bios));
+            }
+        }
+
+        match fallback {
+            Some((path, bios)) => {
+                println!("[SYSTEM] [WARN] No {} BIOS in {}, using {}",
+                         region.unwrap(), directory.display(), path.display());
+                Ok(bios)
+            },
+            None => {
+                let error = format!("no BIOS found in {}", directory.display());
+                Err(io::Error::new(io::ErrorKind::NotFound, error))
+            },
         }
     }
 
+    fn patch_bios(mut bios: Vec<u8>) -> io::Result<Vec<u8>> {
+        if bios.len() != BIOS_SIZE {
+            let error = format!("BIOS must be {} bytes, got {}", BIOS_SIZE, bios.len());
+            return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+        }
+
+        /* Enable TTY output */
+        bios[0x6f0c] = 0x01;
+        bios[0x6f0d] = 0x00;
+        bios[0x6f0e] = 0x01;
+        bios[0x6f0f] = 0x24;
+        bios[0x6f14] = 0xc0;
+        bios[0x6f15] = 0xa9;
+        bios[0x6f16] = 0x81;
+        bios[0x6f17] = 0xaf;
+
+        Ok(bios)
+    }
+
+    // Memory cards only live in memory until given a directory to be saved
+    // in. Each game gets a card of its own, the BIOS shell uses a shared one.
+    pub fn open_memory_cards(&mut self, directory: &Path) {
+        let name = match self.bus.cdrom().get_disc_id().as_str() {
+            NO_DISC_ID => "card1.mcd".to_string(),
+            id => format!("{}.mcd", id),
+        };
+
+        self.bus.sio0().open_memcards(&directory.join(name).to_string_lossy());
+    }
+
     pub fn reset(&mut self) {
         self.bus.reset();
         self.cpu.reset();
@@ -59,53 +221,294 @@ impl System {
         self.timekeeper.reset();
     }
 
-    pub fn reload_host_files(&mut self) {
-        self.bus.cdrom().load_disc(&self.game_filepath);
-        self.bus.sio0().load_memcards();
+    pub fn save_state(&self) -> io::Result<Vec<u8>> {
+        let bytes = rmp_serde::to_vec(self)
+            .map_err(io::Error::other)?;
+
+        let mut compressor = XzEncoder::new(Vec::new(), 6);
+        compressor.write_all(&bytes)?;
+        compressor.finish()
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        let mut decompressor = XzDecoder::new(state);
+        decompressor.read_to_end(&mut bytes)?;
+
+        let mut system: System = rmp_serde::from_slice(&bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        // Host resources are not part of the state, keep using ours
+        system.bus.cdrom().take_discs(self.bus.cdrom())
+            .map_err(io::Error::other)?;
+        system.bus.sio0().take_memcards(self.bus.sio0());
+        system.get_controller().reset_switch_state();
+        system.cpu.take_debug_state(&mut self.cpu);
+        mem::swap(system.bus.monitor(), self.bus.monitor());
+
+        // As is the dithering mode, which also rebuilds the 24-bit buffer
+        system.set_dithering(self.bus.gpu().dithering());
+
+        *self = system;
+        Ok(())
     }
 
     pub fn run_frame(&mut self) {
+        let debugging = self.cpu.debugger().enabled();
+        let watching = self.bus.monitor().watching();
+
         while !self.bus.gpu_mut().frame_complete() {
             while self.timekeeper.elapsed() < 128 {
-                self.cpu.run(&mut self.bus, &mut self.timekeeper);
+                let executed = self.cpu.run(&mut self.bus, &mut self.timekeeper);
+
+                // Stop mid-frame, the next call picks up where we left off
+                if debugging {
+                    if let Some(reason) = self.cpu.debug_stop(executed) {
+                        self.stop_reason = Some(reason);
+                        return;
+                    }
+                }
+
+                if watching {
+                    if let Some(hit) = self.bus.monitor().take_hit() {
+                        self.watch_hit = Some(hit);
+                        return;
+                    }
+                }
             }
 
             self.timekeeper.sync_all(&mut self.bus);
         }
 
         self.bus.sio0().sync();
+        self.cpu.trace_frame();
     }
 
-    #[allow(dead_code)]
-    pub fn load_psexe(&mut self, filename: String) -> io::Result<()> {
-        let mut file = File::open(filename)?;
+    // Executes a single instruction, running any DMA that is in the way
+    pub fn step(&mut self) -> StopReason {
+        loop {
+            let executed = self.cpu.run(&mut self.bus, &mut self.timekeeper);
 
-        util::discard(&mut file, 0x10)?;
+            if self.timekeeper.elapsed() >= 128 {
+                self.timekeeper.sync_all(&mut self.bus);
+            }
 
-        self.cpu.pc = util::read_u32(&mut file)?;
-        self.cpu.new_pc = self.cpu.pc + 4;
+            if executed {
+                break;
+            }
+        }
 
-        self.cpu.regs[28] = util::read_u32(&mut file)?;
+        self.cpu.debug_stop(false).unwrap_or(StopReason::Step)
+    }
 
-        let file_dest = util::read_u32(&mut file)? as usize;
-        let file_size = util::read_u32(&mut file)? as usize;
+    pub fn add_bus_watchpoint(&mut self, watchpoint: BusWatchpoint) {
+        self.bus.monitor().add_watchpoint(watchpoint);
+    }
 
-        util::discard(&mut file, 0x10)?;
+    pub fn clear_bus_watchpoints(&mut self) {
+        self.bus.monitor().clear_watchpoints();
+    }
 
-        self.cpu.regs[29] = util::read_u32(&mut file)? + util::read_u32(&mut file)?;
-        self.cpu.regs[30] = self.cpu.regs[29];
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
 
-        util::discard(&mut file, 0x7c8)?;
+    pub fn start_io_log(&mut self, path: &Path, devices: &[IoDevice]) -> io::Result<()> {
+        self.bus.monitor().start_log(path, devices)
+    }
+
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.cpu.set_tracer(tracer);
+    }
+
+    pub fn set_bios_tracer(&mut self, bios_tracer: Option<BiosTracer>) {
+        self.cpu.set_bios_tracer(bios_tracer);
+    }
+
+    pub fn set_debugging(&mut self, enabled: bool) {
+        self.cpu.debugger().set_enabled(enabled);
+        self.stop_reason = None;
+    }
+
+    pub fn take_stop_reason(&mut self) -> Option<StopReason> {
+        self.stop_reason.take()
+    }
+
+    pub fn debug_register(&self, index: usize) -> Option<u32> {
+        self.cpu.debug_register(index)
+    }
+
+    pub fn set_debug_register(&mut self, index: usize, value: u32) -> bool {
+        self.cpu.set_debug_register(index, value)
+    }
+
+    pub fn peek(&self, address: u32) -> Option<u8> {
+        self.bus.peek(R3000A::translate_address(address))
+    }
+
+    pub fn poke(&mut self, address: u32, value: u8) -> bool {
+        let written = self.bus.poke(R3000A::translate_address(address), value);
+
+        // Software breakpoints are written over code the CPU may have cached
+        if written {
+            self.cpu.flush_icache();
+        }
+
+        written
+    }
+
+    pub fn add_breakpoint(&mut self, address: u32) {
+        self.cpu.debugger().add_breakpoint(R3000A::translate_address(address));
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u32) {
+        self.cpu.debugger().remove_breakpoint(R3000A::translate_address(address));
+    }
+
+    pub fn add_watchpoint(&mut self, address: u32, length: u32, kind: WatchKind) {
+        self.cpu.debugger().add_watchpoint(R3000A::translate_address(address), length, kind);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: u32, length: u32, kind: WatchKind) {
+        self.cpu.debugger().remove_watchpoint(R3000A::translate_address(address), length, kind);
+    }
+
+    pub fn run_to_shell(&mut self) -> io::Result<()> {
+        let mut cycles = 0;
+
+        while self.cpu.pc != SHELL_ENTRY {
+            if cycles >= SHELL_TIMEOUT_CYCLES {
+                let error = format!("BIOS did not reach the shell at 0x{:08x}", SHELL_ENTRY);
+                return Err(io::Error::new(io::ErrorKind::TimedOut, error));
+            }
+
+            self.cpu.run(&mut self.bus, &mut self.timekeeper);
+
+            if self.timekeeper.elapsed() >= 128 {
+                cycles += self.timekeeper.elapsed();
+                self.timekeeper.sync_all(&mut self.bus);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Runs the BIOS until it has initialised the kernel and is about to
+    // enter the shell, then loads the executable in its place. The file is
+    // checked first so a bad one fails before the BIOS is run.
+    pub fn boot_psexe(&mut self, filename: &str, args: &[String]) -> io::Result<()> {
+        let exe = PsExe::parse(&fs::read(filename)?)?;
+
+        self.run_to_shell()?;
+        self.start_psexe(&exe, args)
+    }
+
+    // Boots the executable named in SYSTEM.CNF at the shell hand-off, which
+    // skips the logo and the BIOS reading the disc
+    pub fn fast_boot(&mut self) -> io::Result<()> {
+        let cdrom = self.bus.cdrom();
+
+        let config = match cdrom.find_file("SYSTEM.CNF").and_then(|entry| cdrom.read_file(&entry)) {
+            Ok(data) => SystemConfig::parse(&String::from_utf8_lossy(&data)),
+            Err(_) => SystemConfig::parse(""),
+        };
+
+        let entry = cdrom.find_file(&config.boot)
+            .map_err(|e| io::Error::other(format!("unable to find {}: {}", config.boot, e)))?;
+        let exe = cdrom.read_file(&entry)
+            .map_err(|e| io::Error::other(format!("unable to read {}: {}", config.boot, e)))?;
+        let exe = PsExe::parse(&exe)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", config.boot, e)))?;
+
+        self.run_to_shell()?;
+
+        // SetConf, which the BIOS calls with the SYSTEM.CNF values before
+        // starting the game
+        if config.events.is_some() || config.threads.is_some() {
+            let events = config.events.unwrap_or(DEFAULT_EVENTS);
+            let threads = config.threads.unwrap_or(DEFAULT_THREADS);
+            let stack = config.stack.unwrap_or(DEFAULT_STACK);
+
+            self.cpu.regs[9] = 0x9c;
+            self.cpu.call_function(&mut self.bus, &mut self.timekeeper, 0xa0, &[events, threads, stack], SHELL_ENTRY);
+        }
+
+        self.start_psexe(&exe, &[])?;
+
+        let stack = config.stack.unwrap_or(DEFAULT_STACK);
+        self.cpu.regs[29] = stack;
+        self.cpu.regs[30] = stack;
+
+        self.bus.cdrom().finish_boot(entry.lba + entry.sectors());
+
+        println!("[SYSTEM] [INFO] Fast booting {}", config.boot);
+        Ok(())
+    }
+
+    pub fn load_psexe(&mut self, filename: &str, args: &[String]) -> io::Result<()> {
+        let exe = PsExe::parse(&fs::read(filename)?)?;
+        self.start_psexe(&exe, args)
+    }
+
+    fn start_psexe(&mut self, exe: &PsExe, args: &[String]) -> io::Result<()> {
+        self.write_psexe_args(args)?;
+
+        exe.load(self.bus.ram());
+
+        self.cpu.pc = exe.pc;
+        self.cpu.new_pc = exe.pc.wrapping_add(4);
+
+        self.cpu.regs[28] = exe.gp;
+
+        // Like the BIOS, only move the stack if the executable asks for it
+        if exe.stack_base != 0 {
+            self.cpu.regs[29] = exe.stack_base.wrapping_add(exe.stack_size);
+            self.cpu.regs[30] = self.cpu.regs[29];
+        }
+
+        Ok(())
+    }
+
+    // Arguments are passed as argc/argv in a0/a1. The argv array and the
+    // strings are stored in the kernel's command line area.
+    fn write_psexe_args(&mut self, args: &[String]) -> io::Result<()> {
+        let pointers = (args.len() + 1) * 4;
+        let strings: usize = args.iter().map(|a| a.len() + 1).sum();
+
+        if pointers + strings > PSEXE_ARGS_SIZE {
+            let error = format!("arguments must fit in {} bytes", PSEXE_ARGS_SIZE);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, error));
+        }
 
         let ram = self.bus.ram();
+        let base = (PSEXE_ARGS_ADDRESS & 0x1fffff) as usize;
+
+        ram[base..base + PSEXE_ARGS_SIZE].fill(0);
 
-        for i in 0..file_size {
-            ram[(file_dest + i) & 0x1fffff] = util::read_u8(&mut file)?;
+        let mut offset = pointers;
+
+        for (i, arg) in args.iter().enumerate() {
+            let address = PSEXE_ARGS_ADDRESS + offset as u32;
+            LittleEndian::write_u32(&mut ram[base + i * 4..], address);
+
+            ram[base + offset..base + offset + arg.len()].copy_from_slice(arg.as_bytes());
+            offset += arg.len() + 1;
         }
 
+        self.cpu.regs[4] = args.len() as u32;
+        self.cpu.regs[5] = PSEXE_ARGS_ADDRESS;
+
         Ok(())
     }
 
+    pub fn set_tty_capture(&mut self, enabled: bool) {
+        self.bus.exp2().set_capture(enabled);
+    }
+
+    pub fn take_tty_output(&mut self) -> String {
+        self.bus.exp2().take_output()
+    }
+
     pub fn get_audio_samples(&mut self) -> Vec<i16> {
         self.bus.spu().drain_samples()
     }
@@ -114,19 +517,46 @@ impl System {
         self.bus.sio0().controller()
     }
 
-    pub fn get_disc_id(&mut self) -> String {
-        self.bus.cdrom().get_disc_id()
+    pub fn disc_count(&mut self) -> usize {
+        self.bus.cdrom().disc_count()
     }
 
-    pub fn get_disc_id_raw(&mut self) -> String {
-        self.bus.cdrom().get_disc_id_raw()
+    pub fn disc_index(&mut self) -> usize {
+        self.bus.cdrom().disc_index()
+    }
+
+    pub fn change_disc(&mut self, index: usize) -> Result<(), String> {
+        self.bus.cdrom().change_disc(index)
+    }
+
+    pub fn get_disc_id(&mut self) -> String {
+        self.bus.cdrom().get_disc_id()
     }
 
-    #[allow(dead_code)]
     pub fn get_display_origin(&self) -> (u32, u32) {
         self.bus.gpu().get_display_origin()
     }
 
+    pub fn get_refresh_rate(&self) -> f64 {
+        self.bus.gpu().refresh_rate()
+    }
+
+    pub fn get_standard_height(&self) -> u32 {
+        self.bus.gpu().get_standard_height()
+    }
+
+    pub fn set_dithering(&mut self, dithering: Dithering) {
+        self.bus.gpu_mut().set_dithering(dithering);
+    }
+
+    pub fn set_gpu_capture(&mut self, enabled: bool) {
+        self.bus.gpu_mut().set_capture_commands(enabled);
+    }
+
+    pub fn captured_gpu_commands(&self) -> &[GpuCommandRecord] {
+        self.bus.gpu().captured_commands()
+    }
+
     pub fn get_display_size(&self) -> (u32, u32) {
         self.bus.gpu().get_display_size()
     }
@@ -137,8 +567,26 @@ impl System {
         self.bus.gpu().get_framebuffer(data, draw_full_vram)
     }
 
-    #[allow(dead_code)]
     pub fn dump_vram(&self) {
         self.bus.gpu().dump_vram();
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::{Dithering, System};
+
+    #[test]
+    fn load_state_keeps_dithering() {
+        let mut system = System::from_bytes(Vec::new(), Vec::new()).unwrap();
+        system.reset();
+
+        let state = system.save_state().unwrap();
+
+        for dithering in [Dithering::Off, Dithering::TrueColour, Dithering::Hardware] {
+            system.set_dithering(dithering);
+            system.load_state(&state).unwrap();
+
+            assert_eq!(system.bus.gpu().dithering(), dithering);
+        }
+    }
+}
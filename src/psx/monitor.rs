@@ -0,0 +1,367 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::bus::BusWidth;
+use super::cpu::{R3000A, WatchKind};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IoDevice {
+    Sio0,
+    Intc,
+    Dma,
+    Timers,
+    Cdrom,
+    Gpu,
+    Mdec,
+    Spu,
+    Exp2,
+}
+
+impl IoDevice {
+    pub const ALL: [IoDevice; 9] = [
+        IoDevice::Sio0,
+        IoDevice::Intc,
+        IoDevice::Dma,
+        IoDevice::Timers,
+        IoDevice::Cdrom,
+        IoDevice::Gpu,
+        IoDevice::Mdec,
+        IoDevice::Spu,
+        IoDevice::Exp2,
+    ];
+
+    fn from_address(address: u32) -> Option<IoDevice> {
+        let device = match address {
+            0x1f80_1040..=0x1f80_105f => IoDevice::Sio0,
+            0x1f80_1070..=0x1f80_1077 => IoDevice::Intc,
+            0x1f80_1080..=0x1f80_10ff => IoDevice::Dma,
+            0x1f80_1100..=0x1f80_112f => IoDevice::Timers,
+            0x1f80_1800..=0x1f80_1803 => IoDevice::Cdrom,
+            0x1f80_1810..=0x1f80_1817 => IoDevice::Gpu,
+            0x1f80_1820..=0x1f80_1827 => IoDevice::Mdec,
+            0x1f80_1c00..=0x1f80_1fff => IoDevice::Spu,
+            0x1f80_2000..=0x1f80_207f => IoDevice::Exp2,
+            _ => return None,
+        };
+
+        Some(device)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            IoDevice::Sio0 => "SIO0",
+            IoDevice::Intc => "INTC",
+            IoDevice::Dma => "DMA",
+            IoDevice::Timers => "TIMERS",
+            IoDevice::Cdrom => "CDROM",
+            IoDevice::Gpu => "GPU",
+            IoDevice::Mdec => "MDEC",
+            IoDevice::Spu => "SPU",
+            IoDevice::Exp2 => "EXP2",
+        }
+    }
+}
+
+impl FromStr for IoDevice {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<IoDevice, String> {
+        IoDevice::ALL.iter()
+            .find(|device| device.name().eq_ignore_ascii_case(value))
+            .copied()
+            .ok_or_else(|| format!("unknown device: {}", value))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WatchHit {
+    pub pc: u32,
+    pub address: u32,
+    pub width: u32,
+    pub value: u32,
+    pub write: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BusWatchpoint {
+    start: u32,
+    end: u32,
+    kind: WatchKind,
+    condition: Option<u32>,
+}
+
+// Written as ADDRESS[-END][:r|w|rw][=VALUE] in hex, e.g. 80010000-8001000f:w
+impl FromStr for BusWatchpoint {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<BusWatchpoint, String> {
+        let error = || format!("invalid watchpoint: {}", value);
+        let hex = |s: &str| u32::from_str_radix(s.trim_start_matches("0x"), 16).map_err(|_| error());
+
+        let (range, condition) = match value.split_once('=') {
+            Some((range, condition)) => (range, Some(hex(condition)?)),
+            None => (value, None),
+        };
+
+        let (range, kind) = match range.split_once(':') {
+            Some((range, "r")) => (range, WatchKind::Read),
+            Some((range, "w")) => (range, WatchKind::Write),
+            Some((range, "rw")) => (range, WatchKind::Access),
+            Some(_) => return Err(error()),
+            None => (range, WatchKind::Write),
+        };
+
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (hex(start)?, hex(end)?),
+            None => (hex(range)?, hex(range)?),
+        };
+
+        if end < start {
+            return Err(error());
+        }
+
+        Ok(BusWatchpoint {
+            start,
+            end,
+            kind,
+            condition,
+        })
+    }
+}
+
+// RAM is mirrored across the first 8MB
+fn normalise(address: u32) -> u32 {
+    match address {
+        0x0000_0000..=0x007f_ffff => address & 0x1f_ffff,
+        _ => address,
+    }
+}
+
+// Host side watchpoints and I/O register logging for accesses made by the
+// CPU. The CPU keeps the PC up to date while the monitor is enabled.
+#[derive(Default)]
+pub struct BusMonitor {
+    enabled: bool,
+
+    pc: u32,
+
+    watchpoints: Vec<BusWatchpoint>,
+    hit: Option<WatchHit>,
+
+    log: Option<BufWriter<File>>,
+    log_devices: Vec<IoDevice>,
+}
+
+impl BusMonitor {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn watching(&self) -> bool {
+        !self.watchpoints.is_empty()
+    }
+
+    fn update_enabled(&mut self) {
+        self.enabled = !self.watchpoints.is_empty() || self.log.is_some();
+    }
+
+    pub fn set_pc(&mut self, pc: u32) {
+        self.pc = pc;
+    }
+
+    pub fn add_watchpoint(&mut self, mut watchpoint: BusWatchpoint) {
+        watchpoint.start = normalise(R3000A::translate_address(watchpoint.start));
+        watchpoint.end = normalise(R3000A::translate_address(watchpoint.end));
+
+        self.watchpoints.push(watchpoint);
+        self.update_enabled();
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+        self.hit = None;
+
+        self.update_enabled();
+    }
+
+    pub fn take_hit(&mut self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+
+    pub fn start_log(&mut self, path: &Path, devices: &[IoDevice]) -> io::Result<()> {
+        self.log = Some(BufWriter::new(File::create(path)?));
+        self.log_devices = devices.to_vec();
+
+        self.update_enabled();
+        Ok(())
+    }
+
+    pub fn stop_log(&mut self) {
+        if let Some(mut log) = self.log.take() {
+            let _ = log.flush();
+        }
+
+        self.update_enabled();
+    }
+
+    pub fn access(&mut self, address: u32, width: &BusWidth, value: u32, write: bool) {
+        let size = width.size();
+
+        let value = match size {
+            1 => value & 0xff,
+            2 => value & 0xffff,
+            _ => value,
+        };
+
+        if self.hit.is_none() {
+            self.check_watchpoints(address, size, value, write);
+        }
+
+        if self.log.is_some() {
+            self.log_access(address, size, value, write);
+        }
+    }
+
+    fn check_watchpoints(&mut self, address: u32, size: u32, value: u32, write: bool) {
+        let start = normalise(address);
+        let end = start.wrapping_add(size - 1);
+
+        for w in self.watchpoints.iter() {
+            let matches = match w.kind {
+                WatchKind::Read => !write,
+                WatchKind::Write => write,
+                WatchKind::Access => true,
+            };
+
+            let overlaps = start <= w.end && w.start <= end;
+
+            if matches && overlaps && w.condition.is_none_or(|c| c == value) {
+                self.hit = Some(WatchHit {
+                    pc: self.pc,
+                    address,
+                    width: size,
+                    value,
+                    write,
+                });
+
+                return;
+            }
+        }
+    }
+
+    fn log_access(&mut self, address: u32, size: u32, value: u32, write: bool) {
+        let device = match IoDevice::from_address(address) {
+            Some(device) if self.log_devices.contains(&device) => device,
+            _ => return,
+        };
+
+        let direction = match write {
+            true => "W",
+            false => "R",
+        };
+
+        let result = match self.log.as_mut() {
+            Some(log) => writeln!(log, "{:08x} {:<6} {}{:<2} {:08x} {:0width$x}",
+                                  self.pc, device.name(), direction, size * 8, address, value,
+                                  width = (size * 2) as usize),
+            None => return,
+        };
+
+        if let Err(e) = result {
+            println!("[BUS] [ERROR] Unable to write I/O log: {}", e);
+            self.stop_log();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bus::BusWidth;
+    use super::super::cpu::WatchKind;
+    use super::{BusMonitor, BusWatchpoint, IoDevice};
+
+    fn watchpoint(start: u32, end: u32, kind: WatchKind, condition: Option<u32>) -> BusWatchpoint {
+        BusWatchpoint {
+            start,
+            end,
+            kind,
+            condition,
+        }
+    }
+
+    #[test]
+    fn parse_watchpoint() {
+        let cases = [
+            ("80010000", watchpoint(0x8001_0000, 0x8001_0000, WatchKind::Write, None)),
+            ("0x1f801810", watchpoint(0x1f80_1810, 0x1f80_1810, WatchKind::Write, None)),
+            ("80010000-8001000f", watchpoint(0x8001_0000, 0x8001_000f, WatchKind::Write, None)),
+            ("0x80010000-0x8001000F:r", watchpoint(0x8001_0000, 0x8001_000f, WatchKind::Read, None)),
+            ("1f801070:w", watchpoint(0x1f80_1070, 0x1f80_1070, WatchKind::Write, None)),
+            ("1f801070:rw", watchpoint(0x1f80_1070, 0x1f80_1070, WatchKind::Access, None)),
+            ("80010000:w=dead", watchpoint(0x8001_0000, 0x8001_0000, WatchKind::Write, Some(0xdead))),
+            ("80010000-80010003:rw=0x1", watchpoint(0x8001_0000, 0x8001_0003, WatchKind::Access, Some(0x1))),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(text.parse::<BusWatchpoint>(), Ok(expected), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn parse_invalid_watchpoint() {
+        let cases = [
+            "",
+            "xyz",
+            "80010000:x",
+            "80010000:",
+            "80010000-",
+            "-80010000",
+            "8001000f-80010000",
+            "80010000=",
+            "80010000:w=zz",
+            "100000000",
+        ];
+
+        for text in cases {
+            assert!(text.parse::<BusWatchpoint>().is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn parse_device() {
+        assert_eq!("cdrom".parse(), Ok(IoDevice::Cdrom));
+        assert_eq!("GPU".parse(), Ok(IoDevice::Gpu));
+        assert!("floppy".parse::<IoDevice>().is_err());
+    }
+
+    #[test]
+    fn watchpoint_hits() {
+        let mut monitor = BusMonitor::default();
+
+        // Virtual addresses and RAM mirrors all land on the same bytes
+        monitor.add_watchpoint("80010004-80010007:w=ff".parse().unwrap());
+        monitor.add_watchpoint("1f801814:r".parse().unwrap());
+
+        monitor.set_pc(0x8001_2340);
+
+        monitor.access(0x0001_0004, &BusWidth::BYTE, 0x1ff, false);
+        monitor.access(0x0001_0008, &BusWidth::WORD, 0xff, true);
+        monitor.access(0x0001_0004, &BusWidth::WORD, 0xfe, true);
+        monitor.access(0x1f80_1814, &BusWidth::WORD, 0, true);
+        assert!(monitor.take_hit().is_none());
+
+        monitor.access(0x0061_0006, &BusWidth::HALF, 0xff_00ff, true);
+
+        let hit = monitor.take_hit().unwrap();
+        assert_eq!((hit.pc, hit.address, hit.width, hit.value, hit.write), (0x8001_2340, 0x0061_0006, 2, 0xff, true));
+
+        monitor.access(0x1f80_1814, &BusWidth::WORD, 0x1234, false);
+        assert_eq!(monitor.take_hit().map(|hit| hit.value), Some(0x1234));
+
+        monitor.clear_watchpoints();
+        monitor.access(0x1f80_1814, &BusWidth::WORD, 0, false);
+        assert!(monitor.take_hit().is_none());
+    }
+}
@@ -0,0 +1,139 @@
+use std::io;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use super::cpu::R3000A;
+
+const HEADER_SIZE: usize = 0x800;
+const RAM_SIZE: u32 = 0x20_0000;
+
+// A PS-X EXE checked against main RAM, so loading it cannot fail part way
+pub struct PsExe {
+    pub pc: u32,
+    pub gp: u32,
+    pub text_dest: u32,
+    pub text: Vec<u8>,
+    pub bss_dest: u32,
+    pub bss_size: u32,
+    pub stack_base: u32,
+    pub stack_size: u32,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Physical RAM offset of a region, provided all of it lies in main RAM
+fn ram_range(name: &str, address: u32, size: u32) -> io::Result<usize> {
+    let start = R3000A::translate_address(address);
+
+    match start.checked_add(size) {
+        Some(end) if end <= RAM_SIZE => Ok(start as usize),
+        _ => Err(invalid(format!("{} at 0x{:08x} with size 0x{:x} is outside of RAM", name, address, size))),
+    }
+}
+
+impl PsExe {
+    pub fn parse(data: &[u8]) -> io::Result<PsExe> {
+        if data.len() < HEADER_SIZE || &data[..8] != b"PS-X EXE" {
+            return Err(invalid("missing PS-X EXE header".to_string()));
+        }
+
+        let field = |offset: usize| LittleEndian::read_u32(&data[offset..]);
+
+        let pc = field(0x10);
+        let text_dest = field(0x18);
+        let text_size = field(0x1c);
+        let bss_dest = field(0x28);
+        let bss_size = field(0x2c);
+
+        let text = data.get(HEADER_SIZE..HEADER_SIZE + text_size as usize)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "executable is shorter than its header says"))?;
+
+        ram_range("entry point", pc, 4)?;
+        ram_range("text", text_dest, text_size)?;
+        ram_range("BSS", bss_dest, bss_size)?;
+
+        Ok(PsExe {
+            pc,
+            gp: field(0x14),
+            text_dest,
+            text: text.to_vec(),
+            bss_dest,
+            bss_size,
+            stack_base: field(0x30),
+            stack_size: field(0x34),
+        })
+    }
+
+    // Copies the text into RAM and clears the BSS
+    pub fn load(&self, ram: &mut [u8]) {
+        let text = R3000A::translate_address(self.text_dest) as usize;
+        ram[text..text + self.text.len()].copy_from_slice(&self.text);
+
+        let bss = R3000A::translate_address(self.bss_dest) as usize;
+        ram[bss..bss + self.bss_size as usize].fill(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{ByteOrder, LittleEndian};
+
+    use super::PsExe;
+
+    fn exe(pc: u32, text_dest: u32, text_size: u32, bss_dest: u32, bss_size: u32) -> Vec<u8> {
+        let mut data = vec![0u8; 0x800 + text_size as usize];
+
+        data[..8].copy_from_slice(b"PS-X EXE");
+        LittleEndian::write_u32(&mut data[0x10..], pc);
+        LittleEndian::write_u32(&mut data[0x18..], text_dest);
+        LittleEndian::write_u32(&mut data[0x1c..], text_size);
+        LittleEndian::write_u32(&mut data[0x28..], bss_dest);
+        LittleEndian::write_u32(&mut data[0x2c..], bss_size);
+        LittleEndian::write_u32(&mut data[0x30..], 0x801f_fff0);
+
+        data
+    }
+
+    #[test]
+    fn valid_executable() {
+        let mut data = exe(0x8001_0000, 0x8001_0000, 0x800, 0x8001_0800, 0x100);
+        data[0x800] = 0xaa;
+
+        let exe = PsExe::parse(&data).unwrap();
+
+        assert_eq!(exe.pc, 0x8001_0000);
+        assert_eq!(exe.text.len(), 0x800);
+        assert_eq!(exe.stack_base, 0x801f_fff0);
+
+        let mut ram = vec![0xffu8; 0x20_0000];
+        exe.load(&mut ram);
+
+        assert_eq!(ram[0x1_0000], 0xaa);
+        assert_eq!(ram[0x1_0001], 0x00);
+        assert!(ram[0x1_0800..0x1_0900].iter().all(|&b| b == 0));
+        assert_eq!(ram[0x1_0900], 0xff);
+    }
+
+    #[test]
+    fn bad_header() {
+        assert!(PsExe::parse(&[0; 0x10]).is_err());
+        assert!(PsExe::parse(&[0; 0x800]).is_err());
+    }
+
+    #[test]
+    fn truncated_text() {
+        let mut data = exe(0x8001_0000, 0x8001_0000, 0x800, 0, 0);
+        data.truncate(0xc00);
+
+        assert!(PsExe::parse(&data).is_err());
+    }
+
+    #[test]
+    fn outside_ram() {
+        assert!(PsExe::parse(&exe(0xbfc0_0000, 0x8001_0000, 0, 0, 0)).is_err());
+        assert!(PsExe::parse(&exe(0x8001_0000, 0x801f_ff00, 0x800, 0, 0)).is_err());
+        assert!(PsExe::parse(&exe(0x8001_0000, 0x8001_0000, 0, 0x8001_0000, 0xffff_ff00)).is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Region {
+    Japan,
+    NorthAmerica,
+    Europe,
+}
+
+impl Region {
+    // Licensed discs carry "Licensed by Sony Computer Entertainment Amer ica"
+    // (or Euro pe, Inc.) in sector 4, spaced out differently between discs
+    pub fn from_licence(data: &[u8]) -> Option<Region> {
+        let text: Vec<u8> = data.iter()
+            .copied()
+            .filter(|c| c.is_ascii_alphabetic())
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+
+        let marker = b"SONYCOMPUTERENTERTAINMENT";
+        let position = text.windows(marker.len()).position(|w| w == marker)?;
+
+        match &text[position + marker.len()..] {
+            rest if rest.starts_with(b"AMER") => Some(Region::NorthAmerica),
+            rest if rest.starts_with(b"EURO") => Some(Region::Europe),
+            rest if rest.starts_with(b"INC") => Some(Region::Japan),
+            _ => None,
+        }
+    }
+
+    // The second letter of the serial, SLUS, SCES, SLPM and so on
+    pub fn from_serial(serial: &str) -> Option<Region> {
+        match serial.get(..4)? {
+            "SCUS" | "SLUS" | "PAPX" => Some(Region::NorthAmerica),
+            "SCES" | "SLES" | "SCED" | "SLED" => Some(Region::Europe),
+            "SCPS" | "SLPS" | "SLPM" | "SCPM" | "SIPS" => Some(Region::Japan),
+            _ => None,
+        }
+    }
+
+    // Retail BIOSes end their version string with the region letter, as in
+    // "System ROM Version 4.1 12/16/97 E"; the original 1.0 has none
+    pub fn from_bios(bios: &[u8]) -> Option<Region> {
+        let marker = b"System ROM Version";
+        let position = bios.windows(marker.len()).position(|w| w == marker)?;
+
+        let version = bios[position..].split(|&c| c == 0).next()?;
+
+        match version.trim_ascii_end().last()? {
+            b'A' => Some(Region::NorthAmerica),
+            b'E' => Some(Region::Europe),
+            b'J' => Some(Region::Japan),
+            _ => None,
+        }
+    }
+
+    // What the drive reports in GetID, the BIOS refuses discs that do not
+    // match its own region
+    pub fn licence(self) -> &'static [u8; 4] {
+        match self {
+            Region::Japan => b"SCEI",
+            Region::NorthAmerica => b"SCEA",
+            Region::Europe => b"SCEE",
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Region::Japan => "NTSC-J",
+            Region::NorthAmerica => "NTSC-U",
+            Region::Europe => "PAL",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Region;
+
+    fn bios(version: &[u8]) -> Vec<u8> {
+        let mut bios = vec![0xff; 0x100];
+
+        bios.extend_from_slice(version);
+        bios.push(0);
+        bios.extend_from_slice(b"CEX-3000/1001/1002 by K.S.");
+
+        bios
+    }
+
+    #[test]
+    fn from_licence() {
+        let cases: [(&[u8], Option<Region>); 6] = [
+            (b"          Licensed  by          Sony Computer Entertainment Amer  ica ", Some(Region::NorthAmerica)),
+            (b"          Licensed  by          Sony Computer Entertainment Euro pe   ", Some(Region::Europe)),
+            (b"          Licensed  by          Sony Computer Entertainment Inc.", Some(Region::Japan)),
+            (b"\x00\x00LICENSED BY SONY\r\nCOMPUTER ENTERTAINMENT\tAMERICA\x00", Some(Region::NorthAmerica)),
+            (b"          Licensed  by          Sony Computer Entertainment", None),
+            (&[0; 2048], None),
+        ];
+
+        for (data, expected) in cases {
+            assert_eq!(Region::from_licence(data), expected, "{:?}", String::from_utf8_lossy(data));
+        }
+    }
+
+    #[test]
+    fn from_serial() {
+        let cases = [
+            ("SLUS-00594", Some(Region::NorthAmerica)),
+            ("SCUS-94163", Some(Region::NorthAmerica)),
+            ("PAPX-90044", Some(Region::NorthAmerica)),
+            ("SLES-01234", Some(Region::Europe)),
+            ("SCES-00344", Some(Region::Europe)),
+            ("SCED-01146", Some(Region::Europe)),
+            ("SLED-00038", Some(Region::Europe)),
+            ("SCPS-10001", Some(Region::Japan)),
+            ("SLPS-01234", Some(Region::Japan)),
+            ("SLPM-86023", Some(Region::Japan)),
+            ("SCPM-85001", Some(Region::Japan)),
+            ("SIPS-60001", Some(Region::Japan)),
+            ("SLKA-25001", None),
+            ("slus-00594", None),
+            ("SLU", None),
+            ("", None),
+        ];
+
+        for (serial, expected) in cases {
+            assert_eq!(Region::from_serial(serial), expected, "{:?}", serial);
+        }
+    }
+
+    #[test]
+    fn from_bios() {
+        assert_eq!(Region::from_bios(&bios(b"System ROM Version 4.1 12/16/97 E")), Some(Region::Europe));
+        assert_eq!(Region::from_bios(&bios(b"System ROM Version 2.2 12/04/95 A")), Some(Region::NorthAmerica));
+        assert_eq!(Region::from_bios(&bios(b"System ROM Version 3.0 09/09/96 J  ")), Some(Region::Japan));
+
+        // The original Japanese 1.0 has no region letter
+        assert_eq!(Region::from_bios(&bios(b"System ROM Version 1.0 09/22/94")), None);
+        assert_eq!(Region::from_bios(&bios(b"Not a BIOS")), None);
+        assert_eq!(Region::from_bios(&[]), None);
+    }
+
+    #[test]
+    fn licence_and_name() {
+        assert_eq!(Region::Japan.licence(), b"SCEI");
+        assert_eq!(Region::NorthAmerica.licence(), b"SCEA");
+        assert_eq!(Region::Europe.licence(), b"SCEE");
+
+        assert_eq!(Region::NorthAmerica.to_string(), "NTSC-U");
+        assert_eq!(Region::Europe.to_string(), "PAL");
+    }
+}
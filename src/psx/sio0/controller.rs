@@ -30,6 +30,12 @@ pub struct Controller {
     pub axis_ry: u8,
 }
 
+impl Default for Controller {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Controller {
     pub fn new() -> Controller {
         Controller {
@@ -96,7 +102,7 @@ impl Controller {
             1 => {
                 if command == 0x42 {
                     self.state = 2;
-                    reply = if self.digital_mode { 0x41 } else { 0x73 };
+                    reply = self.id();
                 } else {
                     self.state = 0;
                 }
@@ -143,6 +149,15 @@ impl Controller {
         self.state != 0
     }
 
+    pub fn id(&self) -> u8 {
+        if self.digital_mode { 0x41 } else { 0x73 }
+    }
+
+    // Buttons as the pad sends them, low byte first and active low
+    pub fn switch_state(&self) -> u16 {
+        ((self.get_switch_state_hi() as u16) << 8) | self.get_switch_state_lo() as u16
+    }
+
     fn get_switch_state_hi(&self) -> u8 {
         let mut value = 0;
 
@@ -153,7 +168,7 @@ impl Controller {
         value |= (self.button_r1 as u8) << 3;
         value |= (self.button_l1 as u8) << 2;
         value |= (self.button_r2 as u8) << 1;
-        value |= (self.button_l2 as u8) << 0;
+        value |= self.button_l2 as u8;
 
         !value
     }
@@ -1,17 +1,21 @@
 use std::fs;
-use std::io::prelude::{Read, Write};
-use std::os::windows::prelude::FileExt;
+use std::io::prelude::{Read, Seek, Write};
+use std::io::SeekFrom;
+use std::mem;
 use std::path;
 
 use serde::{Deserialize, Serialize};
 
 pub const MEMORY_CARD_SIZE: usize = 0x20000;
+pub const SECTOR_SIZE: usize = 0x80;
 
 #[derive(Deserialize, Serialize)]
 
 pub struct MemoryCard {
     #[serde(skip)]
     file: Option<fs::File>,
+    #[serde(skip)]
+    filepath: String,
     cache: Box<[u8]>,
     dirty: bool,
 
@@ -29,9 +33,10 @@ pub struct MemoryCard {
 }
 
 impl MemoryCard {
-    pub fn new(filepath: &'static str) -> MemoryCard {
-        let mut card = MemoryCard {
-            file: Some(MemoryCard::get_card_file(filepath)),
+    pub fn new() -> MemoryCard {
+        MemoryCard {
+            file: None,
+            filepath: String::new(),
             cache: vec![0; MEMORY_CARD_SIZE].into_boxed_slice(),
             dirty: false,
 
@@ -46,10 +51,7 @@ impl MemoryCard {
 
             checksum: 0,
             checksum_match: false,
-        };
-
-        card.load_cache();
-        card
+        }
     }
 
     pub fn reset(&mut self) {
@@ -73,12 +75,23 @@ impl MemoryCard {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(filepath)
             .expect("unable to create/open memory card file")
     }
 
-    pub fn load(&mut self, filepath: &str) {
+    pub fn open(&mut self, filepath: &str) {
         self.file = Some(MemoryCard::get_card_file(filepath));
+        self.filepath = filepath.to_string();
+
+        self.cache.fill(0);
+        self.load_cache();
+    }
+
+    // Keeps using another card's file, the contents come from the state
+    pub fn take_file(&mut self, other: &mut MemoryCard) {
+        mem::swap(&mut self.file, &mut other.file);
+        mem::swap(&mut self.filepath, &mut other.filepath);
     }
 
     fn load_cache(&mut self) {
@@ -93,8 +106,13 @@ impl MemoryCard {
     }
 
     fn flush_cache(&mut self) {
-        let file = self.file.as_mut().expect("no memcard file");
-        file.seek_write(&self.cache, 0).unwrap();
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => return,
+        };
+
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(&self.cache).unwrap();
         file.flush().unwrap();
         self.dirty = false;
     }
@@ -108,6 +126,17 @@ impl MemoryCard {
         self.dirty = true;
     }
 
+    pub fn read_sector(&self, sector: usize, data: &mut [u8; SECTOR_SIZE]) {
+        let address = (sector & 0x3ff) * SECTOR_SIZE;
+        data.copy_from_slice(&self.cache[address..address + SECTOR_SIZE]);
+    }
+
+    pub fn write_sector(&mut self, sector: usize, data: &[u8; SECTOR_SIZE]) {
+        let address = (sector & 0x3ff) * SECTOR_SIZE;
+        self.cache[address..address + SECTOR_SIZE].copy_from_slice(data);
+        self.dirty = true;
+    }
+
     pub fn sync(&mut self) {
         if self.dirty {
             self.flush_cache();
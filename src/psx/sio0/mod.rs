@@ -1,5 +1,5 @@
 pub mod controller;
-mod memory_card;
+pub mod memory_card;
 
 use serde::{Deserialize, Serialize};
 
@@ -139,7 +139,7 @@ impl Sio0 {
     pub fn new() -> Sio0 {
         Sio0 {
             controller: Controller::new(),
-            mem_card1: MemoryCard::new("./cards/card1.mcd"),
+            mem_card1: MemoryCard::new(),
 
             active_device: Device::None,
 
@@ -176,8 +176,12 @@ impl Sio0 {
         self.mem_card1.reset_device_state();
     }
 
-    pub fn load_memcards(&mut self) {
-        self.mem_card1.load("./cards/card1.mcd");
+    pub fn open_memcards(&mut self, filepath: &str) {
+        self.mem_card1.open(filepath);
+    }
+
+    pub fn take_memcards(&mut self, other: &mut Sio0) {
+        self.mem_card1.take_file(&mut other.mem_card1);
     }
 
     pub fn tick(&mut self, intc: &mut Intc, clocks: usize) {
@@ -252,6 +256,10 @@ impl Sio0 {
         &mut self.controller
     }
 
+    pub fn memory_card(&mut self) -> &mut MemoryCard {
+        &mut self.mem_card1
+    }
+
     pub fn sync(&mut self) {
         self.mem_card1.sync();
     }
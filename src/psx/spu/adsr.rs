@@ -4,8 +4,9 @@ use serde::{Deserialize, Serialize};
 
 use crate::util::clip;
 
-#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Serialize)]
 pub enum AdsrState {
+    #[default]
     Disabled,
     Attack,
     Decay,
@@ -13,12 +14,6 @@ pub enum AdsrState {
     Release,
 }
 
-impl Default for AdsrState {
-    fn default() -> AdsrState {
-        AdsrState::Disabled
-    }
-}
-
 #[derive(PartialEq)]
 pub enum AdsrMode {
     Linear,
@@ -160,12 +155,12 @@ impl AdsrConfig {
         };
 
         AdsrConfig {
-            mode: mode,
-            direction: direction,
-            shift: shift,
-            step: step,
-            target: target,
-            next: next,
+            mode,
+            direction,
+            shift,
+            step,
+            target,
+            next,
         }
     }
 }
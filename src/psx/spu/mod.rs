@@ -32,20 +32,15 @@ const NOISE_WAVE_TABLE: [isize; 64] = [
 
 const NOISE_FREQ_TABLE: [isize; 5] = [0, 84, 140, 180, 210];
 
-#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Serialize)]
 enum SpuTransferMode {
+    #[default]
     Stop,
     ManualWrite,
     DmaWrite,
     DmaRead,
 }
 
-impl Default for SpuTransferMode {
-    fn default() -> SpuTransferMode {
-        SpuTransferMode::Stop
-    }
-}
-
 impl From<u16> for SpuTransferMode {
     fn from(value: u16) -> SpuTransferMode {
         use self::SpuTransferMode::*;
@@ -145,7 +140,7 @@ impl SpuRam {
     pub fn memory_read16(&mut self, address: u32) -> u16 {
         let index = (address & 0x7fffe) as usize;
 
-        if address >= 0x800 && address < 0x1000 {
+        if (0x800..0x1000).contains(&address) {
             println!(
                 "[SPU] [WARN] Read from voice1/3 buffer at 0x{:08x}",
                 address
@@ -412,7 +407,7 @@ impl Spu {
         }
 
         self.sound_ram
-            .memory_write16(0x000 + self.capture_index, f32_to_i16(cd_left) as u16);
+            .memory_write16(self.capture_index, f32_to_i16(cd_left) as u16);
         self.sound_ram
             .memory_write16(0x400 + self.capture_index, f32_to_i16(cd_right) as u16);
 
@@ -583,7 +578,7 @@ impl Spu {
             }
             0x1f801da8 => self.push_fifo(value),
             0x1f801daa => {
-                if self.control.write(value as u16) {
+                if self.control.write(value) {
                     self.irq_status = false;
                 }
 
@@ -620,14 +615,6 @@ impl Spu {
         self.cd_right_buffer.push_back(right);
     }
 
-    pub fn cd_push_left(&mut self, sample: i16) {
-        self.cd_left_buffer.push_back(sample);
-    }
-
-    pub fn cd_push_right(&mut self, sample: i16) {
-        self.cd_right_buffer.push_back(sample);
-    }
-
     pub fn dma_read(&mut self) -> u32 {
         let address = self.data_transfer.current;
 
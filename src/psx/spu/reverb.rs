@@ -53,6 +53,8 @@ impl Reverb {
             return;
         }
 
+        // Left and right each have their own set of every register
+        #[allow(clippy::needless_range_loop)]
         for i in 0..2 {
             let mut msame = input[i] * i16_to_f32(self.vin[i]);
             msame += self.read(ram, self.dsame[i]) * i16_to_f32(self.vwall);
@@ -122,12 +124,10 @@ impl Reverb {
     }
 
     pub fn read16(&self, address: u32) -> u16 {
-        match address {
-            _ => panic!(
-                "[SPU] [ERROR] Read from invalid reverb register: 0x{:08x}",
-                address
-            ),
-        }
+        panic!(
+            "[SPU] [ERROR] Read from invalid reverb register: 0x{:08x}",
+            address
+        )
     }
 
     pub fn write16(&mut self, address: u32, value: u16) {
@@ -76,7 +76,7 @@ impl Voice {
     }
 
     pub fn disabled(&self) -> bool {
-        return self.adsr.state == AdsrState::Disabled;
+        self.adsr.state == AdsrState::Disabled
     }
 
     pub fn reverb_enabled(&self) -> bool {
@@ -138,13 +138,13 @@ impl Voice {
         let s1 = self.get_sample(index - 3) as i32;
         let s2 = self.get_sample(index - 2) as i32;
         let s3 = self.get_sample(index - 1) as i32;
-        let s4 = self.get_sample(index - 0) as i32;
+        let s4 = self.get_sample(index) as i32;
 
         let mut out = 0;
         out += (GAUSS_TABLE[0x0ff - gauss_index] * s1) >> 15;
         out += (GAUSS_TABLE[0x1ff - gauss_index] * s2) >> 15;
         out += (GAUSS_TABLE[0x100 + gauss_index] * s3) >> 15;
-        out += (GAUSS_TABLE[0x000 + gauss_index] * s4) >> 15;
+        out += (GAUSS_TABLE[gauss_index] * s4) >> 15;
 
         i16_to_f32(out as i16)
     }
@@ -46,15 +46,15 @@ impl Queue<u8> {
     pub fn new(capacity: usize) -> Queue<u8> {
         Queue {
             data: Vec::with_capacity(capacity),
-            capacity: capacity,
+            capacity,
         }
     }
 
     pub fn pop(&mut self) -> u8 {
         if self.has_data() {
-            return self.data.remove(0);
+            self.data.remove(0)
         } else {
-            return 0;
+            0
         }
     }
 }
@@ -63,15 +63,15 @@ impl Queue<u16> {
     pub fn new(capacity: usize) -> Queue<u16> {
         Queue {
             data: Vec::with_capacity(capacity),
-            capacity: capacity,
+            capacity,
         }
     }
 
     pub fn pop(&mut self) -> u16 {
         if self.has_data() {
-            return self.data.remove(0);
+            self.data.remove(0)
         } else {
-            return 0;
+            0
         }
     }
 }
@@ -80,15 +80,15 @@ impl Queue<u32> {
     pub fn new(capacity: usize) -> Queue<u32> {
         Queue {
             data: Vec::with_capacity(capacity),
-            capacity: capacity,
+            capacity,
         }
     }
 
     pub fn pop(&mut self) -> u32 {
         if self.has_data() {
-            return self.data.remove(0);
+            self.data.remove(0)
         } else {
-            return 0;
+            0
         }
     }
 }
@@ -15,17 +15,17 @@ pub fn u8_to_bcd(value: u8) -> u8 {
 
 pub fn i16_to_f32(value: i16) -> f32 {
     if value >= 0 {
-        f32::from(value) / f32::from(i16::max_value())
+        f32::from(value) / f32::from(i16::MAX)
     } else {
-        -f32::from(value) / f32::from(i16::min_value())
+        -f32::from(value) / f32::from(i16::MIN)
     }
 }
 
 pub fn f32_to_i16(value: f32) -> i16 {
     if value >= 0.0 {
-        (value * f32::from(i16::max_value())) as i16
+        (value * f32::from(i16::MAX)) as i16
     } else {
-        (-value * f32::from(i16::min_value())) as i16
+        (-value * f32::from(i16::MIN)) as i16
     }
 }
 
@@ -39,7 +39,7 @@ pub fn sign_extend_u16(mut value: u16, size: usize) -> u16 {
         value &= mask;
     }
 
-    return value;
+    value
 }
 
 pub fn sign_extend_i32(mut value: i32, size: usize) -> i32 {
@@ -52,7 +52,7 @@ pub fn sign_extend_i32(mut value: i32, size: usize) -> i32 {
         value &= mask;
     }
 
-    return value;
+    value
 }
 
 pub fn clip<T: PartialOrd>(value: T, min: T, max: T) -> T {
@@ -64,7 +64,7 @@ pub fn clip<T: PartialOrd>(value: T, min: T, max: T) -> T {
         return max;
     }
 
-    return value;
+    value
 }
 
 pub fn min3<T: Ord>(a: T, b: T, c: T) -> T {
@@ -75,6 +75,18 @@ pub fn max3<T: Ord>(a: T, b: T, c: T) -> T {
     cmp::max(a, cmp::max(b, c))
 }
 
+// FNV-1a, stable across platforms and toolchains
+pub fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    for byte in data.iter() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+
+    hash
+}
+
 pub fn read_file_to_box(filepath: &str) -> Box<[u8]> {
     let path = Path::new(filepath);
 
@@ -0,0 +1,322 @@
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use rpsx::{Controller, System};
+use rpsx::capture;
+use rpsx::util::fnv1a;
+
+#[derive(Deserialize)]
+struct Manifest {
+    tests: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    name: String,
+    bios: Option<PathBuf>,
+    disc: Option<PathBuf>,
+    exe: Option<PathBuf>,
+    frames: usize,
+    input: Option<PathBuf>,
+    hash: Option<String>,
+    image: Option<PathBuf>,
+    tty: Option<String>,
+}
+
+struct InputEvent {
+    frame: usize,
+    pressed: bool,
+    buttons: Vec<String>,
+}
+
+struct Frame {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+fn parse_input_script(path: &Path) -> Result<Vec<InputEvent>, String> {
+    let script = fs::read_to_string(path)
+        .map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+
+    let mut events = Vec::new();
+
+    for (number, line) in script.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let error = || format!("{}:{}: expected \"<frame> press|release <button>...\"", path.display(), number + 1);
+
+        let mut words = line.split_whitespace();
+
+        let frame = words.next().and_then(|w| w.parse().ok()).ok_or_else(error)?;
+
+        let pressed = match words.next() {
+            Some("press") => true,
+            Some("release") => false,
+            _ => return Err(error()),
+        };
+
+        let buttons: Vec<String> = words.map(|w| w.to_string()).collect();
+
+        if buttons.is_empty() {
+            return Err(error());
+        }
+
+        events.push(InputEvent {
+            frame,
+            pressed,
+            buttons,
+        });
+    }
+
+    Ok(events)
+}
+
+fn button<'a>(controller: &'a mut Controller, name: &str) -> Option<&'a mut bool> {
+    let button = match name {
+        "select" => &mut controller.button_select,
+        "l3" => &mut controller.button_l3,
+        "r3" => &mut controller.button_r3,
+        "start" => &mut controller.button_start,
+        "up" => &mut controller.button_dpad_up,
+        "right" => &mut controller.button_dpad_right,
+        "down" => &mut controller.button_dpad_down,
+        "left" => &mut controller.button_dpad_left,
+        "l2" => &mut controller.button_l2,
+        "r2" => &mut controller.button_r2,
+        "l1" => &mut controller.button_l1,
+        "r1" => &mut controller.button_r1,
+        "triangle" => &mut controller.button_triangle,
+        "circle" => &mut controller.button_circle,
+        "cross" => &mut controller.button_cross,
+        "square" => &mut controller.button_square,
+        _ => return None,
+    };
+
+    Some(button)
+}
+
+fn hash_frame(frame: &Frame) -> String {
+    let data = [&frame.width.to_le_bytes()[..], &frame.height.to_le_bytes(), &frame.data].concat();
+
+    format!("{:016x}", fnv1a(&data))
+}
+
+fn load_reference(path: &Path) -> Result<Frame, String> {
+    let error = |e: &dyn std::fmt::Display| format!("unable to read {}: {}", path.display(), e);
+
+    let file = File::open(path).map_err(|e| error(&e))?;
+    let mut reader = png::Decoder::new(file).read_info().map_err(|e| error(&e))?;
+
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(|e| error(&e))?;
+
+    if info.color_type != png::ColorType::Rgb || info.bit_depth != png::BitDepth::Eight {
+        return Err(error(&"reference images must be 8-bit RGB"));
+    }
+
+    data.truncate(info.buffer_size());
+
+    Ok(Frame {
+        width: info.width,
+        height: info.height,
+        data,
+    })
+}
+
+fn diff_frames(actual: &Frame, expected: &Frame) -> Option<(usize, Vec<u8>)> {
+    let mut mismatches = 0;
+    let mut diff = Vec::with_capacity(actual.data.len());
+
+    for (a, e) in actual.data.chunks(3).zip(expected.data.chunks(3)) {
+        if a == e {
+            diff.extend(a.iter().map(|c| c / 4));
+        } else {
+            diff.extend_from_slice(&[0xff, 0x00, 0x00]);
+            mismatches += 1;
+        }
+    }
+
+    match mismatches {
+        0 => None,
+        _ => Some((mismatches, diff)),
+    }
+}
+
+fn run_entry(entry: &Entry, base: &Path) -> Result<(System, Frame), String> {
+    // Without a BIOS the HLE kernel is used
+    let bios = match &entry.bios {
+        Some(bios) => fs::read(base.join(bios))
+            .map_err(|e| format!("unable to read BIOS: {}", e))?,
+        None => Vec::new(),
+    };
+
+    let disc = match &entry.disc {
+        Some(disc) => fs::read(base.join(disc))
+            .map_err(|e| format!("unable to read disc: {}", e))?,
+        None => Vec::new(),
+    };
+
+    let events = match &entry.input {
+        Some(input) => parse_input_script(&base.join(input))?,
+        None => Vec::new(),
+    };
+
+    let mut system = System::from_bytes(bios, disc)
+        .map_err(|e| format!("unable to start emulator: {}", e))?;
+
+    system.set_tty_capture(true);
+    system.reset();
+
+    if let Some(exe) = &entry.exe {
+        let exe = base.join(exe);
+
+        system.boot_psexe(&exe.to_string_lossy(), &[])
+            .map_err(|e| format!("unable to load {}: {}", exe.display(), e))?;
+    }
+
+    for frame in 1..=entry.frames {
+        for event in events.iter().filter(|event| event.frame == frame) {
+            for name in event.buttons.iter() {
+                let button = button(system.get_controller(), name)
+                    .ok_or_else(|| format!("unknown button in input script: {}", name))?;
+
+                *button = event.pressed;
+            }
+        }
+
+        system.run_frame();
+        system.get_audio_samples();
+    }
+
+    let (width, height) = system.get_display_size();
+    let mut data = vec![0; 1024 * 512 * 3];
+
+    system.get_framebuffer(&mut data, false);
+    data.truncate((width * height * 3) as usize);
+
+    Ok((system, Frame {
+        width,
+        height,
+        data,
+    }))
+}
+
+fn check_entry(entry: &Entry, base: &Path, output: &Path) -> Vec<String> {
+    let (mut system, frame) = match run_entry(entry, base) {
+        Ok(result) => result,
+        Err(e) => return vec![e],
+    };
+
+    let mut failures = Vec::new();
+    let mut save_actual = false;
+
+    let hash = hash_frame(&frame);
+
+    if let Some(expected) = &entry.hash {
+        if !expected.eq_ignore_ascii_case(&hash) {
+            failures.push(format!("framebuffer hash is {}, expected {}", hash, expected));
+            save_actual = true;
+        }
+    }
+
+    if let Some(image) = &entry.image {
+        match load_reference(&base.join(image)) {
+            Ok(reference) if reference.width != frame.width || reference.height != frame.height => {
+                failures.push(format!("framebuffer is {}x{}, reference image is {}x{}",
+                                      frame.width, frame.height,
+                                      reference.width, reference.height));
+                save_actual = true;
+            },
+            Ok(reference) => {
+                if let Some((mismatches, diff)) = diff_frames(&frame, &reference) {
+                    let path = output.join("diff.png");
+
+                    if let Err(e) = capture::save_png(&path, frame.width, frame.height, &diff) {
+                        failures.push(format!("unable to write {}: {}", path.display(), e));
+                    }
+
+                    failures.push(format!("{} pixels differ from reference image, see {}",
+                                          mismatches, path.display()));
+                    save_actual = true;
+                }
+            },
+            Err(e) => failures.push(e),
+        }
+    }
+
+    if let Some(expected) = &entry.tty {
+        let tty = system.take_tty_output();
+
+        if tty.trim_end() != expected.trim_end() {
+            let path = output.join("tty.txt");
+
+            if let Err(e) = fs::write(&path, &tty) {
+                failures.push(format!("unable to write {}: {}", path.display(), e));
+            }
+
+            failures.push(format!("TTY output differs from expected, see {}", path.display()));
+        }
+    }
+
+    if save_actual {
+        let path = output.join("actual.png");
+
+        match capture::save_png(&path, frame.width, frame.height, &frame.data) {
+            Ok(()) => failures.push(format!("actual framebuffer written to {}", path.display())),
+            Err(e) => failures.push(format!("unable to write {}: {}", path.display(), e)),
+        }
+    }
+
+    failures
+}
+
+#[test]
+fn golden() {
+    let manifest_path = match env::var_os("RPSX_GOLDEN_MANIFEST") {
+        Some(path) => PathBuf::from(path),
+        None => Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/manifest.yaml"),
+    };
+
+    let manifest = fs::read_to_string(&manifest_path)
+        .unwrap_or_else(|e| panic!("unable to read {}: {}", manifest_path.display(), e));
+    let manifest: Manifest = serde_yaml::from_str(&manifest)
+        .unwrap_or_else(|e| panic!("unable to parse {}: {}", manifest_path.display(), e));
+
+    let base = manifest_path.parent().unwrap();
+    let output_base = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+
+    let mut failed = Vec::new();
+
+    for entry in manifest.tests.iter() {
+        if let Some(bios) = entry.bios.as_ref().filter(|bios| !base.join(bios).exists()) {
+            println!("[GOLDEN] {}: skipped, BIOS {} not found", entry.name, bios.display());
+            continue;
+        }
+
+        let output = output_base.join(&entry.name);
+        let _ = fs::remove_dir_all(&output);
+        fs::create_dir_all(&output).unwrap();
+
+        let failures = check_entry(entry, base, &output);
+
+        if failures.is_empty() {
+            println!("[GOLDEN] {}: ok", entry.name);
+        } else {
+            for failure in failures.iter() {
+                println!("[GOLDEN] {}: {}", entry.name, failure);
+            }
+
+            failed.push(entry.name.as_str());
+        }
+    }
+
+    assert!(failed.is_empty(), "golden tests failed: {}", failed.join(", "));
+}
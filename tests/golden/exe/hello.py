@@ -0,0 +1,40 @@
+#!/usr/bin/env python3
+# Builds hello.exe, which calls printf("hello %d\n", 42) through the A0 table
+# and then spins. Run from this directory to regenerate it.
+import struct
+
+BASE = 0x80010000
+FORMAT = BASE + 0x40
+
+A0, A1, T1, T2, RA = 4, 5, 9, 10, 31
+
+code = []
+
+def lui(rt, imm): code.append((0x0f << 26) | (rt << 16) | (imm & 0xffff))
+def ori(rt, rs, imm): code.append((0x0d << 26) | (rs << 21) | (rt << 16) | (imm & 0xffff))
+def addiu(rt, rs, imm): code.append((0x09 << 26) | (rs << 21) | (rt << 16) | (imm & 0xffff))
+def jalr(rs): code.append((rs << 21) | (RA << 11) | 0x09)
+def beq(rs, rt, offset): code.append((0x04 << 26) | (rs << 21) | (rt << 16) | (offset & 0xffff))
+def nop(): code.append(0)
+
+lui(A0, FORMAT >> 16)
+ori(A0, A0, FORMAT)
+addiu(A1, 0, 42)
+addiu(T2, 0, 0xa0)
+jalr(T2)
+addiu(T1, 0, 0x3f)
+beq(0, 0, -1)
+nop()
+
+text = b''.join(struct.pack('<I', word) for word in code)
+text += b'\0' * (FORMAT - BASE - len(text))
+text += b'hello %d\n\0'
+text += b'\0' * (-len(text) % 4)
+
+header = bytearray(0x800)
+header[:8] = b'PS-X EXE'
+struct.pack_into('<IIII', header, 0x10, BASE, 0, BASE, len(text))
+struct.pack_into('<I', header, 0x30, 0x801fff00)
+
+with open('hello.exe', 'wb') as f:
+    f.write(bytes(header) + text)